@@ -0,0 +1,39 @@
+## Confirmation email, sent when someone subscribes.
+
+confirmation-subject = Willkommen!
+confirmation-text =
+    Willkommen bei unserem Newsletter!
+    Besuchen Sie { $link }, um Ihr Abonnement zu bestätigen.
+confirmation-html = Willkommen bei unserem Newsletter!<br />Klicken Sie <a href="{ $link }">hier</a>, um Ihr Abonnement zu bestätigen.
+
+## Data request email, sent when a subscriber asks for the data we hold about them.
+
+data-request-subject = Ihre Datenanfrage
+data-request-text =
+    Wir haben eine Anfrage zu den Daten erhalten, die wir über Sie speichern.
+    Besuchen Sie { $link }, um sie herunterzuladen oder zu löschen. Der Link ist 24 Stunden gültig.
+data-request-html = Wir haben eine Anfrage zu den Daten erhalten, die wir über Sie speichern.<br />Klicken Sie <a href="{ $link }">hier</a>, um sie herunterzuladen oder zu löschen. Der Link ist 24 Stunden gültig.
+
+## Appended to every newsletter issue.
+
+newsletter-footer = Sie erhalten diese E-Mail, weil Sie unseren Newsletter abonniert haben.
+
+## Weekly digest, rolling up the issues published since the previous one.
+
+digest-subject = Ihr Wochenrückblick
+digest-intro = Das haben wir diese Woche veröffentlicht.
+
+## Closes every email, linking to the preference page.
+
+preferences-text = Wählen Sie, was Sie von uns erhalten, oder pausieren Sie es: { $link }
+preferences-html = <a href="{ $link }">Wählen Sie, was Sie von uns erhalten, oder pausieren Sie es</a>.
+
+## Validation errors returned when subscribing.
+
+invalid-email = { $value } ist keine gültige E-Mail-Adresse.
+invalid-name = { $value } ist kein gültiger Name.
+unknown-attribute = { $name } ist kein Attribut, das wir erfassen.
+invalid-attribute = { $name } muss vom Typ { $type } sein.
+role-account = { $email } ist eine Funktionsadresse, bitte abonnieren Sie mit einer persönlichen Adresse.
+blocked-domain = Wir akzeptieren keine Anmeldungen mit { $domain }-Adressen.
+disposable-domain = { $domain } ist ein Anbieter von Wegwerfadressen, bitte abonnieren Sie mit einer dauerhaften Adresse.
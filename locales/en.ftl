@@ -0,0 +1,39 @@
+## Confirmation email, sent when someone subscribes.
+
+confirmation-subject = Welcome!
+confirmation-text =
+    Welcome to our newsletter!
+    Visit { $link } to confirm your subscription.
+confirmation-html = Welcome to our newsletter!<br />Click <a href="{ $link }">here</a> to confirm your subscription.
+
+## Data request email, sent when a subscriber asks for the data we hold about them.
+
+data-request-subject = Your data request
+data-request-text =
+    We received a request for the data we hold about you.
+    Visit { $link } to download or erase it. The link expires in 24 hours.
+data-request-html = We received a request for the data we hold about you.<br />Click <a href="{ $link }">here</a> to download or erase it. The link expires in 24 hours.
+
+## Appended to every newsletter issue.
+
+newsletter-footer = You are receiving this email because you subscribed to our newsletter.
+
+## Weekly digest, rolling up the issues published since the previous one.
+
+digest-subject = Your weekly digest
+digest-intro = Here is what we published this week.
+
+## Closes every email, linking to the preference page.
+
+preferences-text = Choose what you receive from us, or pause it: { $link }
+preferences-html = <a href="{ $link }">Choose what you receive from us, or pause it</a>.
+
+## Validation errors returned when subscribing.
+
+invalid-email = { $value } is not a valid subscriber email.
+invalid-name = { $value } is not a valid subscriber name.
+unknown-attribute = { $name } is not an attribute we collect.
+invalid-attribute = { $name } must be of type { $type }.
+role-account = { $email } is a role account, please subscribe with a personal address.
+blocked-domain = We don't accept subscriptions from { $domain } addresses.
+disposable-domain = { $domain } is a disposable email provider, please subscribe with a permanent address.
@@ -0,0 +1,39 @@
+## Confirmation email, sent when someone subscribes.
+
+confirmation-subject = Bienvenue !
+confirmation-text =
+    Bienvenue dans notre newsletter !
+    Rendez-vous sur { $link } pour confirmer votre inscription.
+confirmation-html = Bienvenue dans notre newsletter !<br />Cliquez <a href="{ $link }">ici</a> pour confirmer votre inscription.
+
+## Data request email, sent when a subscriber asks for the data we hold about them.
+
+data-request-subject = Votre demande d'accès à vos données
+data-request-text =
+    Nous avons reçu une demande d'accès aux données que nous détenons sur vous.
+    Rendez-vous sur { $link } pour les télécharger ou les effacer. Le lien expire dans 24 heures.
+data-request-html = Nous avons reçu une demande d'accès aux données que nous détenons sur vous.<br />Cliquez <a href="{ $link }">ici</a> pour les télécharger ou les effacer. Le lien expire dans 24 heures.
+
+## Appended to every newsletter issue.
+
+newsletter-footer = Vous recevez cet e-mail car vous êtes inscrit à notre newsletter.
+
+## Weekly digest, rolling up the issues published since the previous one.
+
+digest-subject = Votre résumé de la semaine
+digest-intro = Voici ce que nous avons publié cette semaine.
+
+## Closes every email, linking to the preference page.
+
+preferences-text = Choisissez ce que vous recevez de notre part, ou mettez-le en pause : { $link }
+preferences-html = <a href="{ $link }">Choisissez ce que vous recevez de notre part, ou mettez-le en pause</a>.
+
+## Validation errors returned when subscribing.
+
+invalid-email = { $value } n'est pas une adresse e-mail valide.
+invalid-name = { $value } n'est pas un nom valide.
+unknown-attribute = { $name } n'est pas un attribut que nous collectons.
+invalid-attribute = { $name } doit être de type { $type }.
+role-account = { $email } est une adresse de service, merci de vous inscrire avec une adresse personnelle.
+blocked-domain = Nous n'acceptons pas les inscriptions avec des adresses { $domain }.
+disposable-domain = { $domain } est un fournisseur d'adresses jetables, merci de vous inscrire avec une adresse permanente.
@@ -0,0 +1,13 @@
+-- Erasing a subscriber must take their tokens with them.
+ALTER TABLE subscription_tokens
+DROP CONSTRAINT subscription_tokens_subscriber_id_fkey,
+ADD CONSTRAINT subscription_tokens_subscriber_id_fkey
+  FOREIGN KEY (subscriber_id) REFERENCES subscriptions (id) ON DELETE CASCADE;
+
+-- Create Data Request Tokens Table
+CREATE TABLE data_request_tokens(
+  data_request_token TEXT NOT NULL,
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  requested_at timestamptz NOT NULL,
+  PRIMARY KEY (data_request_token)
+);
@@ -0,0 +1,40 @@
+-- Keyset pagination over `(subscribed_at, id)`, optionally filtered by status.
+CREATE INDEX subscriptions_subscribed_at_id_idx ON subscriptions (subscribed_at, id);
+CREATE INDEX subscriptions_status_subscribed_at_id_idx ON subscriptions (status, subscribed_at, id);
+
+-- Email substring search.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX subscriptions_email_trgm_idx ON subscriptions USING gin (email gin_trgm_ops);
+
+-- Create Subscription Status Changes Table
+CREATE TABLE subscription_status_changes(
+  id BIGINT GENERATED ALWAYS AS IDENTITY,
+  PRIMARY KEY (id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  status TEXT NOT NULL,
+  changed_at timestamptz NOT NULL
+);
+CREATE INDEX subscription_status_changes_subscriber_id_idx
+  ON subscription_status_changes (subscriber_id, changed_at);
+
+-- Record every status a subscriber goes through, whichever code path wrote it.
+CREATE FUNCTION record_subscription_status_change() RETURNS trigger AS $$
+BEGIN
+  INSERT INTO subscription_status_changes (subscriber_id, status, changed_at)
+  VALUES (NEW.id, NEW.status, now());
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER subscriptions_status_inserted
+AFTER INSERT ON subscriptions
+FOR EACH ROW EXECUTE FUNCTION record_subscription_status_change();
+
+CREATE TRIGGER subscriptions_status_updated
+AFTER UPDATE OF status ON subscriptions
+FOR EACH ROW WHEN (OLD.status IS DISTINCT FROM NEW.status)
+EXECUTE FUNCTION record_subscription_status_change();
+
+-- Backfill the current status of historical entries.
+INSERT INTO subscription_status_changes (subscriber_id, status, changed_at)
+SELECT id, status, subscribed_at FROM subscriptions;
@@ -0,0 +1,32 @@
+-- Create Audit Events Table
+-- `target_id` is deliberately not a foreign key: events must outlive erased subscribers.
+CREATE TABLE audit_events(
+  id BIGINT GENERATED ALWAYS AS IDENTITY,
+  PRIMARY KEY (id),
+  occurred_at timestamptz NOT NULL DEFAULT now(),
+  actor TEXT NOT NULL,
+  actor_id TEXT NULL,
+  action TEXT NOT NULL,
+  target TEXT NOT NULL,
+  target_id TEXT NULL,
+  status_before TEXT NULL,
+  status_after TEXT NULL,
+  details JSONB NOT NULL DEFAULT '{}'
+);
+CREATE INDEX audit_events_target_idx ON audit_events (target, target_id, id);
+CREATE INDEX audit_events_action_idx ON audit_events (action, id);
+
+-- The log is append-only.
+CREATE FUNCTION reject_audit_event_change() RETURNS trigger AS $$
+BEGIN
+  RAISE EXCEPTION 'audit_events is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_events_append_only
+BEFORE UPDATE OR DELETE ON audit_events
+FOR EACH ROW EXECUTE FUNCTION reject_audit_event_change();
+
+CREATE TRIGGER audit_events_no_truncate
+BEFORE TRUNCATE ON audit_events
+FOR EACH STATEMENT EXECUTE FUNCTION reject_audit_event_change();
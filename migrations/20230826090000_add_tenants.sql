@@ -0,0 +1,23 @@
+-- Scope subscribers, issues and audit events to the newsletter (tenant) they belong to.
+-- Everything that existed before belongs to the default tenant.
+ALTER TABLE subscriptions ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE subscriptions ALTER COLUMN tenant DROP DEFAULT;
+
+-- The same person can subscribe to several newsletters.
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+ALTER TABLE subscriptions ADD CONSTRAINT subscriptions_tenant_email_key UNIQUE (tenant, email);
+
+-- Admin searches are always scoped to a tenant.
+DROP INDEX subscriptions_subscribed_at_id_idx;
+DROP INDEX subscriptions_status_subscribed_at_id_idx;
+CREATE INDEX subscriptions_tenant_subscribed_at_id_idx
+  ON subscriptions (tenant, subscribed_at, id);
+CREATE INDEX subscriptions_tenant_status_subscribed_at_id_idx
+  ON subscriptions (tenant, status, subscribed_at, id);
+
+ALTER TABLE newsletter_issues ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE newsletter_issues ALTER COLUMN tenant DROP DEFAULT;
+
+ALTER TABLE audit_events ADD COLUMN tenant TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE audit_events ALTER COLUMN tenant DROP DEFAULT;
+CREATE INDEX audit_events_tenant_idx ON audit_events (tenant, id);
@@ -0,0 +1,37 @@
+-- Subscribers are identified by their canonical email: lowercase, with a punycode domain.
+-- `email` keeps the address as they typed it, for display and delivery.
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT;
+
+-- Internationalised domains can't be converted to punycode in SQL: subscribers who signed up
+-- with a Unicode domain keep it until they are next imported or re-subscribe.
+UPDATE subscriptions SET canonical_email = lower(email);
+ALTER TABLE subscriptions ALTER COLUMN canonical_email SET NOT NULL;
+
+-- Keep a single subscriber per canonical email: confirmed over pending, then the oldest.
+-- The others are erased, and recorded as such in the audit log.
+CREATE TEMPORARY TABLE duplicate_subscriptions AS
+SELECT id, tenant, status, kept_id
+FROM (
+  SELECT id, tenant, status,
+    first_value(id) OVER same_subscriber AS kept_id,
+    row_number() OVER same_subscriber AS rank
+  FROM subscriptions
+  WINDOW same_subscriber AS (
+    PARTITION BY tenant, canonical_email
+    ORDER BY status = 'confirmed' DESC, subscribed_at, id
+  )
+) ranked
+WHERE rank > 1;
+
+INSERT INTO audit_events
+  (tenant, actor, actor_id, action, target, target_id, status_before, status_after, details)
+SELECT tenant, 'system', 'canonical email migration', 'erase', 'subscriber', id::text, status,
+  NULL, jsonb_build_object('duplicate_of', kept_id)
+FROM duplicate_subscriptions;
+
+DELETE FROM subscriptions WHERE id IN (SELECT id FROM duplicate_subscriptions);
+DROP TABLE duplicate_subscriptions;
+
+ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_tenant_email_key;
+CREATE UNIQUE INDEX subscriptions_tenant_canonical_email_idx
+  ON subscriptions (tenant, canonical_email);
@@ -0,0 +1,8 @@
+-- Domains a tenant explicitly allows or blocks signups from, subdomains included.
+CREATE TABLE email_domain_rules(
+  tenant TEXT NOT NULL,
+  domain TEXT NOT NULL,
+  rule TEXT NOT NULL CHECK (rule IN ('allow', 'block')),
+  created_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY (tenant, domain)
+);
@@ -0,0 +1,2 @@
+-- The language subscribers get their emails in.
+ALTER TABLE subscriptions ADD COLUMN locale TEXT NOT NULL DEFAULT 'en';
@@ -0,0 +1,24 @@
+-- Lists a tenant publishes issues to, which subscribers can opt out of.
+CREATE TABLE mailing_lists(
+  tenant TEXT NOT NULL,
+  slug TEXT NOT NULL,
+  name TEXT NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now(),
+  PRIMARY KEY (tenant, slug)
+);
+
+-- `NULL` for issues sent to every subscriber.
+ALTER TABLE newsletter_issues ADD COLUMN list TEXT;
+
+-- What subscribers choose on their preference page, reached through a token emailed to them.
+ALTER TABLE subscriptions
+  ADD COLUMN preferences_token TEXT,
+  ADD COLUMN unsubscribed_lists TEXT[] NOT NULL DEFAULT '{}',
+  ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue'
+    CHECK (frequency IN ('every_issue', 'weekly_digest')),
+  ADD COLUMN paused_until timestamptz;
+
+UPDATE subscriptions
+SET preferences_token = md5(gen_random_uuid()::text || id::text);
+ALTER TABLE subscriptions ALTER COLUMN preferences_token SET NOT NULL;
+CREATE UNIQUE INDEX subscriptions_preferences_token_idx ON subscriptions (preferences_token);
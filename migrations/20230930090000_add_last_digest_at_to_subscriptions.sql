@@ -0,0 +1,5 @@
+-- When weekly digest subscribers were last sent one, `NULL` if never.
+ALTER TABLE subscriptions ADD COLUMN last_digest_at timestamptz;
+
+-- Digests collect the issues published since then.
+CREATE INDEX newsletter_issues_tenant_published_at_idx ON newsletter_issues (tenant, published_at);
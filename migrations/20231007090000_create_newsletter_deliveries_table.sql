@@ -0,0 +1,22 @@
+-- Issues testing subject lines on a sample of their recipients before sending the best one to
+-- everyone else. `winning_subject` is `NULL` until the test window closes.
+ALTER TABLE newsletter_issues
+  ADD COLUMN subject_variants TEXT[],
+  ADD COLUMN winner_metric TEXT CHECK (winner_metric IN ('open_rate', 'click_rate')),
+  ADD COLUMN winner_due_at timestamptz,
+  ADD COLUMN winning_subject TEXT;
+
+-- Who an issue under test goes to. Those in the test cohort get one of the variants, and a token
+-- tracking their opens and clicks. Everyone else gets the winning subject, when there is one.
+CREATE TABLE newsletter_deliveries(
+  newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (newsletter_issue_id),
+  subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+  PRIMARY KEY (newsletter_issue_id, subscriber_id),
+  variant SMALLINT,
+  tracking_token TEXT UNIQUE,
+  sent_at timestamptz,
+  opened_at timestamptz,
+  clicked_at timestamptz
+);
+CREATE INDEX newsletter_deliveries_unsent_idx ON newsletter_deliveries (newsletter_issue_id)
+  WHERE sent_at IS NULL;
@@ -0,0 +1,10 @@
+-- What subscribers tell us about themselves, e.g. `{"country": "US", "plan": "pro"}`.
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX subscriptions_attributes_idx ON subscriptions USING GIN (attributes);
+
+-- The attributes a list collects, by name, with the type of their values, e.g.
+-- `{"country": "string"}`.
+ALTER TABLE mailing_lists ADD COLUMN attribute_schema JSONB NOT NULL DEFAULT '{}';
+
+-- The segment filter an issue was published with, `NULL` for every subscriber.
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT;
@@ -0,0 +1,30 @@
+-- Where to POST the events of a tenant, e.g. to keep a CRM in sync. Every request is signed with
+-- the endpoint's secret.
+CREATE TABLE webhook_endpoints(
+  id uuid NOT NULL PRIMARY KEY,
+  tenant TEXT NOT NULL,
+  url TEXT NOT NULL,
+  secret TEXT NOT NULL,
+  events TEXT[] NOT NULL,
+  created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX webhook_endpoints_tenant_idx ON webhook_endpoints (tenant);
+
+-- One row per event and endpoint subscribed to it, kept once delivered as the delivery log.
+-- `payload` is the body exactly as it is sent, on every attempt.
+CREATE TABLE webhook_deliveries(
+  endpoint_id uuid NOT NULL REFERENCES webhook_endpoints (id) ON DELETE CASCADE,
+  event_id uuid NOT NULL,
+  PRIMARY KEY (endpoint_id, event_id),
+  event_type TEXT NOT NULL,
+  payload JSONB NOT NULL,
+  status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'failed')),
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at timestamptz NOT NULL DEFAULT now(),
+  last_attempt_at timestamptz,
+  last_response_status SMALLINT,
+  last_error TEXT,
+  created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt_at)
+  WHERE status = 'pending';
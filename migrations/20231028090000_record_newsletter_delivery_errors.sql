@@ -0,0 +1,3 @@
+-- Every recipient of an issue now has a delivery, whether or not its subject is tested, recording
+-- when the email API accepted their email, or why it didn't.
+ALTER TABLE newsletter_deliveries ADD COLUMN error TEXT;
@@ -0,0 +1,15 @@
+-- Emails we couldn't send as the email provider was down, sent again once it is back up. Rows are
+-- deleted once sent or given up on.
+CREATE TABLE email_outbox(
+  id uuid NOT NULL PRIMARY KEY,
+  tenant TEXT NOT NULL,
+  recipient TEXT NOT NULL,
+  subject TEXT NOT NULL,
+  html_content TEXT NOT NULL,
+  text_content TEXT NOT NULL,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at timestamptz NOT NULL DEFAULT now(),
+  last_error TEXT,
+  created_at timestamptz NOT NULL DEFAULT now()
+);
+CREATE INDEX email_outbox_next_attempt_at_idx ON email_outbox (next_attempt_at);
@@ -0,0 +1,4 @@
+-- Digests that failed to send are retried with exponential backoff, rather than blocking the
+-- digests of everyone else until they are sent.
+ALTER TABLE subscriptions ADD COLUMN digest_attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN next_digest_attempt_at timestamptz;
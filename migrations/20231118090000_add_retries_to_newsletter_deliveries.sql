@@ -0,0 +1,5 @@
+-- Deliveries that failed to send are retried with exponential backoff, until `error` records why
+-- they were given up on.
+ALTER TABLE newsletter_deliveries
+  ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN next_attempt_at timestamptz;
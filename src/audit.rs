@@ -0,0 +1,215 @@
+//! Append-only log of the changes made to subscribers and newsletter issues, and of who made them.
+//!
+//! Events are written in the same transaction as the change they describe: either both are
+//! committed or neither is.
+
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::subscription_status::SubscriptionStatus;
+
+/// Who made a change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Actor {
+    /// Someone holding the admin token.
+    Admin,
+    /// A subscriber acting on their own data, through a link we emailed them.
+    Subscriber(Uuid),
+    /// A third party calling one of our webhooks, identified by name.
+    Webhook(String),
+    /// A scheduled job or a command line tool, identified by name.
+    System(&'static str),
+}
+
+impl Actor {
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Actor::Admin => "admin",
+            Actor::Subscriber(_) => "subscriber",
+            Actor::Webhook(_) => "webhook",
+            Actor::System(_) => "system",
+        }
+    }
+
+    #[must_use]
+    pub fn id(&self) -> Option<String> {
+        match self {
+            Actor::Admin => None,
+            Actor::Subscriber(id) => Some(id.to_string()),
+            Actor::Webhook(name) => Some(name.clone()),
+            Actor::System(name) => Some((*name).to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Subscribe,
+    Confirm,
+    Import,
+    Erase,
+    Publish,
+    UpdatePreferences,
+    PickSubject,
+    UpdateAttributes,
+}
+
+impl Action {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Subscribe => "subscribe",
+            Action::Confirm => "confirm",
+            Action::Import => "import",
+            Action::Erase => "erase",
+            Action::Publish => "publish",
+            Action::UpdatePreferences => "update_preferences",
+            Action::PickSubject => "pick_subject",
+            Action::UpdateAttributes => "update_attributes",
+        }
+    }
+}
+
+/// What a change was made to.
+///
+/// Targets are referenced by id only, so the log keeps no personal data about erased subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Subscriber(Uuid),
+    NewsletterIssue(Uuid),
+}
+
+impl Target {
+    #[must_use]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Target::Subscriber(_) => "subscriber",
+            Target::NewsletterIssue(_) => "newsletter_issue",
+        }
+    }
+
+    #[must_use]
+    pub fn id(&self) -> Uuid {
+        match self {
+            Target::Subscriber(id) | Target::NewsletterIssue(id) => *id,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub actor: Actor,
+    pub action: Action,
+    pub target: Target,
+    /// `None` if the target didn't exist before the change.
+    pub status_before: Option<SubscriptionStatus>,
+    /// `None` if the target no longer exists after the change.
+    pub status_after: Option<SubscriptionStatus>,
+    pub details: serde_json::Value,
+}
+
+impl AuditEvent {
+    #[must_use]
+    pub fn new(actor: Actor, action: Action, target: Target) -> Self {
+        Self {
+            actor,
+            action,
+            target,
+            status_before: None,
+            status_after: None,
+            details: serde_json::json!({}),
+        }
+    }
+
+    #[must_use]
+    pub fn status_change(
+        mut self,
+        before: Option<SubscriptionStatus>,
+        after: Option<SubscriptionStatus>,
+    ) -> Self {
+        self.status_before = before;
+        self.status_after = after;
+        self
+    }
+
+    #[must_use]
+    pub fn details(mut self, details: serde_json::Value) -> Self {
+        self.details = details;
+        self
+    }
+}
+
+#[tracing::instrument(name = "Record an audit event", skip(transaction))]
+pub async fn record(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    event: AuditEvent,
+) -> Result<(), sqlx::Error> {
+    record_all(transaction, tenant, &[event]).await
+}
+
+/// Record several events about the same tenant with a single `INSERT`.
+#[tracing::instrument(name = "Record audit events", skip_all, fields(count = events.len()))]
+pub async fn record_all(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    events: &[AuditEvent],
+) -> Result<(), sqlx::Error> {
+    let actors: Vec<&str> = events.iter().map(|e| e.actor.kind()).collect();
+    let actor_ids: Vec<Option<String>> = events.iter().map(|e| e.actor.id()).collect();
+    let actions: Vec<&str> = events.iter().map(|e| e.action.as_str()).collect();
+    let targets: Vec<&str> = events.iter().map(|e| e.target.kind()).collect();
+    let target_ids: Vec<String> = events.iter().map(|e| e.target.id().to_string()).collect();
+    let statuses_before: Vec<Option<&str>> = events
+        .iter()
+        .map(|e| e.status_before.as_ref().map(SubscriptionStatus::as_str))
+        .collect();
+    let statuses_after: Vec<Option<&str>> = events
+        .iter()
+        .map(|e| e.status_after.as_ref().map(SubscriptionStatus::as_str))
+        .collect();
+    let details: Vec<serde_json::Value> = events.iter().map(|e| e.details.clone()).collect();
+
+    sqlx::query!(
+        r#"
+    INSERT INTO audit_events
+      (tenant, actor, actor_id, action, target, target_id, status_before, status_after, details)
+    SELECT $1::text, * FROM UNNEST(
+      $2::text[], $3::text[], $4::text[], $5::text[], $6::text[], $7::text[], $8::text[], $9::jsonb[]
+    )
+        "#,
+        tenant,
+        &actors as &[&str],
+        &actor_ids as &[Option<String>],
+        &actions as &[&str],
+        &targets as &[&str],
+        &target_ids,
+        &statuses_before as &[Option<&str>],
+        &statuses_after as &[Option<&str>],
+        &details,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Actor, Target};
+    use uuid::Uuid;
+
+    #[test]
+    fn admins_are_recorded_without_an_id() {
+        assert_eq!(Actor::Admin.kind(), "admin");
+        assert_eq!(Actor::Admin.id(), None);
+    }
+
+    #[test]
+    fn subscribers_are_recorded_by_id() {
+        let id = Uuid::new_v4();
+        assert_eq!(Actor::Subscriber(id).id(), Some(id.to_string()));
+        assert_eq!(Target::Subscriber(id).id(), id);
+    }
+}
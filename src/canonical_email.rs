@@ -0,0 +1,158 @@
+//! Bringing the canonical emails stored before internationalised domains were encoded as
+//! punycode in line with [`SubscriberEmail::canonical`].
+//!
+//! The migration adding `canonical_email` could only lowercase addresses: SQL can't encode a
+//! Unicode domain as punycode. Left as they are, those subscribers would be added again when
+//! they re-subscribe or are imported, as the new row's canonical email is the punycode one.
+//! [`backfill`] runs before the application serves requests, and keeps a single subscriber per
+//! canonical email as the migration did: confirmed over pending, then the oldest.
+
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent, Target},
+    domain::{subscriber_email::SubscriberEmail, subscription_status::SubscriptionStatus},
+};
+
+struct Subscriber {
+    id: Uuid,
+    status: String,
+    subscribed_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Subscriber {
+    /// Whether to keep `self` rather than `other`, when they share a canonical email.
+    fn comes_before(&self, other: &Subscriber) -> bool {
+        let rank = |s: &Subscriber| (s.status != "confirmed", s.subscribed_at, s.id);
+        rank(self) < rank(other)
+    }
+}
+
+/// Recompute the canonical email of every subscriber whose canonical email isn't ASCII,
+/// erasing duplicates. Returns how many subscribers were updated or erased.
+#[tracing::instrument(name = "Backfill canonical emails", skip_all)]
+pub async fn backfill(pool: &PgPool) -> Result<usize, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // Whatever the database's encoding, bytes beyond ASCII are outside the range.
+    let legacy = sqlx::query!(
+        r#"
+    SELECT id, tenant, email, status, subscribed_at FROM subscriptions
+    WHERE canonical_email !~ '^[\x20-\x7e]*$'
+        "#,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look for non-ASCII canonical emails")?;
+    if legacy.is_empty() {
+        return Ok(0);
+    }
+    // Keep signups from adding a row with a canonical email we're about to take.
+    sqlx::query!("LOCK TABLE subscriptions IN SHARE ROW EXCLUSIVE MODE")
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to lock the subscriptions")?;
+
+    let mut fixed = 0;
+    for row in legacy {
+        let Ok(email) = SubscriberEmail::try_from(row.email) else {
+            tracing::warn!(subscriber_id = %row.id, "Skipping an invalid stored email");
+            continue;
+        };
+        if !email.canonical().is_ascii() {
+            tracing::warn!(subscriber_id = %row.id, "Skipping a domain we can't encode");
+            continue;
+        }
+        let subscriber = Subscriber {
+            id: row.id,
+            status: row.status,
+            subscribed_at: row.subscribed_at,
+        };
+        merge(&mut transaction, &row.tenant, &email, subscriber).await?;
+        fixed += 1;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    tracing::info!(fixed, "Backfilled canonical emails");
+    Ok(fixed)
+}
+
+/// Give `subscriber` the canonical email of `email`, erasing whichever of them and the
+/// subscriber who already has it comes second.
+async fn merge(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    email: &SubscriberEmail,
+    subscriber: Subscriber,
+) -> Result<(), anyhow::Error> {
+    let existing = sqlx::query_as!(
+        Subscriber,
+        r#"
+    SELECT id, status, subscribed_at FROM subscriptions
+    WHERE tenant = $1 AND canonical_email = $2
+        "#,
+        tenant,
+        email.canonical(),
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .context("Failed to look for a subscriber with the same canonical email")?;
+
+    let kept = match existing {
+        Some(existing) if existing.comes_before(&subscriber) => {
+            erase(transaction, tenant, &subscriber, existing.id).await?;
+            return Ok(());
+        }
+        Some(existing) => {
+            erase(transaction, tenant, &existing, subscriber.id).await?;
+            subscriber.id
+        }
+        None => subscriber.id,
+    };
+    sqlx::query!(
+        "UPDATE subscriptions SET canonical_email = $2 WHERE id = $1",
+        kept,
+        email.canonical(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to update the canonical email")?;
+    Ok(())
+}
+
+async fn erase(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    duplicate: &Subscriber,
+    kept_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!("DELETE FROM subscriptions WHERE id = $1", duplicate.id)
+        .execute(&mut **transaction)
+        .await
+        .context("Failed to erase a duplicate subscriber")?;
+    audit::record(
+        transaction,
+        tenant,
+        AuditEvent::new(
+            Actor::System("canonical email backfill"),
+            Action::Erase,
+            Target::Subscriber(duplicate.id),
+        )
+        .status_change(
+            SubscriptionStatus::try_from(duplicate.status.as_str()).ok(),
+            None,
+        )
+        .details(serde_json::json!({ "duplicate_of": kept_id })),
+    )
+    .await
+    .context("Failed to record the erasure in the audit log")?;
+    Ok(())
+}
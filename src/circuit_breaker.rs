@@ -0,0 +1,278 @@
+//! Failing fast while the email provider is down, rather than having every request wait for it
+//! to time out.
+//!
+//! The breaker opens after a number of consecutive failures, and sends fail right away while it
+//! is open. Once it has been open for a while, it is half-open: a single trial send goes through,
+//! closing the breaker if it succeeds, and opening it again if it fails.
+
+use std::{
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+/// Consecutive failures that open the breaker unless configured otherwise.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+
+/// How long the breaker stays open before a trial, unless configured otherwise.
+pub const DEFAULT_OPEN_DURATION: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Sends go through.
+    Closed,
+    /// Sends fail fast.
+    Open,
+    /// A trial send decides whether to close the breaker again.
+    HalfOpen,
+}
+
+impl CircuitState {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Inner {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { trial_in_flight: bool },
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    /// What the breaker protects, in its logs, e.g. the tenant's slug.
+    name: String,
+    failure_threshold: u32,
+    open_duration: Duration,
+    inner: Mutex<Inner>,
+}
+
+/// Permission to send, to be followed by the outcome of the send.
+#[must_use]
+pub struct Attempt<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl Attempt<'_> {
+    /// Report whether the send succeeded, as far as the provider's health goes.
+    pub fn record(mut self, succeeded: bool) {
+        self.recorded = true;
+        self.breaker.record(succeeded, Instant::now());
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        // A trial that was given up on, e.g. as its request was cancelled, proves nothing: let
+        // the next send try again.
+        if !self.recorded {
+            let mut inner = self.breaker.lock();
+            if let Inner::HalfOpen { trial_in_flight } = &mut *inner {
+                *trial_in_flight = false;
+            }
+        }
+    }
+}
+
+impl CircuitBreaker {
+    #[must_use]
+    pub fn new(name: &str, failure_threshold: u32, open_duration: Duration) -> Self {
+        Self {
+            name: name.to_owned(),
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            inner: Mutex::new(Inner::Closed { failures: 0 }),
+        }
+    }
+
+    /// Permission to send now, `None` while the breaker is open or a trial is in flight.
+    pub fn attempt(&self) -> Option<Attempt<'_>> {
+        self.allows(Instant::now()).then_some(Attempt {
+            breaker: self,
+            recorded: false,
+        })
+    }
+
+    #[must_use]
+    pub fn state(&self) -> CircuitState {
+        match *self.lock() {
+            Inner::Closed { .. } => CircuitState::Closed,
+            Inner::Open { until } if until > Instant::now() => CircuitState::Open,
+            Inner::Open { .. } | Inner::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    fn allows(&self, now: Instant) -> bool {
+        let mut inner = self.lock();
+        match *inner {
+            Inner::Closed { .. } => true,
+            Inner::Open { until } if until > now => false,
+            Inner::Open { .. } => {
+                tracing::info!(
+                    circuit = self.name,
+                    "Email circuit half-open, trying a send"
+                );
+                *inner = Inner::HalfOpen {
+                    trial_in_flight: true,
+                };
+                true
+            }
+            Inner::HalfOpen { trial_in_flight } => {
+                *inner = Inner::HalfOpen {
+                    trial_in_flight: true,
+                };
+                !trial_in_flight
+            }
+        }
+    }
+
+    fn record(&self, succeeded: bool, now: Instant) {
+        let mut inner = self.lock();
+        *inner = match (*inner, succeeded) {
+            (Inner::HalfOpen { .. }, true) => {
+                tracing::info!(
+                    circuit = self.name,
+                    "Email circuit closed, the provider is back"
+                );
+                Inner::Closed { failures: 0 }
+            }
+            (_, true) => Inner::Closed { failures: 0 },
+            (Inner::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                Inner::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (Inner::Closed { failures }, false) => {
+                tracing::warn!(
+                    circuit = self.name,
+                    failures = failures + 1,
+                    open_for_seconds = self.open_duration.as_secs(),
+                    "Email circuit opened, failing sends fast"
+                );
+                Inner::Open {
+                    until: now + self.open_duration,
+                }
+            }
+            (Inner::HalfOpen { .. }, false) => {
+                tracing::warn!(
+                    circuit = self.name,
+                    open_for_seconds = self.open_duration.as_secs(),
+                    "Email circuit opened again, the trial send failed"
+                );
+                Inner::Open {
+                    until: now + self.open_duration,
+                }
+            }
+            // Sends that started before the breaker opened.
+            (open @ Inner::Open { .. }, false) => open,
+        };
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // The state stays consistent even if a thread panicked while holding the lock.
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Attempt, CircuitBreaker, CircuitState};
+
+    const OPEN_DURATION: Duration = Duration::from_secs(30);
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new("test", 3, OPEN_DURATION)
+    }
+
+    #[test]
+    fn the_breaker_opens_after_consecutive_failures() {
+        let breaker = breaker();
+        let now = Instant::now();
+
+        breaker.record(false, now);
+        breaker.record(true, now);
+        breaker.record(false, now);
+        breaker.record(false, now);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allows(now));
+
+        breaker.record(false, now);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allows(now));
+    }
+
+    #[test]
+    fn a_single_trial_goes_through_once_the_breaker_has_been_open_for_a_while() {
+        let breaker = breaker();
+        let now = Instant::now();
+        for _ in 0..3 {
+            breaker.record(false, now);
+        }
+        let later = now + OPEN_DURATION;
+
+        assert!(breaker.allows(later));
+        assert!(!breaker.allows(later), "Only one trial may be in flight");
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+    }
+
+    #[test]
+    fn a_successful_trial_closes_the_breaker() {
+        let breaker = breaker();
+        let now = Instant::now();
+        for _ in 0..3 {
+            breaker.record(false, now);
+        }
+        let later = now + OPEN_DURATION;
+        assert!(breaker.allows(later));
+
+        breaker.record(true, later);
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allows(later));
+    }
+
+    #[test]
+    fn a_failed_trial_opens_the_breaker_again() {
+        let breaker = breaker();
+        let now = Instant::now();
+        for _ in 0..3 {
+            breaker.record(false, now);
+        }
+        let later = now + OPEN_DURATION;
+        assert!(breaker.allows(later));
+
+        breaker.record(false, later);
+
+        assert!(!breaker.allows(later + OPEN_DURATION / 2));
+        assert!(breaker.allows(later + OPEN_DURATION));
+    }
+
+    #[test]
+    fn an_abandoned_trial_lets_the_next_send_try() {
+        let breaker = breaker();
+        let now = Instant::now();
+        for _ in 0..3 {
+            breaker.record(false, now);
+        }
+        assert!(breaker.allows(now + OPEN_DURATION));
+        let trial = Attempt {
+            breaker: &breaker,
+            recorded: false,
+        };
+
+        drop(trial);
+
+        assert!(breaker.allows(now + OPEN_DURATION));
+    }
+}
@@ -5,7 +5,21 @@ use sqlx::{
     ConnectOptions, PgPool,
 };
 
-use crate::domain::subscriber_email::SubscriberEmail;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use url::Url;
+
+use crate::{
+    circuit_breaker,
+    domain::subscriber_email::SubscriberEmail,
+    email_client::{AttachmentLimits, EmailProvider, DEFAULT_MAX_ATTACHMENTS_BYTES},
+    error::error_chain_fmt,
+    tenant::DEFAULT_TENANT,
+    throttle::ThrottleLimits,
+};
 
 pub type Db = axum::extract::State<PgPool>;
 
@@ -14,6 +28,50 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    /// Newsletters served alongside the default one, described by `application.base_url`
+    /// and `email_client`.
+    #[serde(default)]
+    pub tenants: Vec<TenantSettings>,
+    /// How fast emails go out, whichever tenant sends them.
+    #[serde(default)]
+    pub email_throttle: EmailThrottleSettings,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailThrottleSettings {
+    /// Through each email provider.
+    #[serde(
+        default = "default_messages_per_second",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub messages_per_second: u32,
+    /// To each recipient domain, unless it has a limit in `domains`.
+    #[serde(
+        default = "default_domain_messages_per_second",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub domain_messages_per_second: u32,
+    /// Messages per second to some recipient domains, e.g. `gmail.com: 5`.
+    #[serde(default)]
+    pub domains: HashMap<String, u32>,
+}
+
+impl Default for EmailThrottleSettings {
+    fn default() -> Self {
+        Self {
+            messages_per_second: default_messages_per_second(),
+            domain_messages_per_second: default_domain_messages_per_second(),
+            domains: HashMap::new(),
+        }
+    }
+}
+
+fn default_messages_per_second() -> u32 {
+    100
+}
+
+fn default_domain_messages_per_second() -> u32 {
+    20
 }
 
 #[derive(serde::Deserialize)]
@@ -22,6 +80,122 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// Bearer token required on every `/admin` endpoint.
+    pub admin_token: Secret<String>,
+    /// Read `admin_token` from this file instead.
+    #[serde(default)]
+    pub admin_token_file: Option<PathBuf>,
+    /// Serve HTTPS directly, when there is no TLS-terminating proxy in front of us.
+    #[serde(default)]
+    pub tls: Option<TlsSettings>,
+    /// Throwaway mailbox domains, one per line, to use instead of the bundled list.
+    /// Changes are picked up without a restart.
+    #[serde(default)]
+    pub disposable_domains_file: Option<PathBuf>,
+    /// How often to look for subscribers due their weekly digest.
+    #[serde(
+        default = "default_digest_check_interval_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub digest_check_interval_seconds: u64,
+    /// How long subject lines are tested on a cohort before the winner goes to everyone else.
+    #[serde(
+        default = "default_subject_test_window_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub subject_test_window_seconds: u64,
+    /// How often to look for subject tests whose window closed.
+    #[serde(
+        default = "default_subject_test_check_interval_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub subject_test_check_interval_seconds: u64,
+    /// How often to look for webhook deliveries that are due.
+    #[serde(
+        default = "default_webhook_check_interval_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub webhook_check_interval_seconds: u64,
+    /// How long to wait before retrying a failed webhook delivery the first time, doubling with
+    /// each attempt.
+    #[serde(
+        default = "default_webhook_retry_delay_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub webhook_retry_delay_seconds: u64,
+    /// How often to look for deferred emails that are due.
+    #[serde(
+        default = "default_email_outbox_check_interval_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub email_outbox_check_interval_seconds: u64,
+    /// How long to wait before retrying a deferred email the first time, doubling with each
+    /// attempt.
+    #[serde(
+        default = "default_email_outbox_retry_delay_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub email_outbox_retry_delay_seconds: u64,
+    /// How often to look for newsletter deliveries due another attempt.
+    #[serde(
+        default = "default_newsletter_delivery_check_interval_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub newsletter_delivery_check_interval_seconds: u64,
+    /// How long to wait before retrying a failed newsletter delivery the first time, doubling
+    /// with each attempt.
+    #[serde(
+        default = "default_newsletter_delivery_retry_delay_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub newsletter_delivery_retry_delay_seconds: u64,
+}
+
+fn default_digest_check_interval_seconds() -> u64 {
+    15 * 60
+}
+
+fn default_subject_test_window_seconds() -> u64 {
+    4 * 60 * 60
+}
+
+fn default_subject_test_check_interval_seconds() -> u64 {
+    60
+}
+
+fn default_webhook_check_interval_seconds() -> u64 {
+    5
+}
+
+fn default_webhook_retry_delay_seconds() -> u64 {
+    30
+}
+
+fn default_email_outbox_check_interval_seconds() -> u64 {
+    5
+}
+
+fn default_email_outbox_retry_delay_seconds() -> u64 {
+    30
+}
+
+fn default_newsletter_delivery_check_interval_seconds() -> u64 {
+    5
+}
+
+fn default_newsletter_delivery_retry_delay_seconds() -> u64 {
+    60
+}
+
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TlsSettings {
+    /// PEM certificate chain, reloaded on SIGHUP or when the file changes.
+    pub certificate_file: PathBuf,
+    /// PEM private key, reloaded along with the certificate.
+    pub key_file: PathBuf,
+    /// Also listen for plain HTTP on this port, redirecting every request to HTTPS.
+    #[serde(default)]
+    pub redirect_http_port: Option<u16>,
 }
 
 impl ApplicationSettings {
@@ -31,7 +205,21 @@ impl ApplicationSettings {
     }
 }
 
+/// A newsletter with its own subscribers, links and sender.
+///
+/// Requests are routed to it when their `Host` header is one of `hosts`, or when their path
+/// starts with `/t/<slug>`.
 #[derive(serde::Deserialize)]
+pub struct TenantSettings {
+    pub slug: String,
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Prefix of the links we email, `/t/<slug>` included if the tenant has no host of its own.
+    pub base_url: String,
+    pub email_client: EmailClientSettings,
+}
+
+#[derive(serde::Deserialize, Debug)]
 pub struct DatabaseSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
@@ -40,6 +228,9 @@ pub struct DatabaseSettings {
     pub require_ssl: bool,
     pub database_name: String,
     pub password: Secret<String>,
+    /// Read `password` from this file instead, e.g. a Docker or Kubernetes secret mount.
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
 }
 
 impl DatabaseSettings {
@@ -73,74 +264,1094 @@ pub struct EmailClientSettings {
     pub sender_email: String,
     pub timeout_milliseconds: u64,
     pub authorization_token: Secret<String>,
+    /// Read `authorization_token` from this file instead.
+    #[serde(default)]
+    pub authorization_token_file: Option<PathBuf>,
+    /// The API at `base_url`, `mailgun` or `postmark`.
+    #[serde(default)]
+    pub provider: EmailProvider,
+    /// The most the attachments of an email may weigh together.
+    #[serde(
+        default = "default_max_attachments_bytes",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub max_attachments_bytes: usize,
+    /// The content types attachments may have, e.g. `image/png`.
+    #[serde(default = "default_attachment_content_types")]
+    pub attachment_content_types: Vec<String>,
+    /// Consecutive failures of the email API after which sends fail fast.
+    #[serde(
+        default = "default_circuit_breaker_failures",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub circuit_breaker_failures: u32,
+    /// How long sends fail fast before one is tried again.
+    #[serde(
+        default = "default_circuit_breaker_open_seconds",
+        deserialize_with = "deserialize_number_from_string"
+    )]
+    pub circuit_breaker_open_seconds: u64,
+}
+
+fn default_max_attachments_bytes() -> usize {
+    DEFAULT_MAX_ATTACHMENTS_BYTES
 }
 
-impl EmailClientSettings {
-    pub fn sender(&self) -> Result<SubscriberEmail, String> {
-        SubscriberEmail::try_from(self.sender_email.clone())
+fn default_attachment_content_types() -> Vec<String> {
+    AttachmentLimits::default().content_types
+}
+
+fn default_circuit_breaker_failures() -> u32 {
+    circuit_breaker::DEFAULT_FAILURE_THRESHOLD
+}
+
+fn default_circuit_breaker_open_seconds() -> u64 {
+    circuit_breaker::DEFAULT_OPEN_DURATION.as_secs()
+}
+
+/// [`Settings`] once every value has been checked and parsed, see [`Settings::validate`].
+#[derive(Debug)]
+pub struct ValidatedSettings {
+    pub database: DatabaseSettings,
+    /// `host:port` to listen on.
+    pub address: String,
+    pub admin_token: Secret<String>,
+    /// The default tenant comes first.
+    pub tenants: Vec<ValidatedTenant>,
+    /// `None` to serve plain HTTP.
+    pub tls: Option<ValidatedTls>,
+    pub disposable_domains_file: Option<PathBuf>,
+    pub digest_check_interval: std::time::Duration,
+    pub subject_test_window: std::time::Duration,
+    pub subject_test_check_interval: std::time::Duration,
+    pub webhook_check_interval: std::time::Duration,
+    pub webhook_retry_delay: std::time::Duration,
+    pub email_outbox_check_interval: std::time::Duration,
+    pub email_outbox_retry_delay: std::time::Duration,
+    pub newsletter_delivery_check_interval: std::time::Duration,
+    pub newsletter_delivery_retry_delay: std::time::Duration,
+    pub email_throttle: ThrottleLimits,
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidatedTls {
+    pub certificate_file: PathBuf,
+    pub key_file: PathBuf,
+    /// `host:port` to listen on for plain HTTP, redirected to HTTPS.
+    pub redirect_address: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ValidatedTenant {
+    pub slug: String,
+    pub hosts: Vec<String>,
+    pub base_url: Url,
+    pub email_client: ValidatedEmailClient,
+}
+
+#[derive(Debug)]
+pub struct ValidatedEmailClient {
+    pub base_url: Url,
+    pub sender: SubscriberEmail,
+    pub authorization_token: Secret<String>,
+    pub timeout: std::time::Duration,
+    pub provider: EmailProvider,
+    pub attachment_limits: AttachmentLimits,
+    pub circuit_breaker_failures: u32,
+    pub circuit_breaker_open: std::time::Duration,
+}
+
+/// Every problem found in the configuration, one per line, prefixed with the offending key.
+#[derive(thiserror::Error)]
+#[error("Invalid configuration:\n  {}", .0.join("\n  "))]
+pub struct ConfigurationError(pub Vec<String>);
+
+impl std::fmt::Debug for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
     }
+}
 
-    #[must_use]
-    pub fn timeout(&self) -> std::time::Duration {
-        std::time::Duration::from_millis(self.timeout_milliseconds)
+impl From<config::ConfigError> for ConfigurationError {
+    fn from(e: config::ConfigError) -> Self {
+        Self(vec![e.to_string()])
     }
 }
 
-#[allow(clippy::module_name_repetitions)]
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let configuration_directory = base_path.join("configuration");
-
-    // Detect the running environment.
-    // Default to `local` if unspecified.
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT.");
-
-    let environment_filename = format!("{}.yaml", environment.as_str());
-
-    let settings = config::Config::builder()
-        .add_source(config::File::from(
-            configuration_directory.join("base.yaml"),
-        ))
-        .add_source(config::File::from(
-            configuration_directory.join(environment_filename),
+/// Collects problems instead of stopping at the first one.
+#[derive(Default)]
+struct Problems(Vec<String>);
+
+impl Problems {
+    fn check<T>(&mut self, key: &str, result: Result<T, String>) -> Option<T> {
+        result.map_err(|e| self.0.push(format!("{key}: {e}"))).ok()
+    }
+
+    /// A period of at least a second, or zero after recording a problem.
+    fn duration(&mut self, key: &str, seconds: u64) -> std::time::Duration {
+        let result = match seconds {
+            0 => Err("must be at least 1 second.".to_owned()),
+            seconds => Ok(std::time::Duration::from_secs(seconds)),
+        };
+        self.check(key, result).unwrap_or_default()
+    }
+}
+
+impl Settings {
+    /// Check every setting, reporting all the problems at once rather than the first one.
+    pub fn validate(self) -> Result<ValidatedSettings, ConfigurationError> {
+        let mut problems = Problems::default();
+
+        let admin_token = problems.check(
+            "application.admin_token",
+            non_empty(self.application.admin_token.expose_secret()),
+        );
+        let mut tenants = vec![validate_tenant(
+            &mut problems,
+            "application",
+            "email_client",
+            DEFAULT_TENANT,
+            &[],
+            &self.application.base_url,
+            self.email_client,
+        )];
+        for (i, tenant) in self.tenants.into_iter().enumerate() {
+            let key = format!("tenants[{i}]");
+            tenants.push(validate_tenant(
+                &mut problems,
+                &key,
+                &format!("{key}.email_client"),
+                &tenant.slug,
+                &tenant.hosts,
+                &tenant.base_url,
+                tenant.email_client,
+            ));
+        }
+        check_uniqueness(&mut problems, &tenants);
+        let tls = self
+            .application
+            .tls
+            .as_ref()
+            .map(|tls| validate_tls(&mut problems, tls, &self.application));
+        if let Some(path) = &self.application.disposable_domains_file {
+            if !path.is_file() {
+                problems.0.push(format!(
+                    "application.disposable_domains_file: there is no file at {}.",
+                    path.display()
+                ));
+            }
+        }
+
+        let digest_check_interval = problems.duration(
+            "application.digest_check_interval_seconds",
+            self.application.digest_check_interval_seconds,
+        );
+        let subject_test_window = problems.duration(
+            "application.subject_test_window_seconds",
+            self.application.subject_test_window_seconds,
+        );
+        let subject_test_check_interval = problems.duration(
+            "application.subject_test_check_interval_seconds",
+            self.application.subject_test_check_interval_seconds,
+        );
+        let webhook_check_interval = problems.duration(
+            "application.webhook_check_interval_seconds",
+            self.application.webhook_check_interval_seconds,
+        );
+        let webhook_retry_delay = problems.duration(
+            "application.webhook_retry_delay_seconds",
+            self.application.webhook_retry_delay_seconds,
+        );
+        let email_outbox_check_interval = problems.duration(
+            "application.email_outbox_check_interval_seconds",
+            self.application.email_outbox_check_interval_seconds,
+        );
+        let email_outbox_retry_delay = problems.duration(
+            "application.email_outbox_retry_delay_seconds",
+            self.application.email_outbox_retry_delay_seconds,
+        );
+        let newsletter_delivery_check_interval = problems.duration(
+            "application.newsletter_delivery_check_interval_seconds",
+            self.application.newsletter_delivery_check_interval_seconds,
+        );
+        let newsletter_delivery_retry_delay = problems.duration(
+            "application.newsletter_delivery_retry_delay_seconds",
+            self.application.newsletter_delivery_retry_delay_seconds,
+        );
+
+        let email_throttle = validate_email_throttle(&mut problems, self.email_throttle);
+
+        match (admin_token, tenants.into_iter().collect::<Option<Vec<_>>>()) {
+            (Some(()), Some(tenants)) if problems.0.is_empty() => Ok(ValidatedSettings {
+                address: self.application.address(),
+                database: self.database,
+                admin_token: self.application.admin_token,
+                tenants,
+                tls,
+                disposable_domains_file: self.application.disposable_domains_file,
+                digest_check_interval,
+                subject_test_window,
+                subject_test_check_interval,
+                webhook_check_interval,
+                webhook_retry_delay,
+                email_outbox_check_interval,
+                email_outbox_retry_delay,
+                newsletter_delivery_check_interval,
+                newsletter_delivery_retry_delay,
+                email_throttle,
+            }),
+            _ => Err(ConfigurationError(problems.0)),
+        }
+    }
+}
+
+fn validate_tenant(
+    problems: &mut Problems,
+    key: &str,
+    email_client_key: &str,
+    slug: &str,
+    hosts: &[String],
+    base_url: &str,
+    email_client: EmailClientSettings,
+) -> Option<ValidatedTenant> {
+    let slug = problems.check(&format!("{key}.slug"), parse_slug(slug));
+    let base_url = problems.check(&format!("{key}.base_url"), parse_base_url(base_url));
+    let email_client = validate_email_client(problems, email_client_key, email_client);
+
+    Some(ValidatedTenant {
+        slug: slug?,
+        hosts: hosts.iter().map(|host| host.to_lowercase()).collect(),
+        base_url: base_url?,
+        email_client: email_client?,
+    })
+}
+
+fn validate_email_client(
+    problems: &mut Problems,
+    key: &str,
+    settings: EmailClientSettings,
+) -> Option<ValidatedEmailClient> {
+    let base_url = problems.check(
+        &format!("{key}.base_url"),
+        parse_base_url(&settings.base_url),
+    );
+    let sender = problems.check(
+        &format!("{key}.sender_email"),
+        SubscriberEmail::try_from(settings.sender_email).map_err(String::from),
+    );
+    let timeout = problems.check(
+        &format!("{key}.timeout_milliseconds"),
+        match settings.timeout_milliseconds {
+            0 => Err("must be greater than zero.".into()),
+            ms => Ok(std::time::Duration::from_millis(ms)),
+        },
+    );
+    let authorization_token = problems.check(
+        &format!("{key}.authorization_token"),
+        non_empty(settings.authorization_token.expose_secret())
+            .map(|()| settings.authorization_token),
+    );
+    let max_attachments_bytes = problems.check(
+        &format!("{key}.max_attachments_bytes"),
+        match settings.max_attachments_bytes {
+            0 => Err("must be greater than zero.".into()),
+            bytes => Ok(bytes),
+        },
+    );
+    let content_types = problems.check(
+        &format!("{key}.attachment_content_types"),
+        settings
+            .attachment_content_types
+            .iter()
+            .map(|content_type| parse_content_type(content_type))
+            .collect(),
+    );
+    let circuit_breaker_failures = problems.check(
+        &format!("{key}.circuit_breaker_failures"),
+        match settings.circuit_breaker_failures {
+            0 => Err("must be at least 1.".into()),
+            failures => Ok(failures),
+        },
+    );
+    let circuit_breaker_open = problems.duration(
+        &format!("{key}.circuit_breaker_open_seconds"),
+        settings.circuit_breaker_open_seconds,
+    );
+
+    Some(ValidatedEmailClient {
+        base_url: base_url?,
+        sender: sender?,
+        authorization_token: authorization_token?,
+        timeout: timeout?,
+        provider: settings.provider,
+        attachment_limits: AttachmentLimits {
+            max_bytes: max_attachments_bytes?,
+            content_types: content_types?,
+        },
+        circuit_breaker_failures: circuit_breaker_failures?,
+        circuit_breaker_open,
+    })
+}
+
+fn validate_email_throttle(
+    problems: &mut Problems,
+    settings: EmailThrottleSettings,
+) -> ThrottleLimits {
+    let mut rate = |key: &str, messages_per_second: u32| {
+        let result = match messages_per_second {
+            0 => Err("must be at least 1 message per second.".to_owned()),
+            rate => Ok(rate),
+        };
+        problems.check(key, result).unwrap_or(1)
+    };
+    let messages_per_second = rate(
+        "email_throttle.messages_per_second",
+        settings.messages_per_second,
+    );
+    let domain_messages_per_second = rate(
+        "email_throttle.domain_messages_per_second",
+        settings.domain_messages_per_second,
+    );
+    let domains = settings
+        .domains
+        .into_iter()
+        .map(|(domain, messages_per_second)| {
+            let messages_per_second = rate(
+                &format!("email_throttle.domains.{domain}"),
+                messages_per_second,
+            );
+            (domain.to_ascii_lowercase(), messages_per_second)
+        })
+        .collect();
+
+    ThrottleLimits {
+        messages_per_second,
+        domain_messages_per_second,
+        domains,
+    }
+}
+
+fn validate_tls(
+    problems: &mut Problems,
+    settings: &TlsSettings,
+    application: &ApplicationSettings,
+) -> ValidatedTls {
+    for (key, path) in [
+        (
+            "application.tls.certificate_file",
+            &settings.certificate_file,
+        ),
+        ("application.tls.key_file", &settings.key_file),
+    ] {
+        if !path.is_file() {
+            problems
+                .0
+                .push(format!("{key}: there is no file at {}.", path.display()));
+        }
+    }
+    if settings.redirect_http_port == Some(application.port) && application.port != 0 {
+        problems.0.push(format!(
+            "application.tls.redirect_http_port: {} is already used for HTTPS.",
+            application.port
+        ));
+    }
+
+    ValidatedTls {
+        certificate_file: settings.certificate_file.clone(),
+        key_file: settings.key_file.clone(),
+        redirect_address: settings
+            .redirect_http_port
+            .map(|port| format!("{}:{port}", application.host)),
+    }
+}
+
+/// Slugs and hosts must each point to a single tenant.
+fn check_uniqueness(problems: &mut Problems, tenants: &[Option<ValidatedTenant>]) {
+    let mut slugs = HashSet::new();
+    let mut hosts = HashSet::new();
+    for (i, tenant) in tenants.iter().enumerate() {
+        let Some(tenant) = tenant else { continue };
+        // The default tenant is at index 0 but comes from the top-level settings.
+        let key = format!("tenants[{}]", i.saturating_sub(1));
+        if !slugs.insert(tenant.slug.as_str()) {
+            problems
+                .0
+                .push(format!("{key}.slug: {} is used twice.", tenant.slug));
+        }
+        for host in &tenant.hosts {
+            if !hosts.insert(host.as_str()) {
+                problems.0.push(format!(
+                    "{key}.hosts: {host} is assigned to several tenants."
+                ));
+            }
+        }
+    }
+}
+
+fn parse_base_url(value: &str) -> Result<Url, String> {
+    let url = Url::parse(value).map_err(|e| format!("{value:?} is not a valid URL: {e}."))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        other => Err(format!(
+            "{value:?} must be an http or https URL, not {other}."
+        )),
+    }
+}
+
+fn parse_slug(value: &str) -> Result<String, String> {
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Ok(value.to_owned())
+    } else {
+        Err(format!(
+            "{value:?} is not a valid tenant slug. Use letters, digits and dashes."
         ))
-        .add_source(
-            config::Environment::with_prefix("APP")
-                .prefix_separator("_")
-                .separator("__"),
-        )
-        .build()?;
+    }
+}
+
+/// `value` lowercased, if it is a `type/subtype` without parameters.
+fn parse_content_type(value: &str) -> Result<String, String> {
+    match value.split_once('/') {
+        Some((kind, subtype))
+            if !kind.is_empty()
+                && !subtype.is_empty()
+                && !value.contains([';', ' ', '*'])
+                && !subtype.contains('/') =>
+        {
+            Ok(value.to_ascii_lowercase())
+        }
+        _ => Err(format!("{value:?} is not a content type like image/png.")),
+    }
+}
 
-    settings.try_deserialize::<Settings>()
+fn non_empty(value: &str) -> Result<(), String> {
+    if value.trim().is_empty() {
+        Err("must not be empty.".into())
+    } else {
+        Ok(())
+    }
 }
 
-/// The possible runtime environment for our application.
-pub enum Environment {
-    Local,
-    Production,
+/// Secret values nobody should deploy with.
+const PLACEHOLDER_SECRETS: &[&str] = &["", "password", "secret", "changeme", "change-me", "todo"];
+
+/// Settings holding secrets, by name, wherever they appear.
+const SECRET_SETTINGS: &[&str] = &["admin_token", "authorization_token", "password"];
+
+/// The configuration layers, from lowest to highest precedence.
+struct Sources {
+    environment: Environment,
+    /// `base.yaml`, then the environment's file, labelled with their path.
+    files: Vec<(String, config::Config)>,
+    /// `APP_`-prefixed environment variables, `__` separating nested keys.
+    variables: config::Config,
 }
+
+impl Sources {
+    fn load() -> Result<Self, ConfigurationError> {
+        let base_path = std::env::current_dir().expect("Failed to determine the current directory");
+        let configuration_directory = base_path.join("configuration");
+
+        // Detect the running environment.
+        // Default to `local` if unspecified.
+        let environment: Environment = std::env::var("APP_ENVIRONMENT")
+            .unwrap_or_else(|_| "local".into())
+            .try_into()
+            .map_err(|e| ConfigurationError(vec![format!("APP_ENVIRONMENT: {e}")]))?;
+
+        let environment_filename = format!("{}.yaml", environment.as_str());
+        if !configuration_directory
+            .join(&environment_filename)
+            .is_file()
+        {
+            return Err(ConfigurationError(vec![format!(
+                "APP_ENVIRONMENT: there is no configuration/{environment_filename} \
+                for the {} environment.",
+                environment.as_str()
+            )]));
+        }
+
+        let mut files = Vec::new();
+        for filename in ["base.yaml", environment_filename.as_str()] {
+            let file = config::Config::builder()
+                .add_source(config::File::from(configuration_directory.join(filename)))
+                .build()?;
+            files.push((format!("configuration/{filename}"), file));
+        }
+        let variables = config::Config::builder()
+            .add_source(
+                config::Environment::with_prefix("APP")
+                    .prefix_separator("_")
+                    .separator("__"),
+            )
+            .build()?;
+
+        Ok(Self {
+            environment,
+            files,
+            variables,
+        })
+    }
+
+    /// What's in the repository, without the environment variables.
+    fn committed(&self) -> Result<config::Config, config::ConfigError> {
+        self.files
+            .iter()
+            .fold(config::Config::builder(), |builder, (_, file)| {
+                builder.add_source(file.clone())
+            })
+            .build()
+    }
+
+    fn merged(&self) -> Result<config::Config, config::ConfigError> {
+        config::Config::builder()
+            .add_source(self.committed()?)
+            .add_source(self.variables.clone())
+            .build()
+    }
+
+    /// Every value of the merged configuration, secrets redacted, with the layer it comes from.
+    fn effective_values(&self) -> Result<Vec<EffectiveValue>, config::ConfigError> {
+        let mut values = Vec::new();
+        flatten(String::new(), &self.merged()?.cache, &mut values);
+
+        for value in &mut values {
+            value.source = if self.variables.get::<config::Value>(&value.key).is_ok() {
+                format!(
+                    "environment variable APP_{}",
+                    value.key.replace('.', "__").to_uppercase()
+                )
+            } else {
+                self.files
+                    .iter()
+                    .rev()
+                    .find(|(_, file)| file.get::<config::Value>(&value.key).is_ok())
+                    .map_or_else(String::new, |(label, _)| label.clone())
+            };
+        }
+
+        // Secrets read from a file come from that file, whatever the layers say.
+        let secret_files: Vec<(String, String)> = values
+            .iter()
+            .filter_map(|v| {
+                let key = v.key.strip_suffix("_file")?;
+                // Other files, e.g. `tls.certificate_file`, are settings of their own.
+                let name = key.rsplit('.').next().unwrap_or_default();
+                SECRET_SETTINGS
+                    .contains(&name)
+                    .then(|| (key.to_owned(), v.value.clone()))
+            })
+            .collect();
+        for (key, path) in secret_files {
+            match values.iter_mut().find(|v| v.key == key) {
+                Some(value) => value.source = format!("file {path}"),
+                None => values.push(EffectiveValue {
+                    key,
+                    value: String::new(),
+                    source: format!("file {path}"),
+                }),
+            }
+        }
+
+        for value in &mut values {
+            let name = value.key.rsplit('.').next().unwrap_or_default();
+            if SECRET_SETTINGS.contains(&name) {
+                value.value = "[REDACTED]".into();
+            }
+        }
+        values.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(values)
+    }
+}
+
+/// Collect the leaves of `value`, keyed by their path, e.g. `tenants[0].email_client.base_url`.
+fn flatten(key: String, value: &config::Value, values: &mut Vec<EffectiveValue>) {
+    match &value.kind {
+        config::ValueKind::Table(table) => {
+            for (name, value) in table {
+                let key = if key.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{key}.{name}")
+                };
+                flatten(key, value, values);
+            }
+        }
+        config::ValueKind::Array(array) => {
+            for (i, value) in array.iter().enumerate() {
+                flatten(format!("{key}[{i}]"), value, values);
+            }
+        }
+        _ => values.push(EffectiveValue {
+            key,
+            value: value.to_string(),
+            source: String::new(),
+        }),
+    }
+}
+
+/// A setting as the application sees it, see [`show_configuration`].
+pub struct EffectiveValue {
+    pub key: String,
+    /// `[REDACTED]` for secrets.
+    pub value: String,
+    /// The file or environment variable the value comes from.
+    pub source: String,
+}
+
+/// The fully merged configuration of the current environment, for inspection.
+pub fn show_configuration() -> Result<(Environment, Vec<EffectiveValue>), ConfigurationError> {
+    let sources = Sources::load()?;
+    let values = sources.effective_values()?;
+    Ok((sources.environment, values))
+}
+
+#[allow(clippy::module_name_repetitions)]
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    let sources = Sources::load()?;
+    let committed = sources.committed()?;
+    let mut settings = sources.merged()?.try_deserialize::<Settings>()?;
+
+    let mut problems = Problems::default();
+    settings.read_secret_files(&mut problems);
+    if sources.environment.is_production() {
+        settings.check_production_secrets(&committed, &mut problems);
+    }
+
+    if problems.0.is_empty() {
+        Ok(settings)
+    } else {
+        Err(ConfigurationError(problems.0))
+    }
+}
+
+impl Settings {
+    /// Replace every secret that has a `_file` variant set with the content of that file.
+    fn read_secret_files(&mut self, problems: &mut Problems) {
+        read_secret_file(
+            problems,
+            "application.admin_token",
+            &mut self.application.admin_token,
+            self.application.admin_token_file.as_deref(),
+        );
+        read_secret_file(
+            problems,
+            "database.password",
+            &mut self.database.password,
+            self.database.password_file.as_deref(),
+        );
+        let email_clients = std::iter::once(("email_client".to_owned(), &mut self.email_client))
+            .chain(
+                self.tenants
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(i, t)| (format!("tenants[{i}].email_client"), &mut t.email_client)),
+            );
+        for (key, email_client) in email_clients {
+            read_secret_file(
+                problems,
+                &format!("{key}.authorization_token"),
+                &mut email_client.authorization_token,
+                email_client.authorization_token_file.as_deref(),
+            );
+        }
+    }
+
+    /// Refuse secrets that are placeholders, or that weren't overridden from the values
+    /// committed in the configuration files.
+    fn check_production_secrets(&self, committed: &config::Config, problems: &mut Problems) {
+        for (key, secret) in self.secrets() {
+            let secret = secret.expose_secret();
+            if PLACEHOLDER_SECRETS.contains(&secret.trim().to_lowercase().as_str()) {
+                problems
+                    .0
+                    .push(format!("{key}: is a placeholder, set a real secret."));
+            } else if committed.get_string(&key).is_ok_and(|c| &c == secret) {
+                problems.0.push(format!(
+                    "{key}: comes from the committed configuration files, \
+                    set it through APP_ environment variables or a `_file` instead."
+                ));
+            }
+        }
+    }
+
+    /// Every secret, keyed by its path in the configuration.
+    fn secrets(&self) -> Vec<(String, &Secret<String>)> {
+        let mut secrets = vec![
+            (
+                "application.admin_token".to_owned(),
+                &self.application.admin_token,
+            ),
+            ("database.password".to_owned(), &self.database.password),
+            (
+                "email_client.authorization_token".to_owned(),
+                &self.email_client.authorization_token,
+            ),
+        ];
+        for (i, tenant) in self.tenants.iter().enumerate() {
+            secrets.push((
+                format!("tenants[{i}].email_client.authorization_token"),
+                &tenant.email_client.authorization_token,
+            ));
+        }
+        secrets
+    }
+}
+
+fn read_secret_file(
+    problems: &mut Problems,
+    key: &str,
+    secret: &mut Secret<String>,
+    file: Option<&Path>,
+) {
+    let Some(file) = file else { return };
+    match std::fs::read_to_string(file) {
+        // Editors and `echo` leave a trailing newline behind.
+        Ok(content) => *secret = Secret::new(content.trim_end_matches(['\n', '\r']).to_owned()),
+        Err(e) => problems.0.push(format!(
+            "{key}_file: failed to read {}: {e}.",
+            file.display()
+        )),
+    }
+}
+
+/// The runtime environment, `local` by default.
+///
+/// Any name works as long as there is a matching `configuration/{name}.yaml`, layered on top of
+/// `base.yaml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Environment(String);
+
 impl Environment {
     #[must_use]
-    pub fn as_str(&self) -> &'static str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
-        }
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Production gets extra checks, see [`get_configuration`].
+    #[must_use]
+    pub fn is_production(&self) -> bool {
+        self.0 == "production"
     }
 }
+
 impl TryFrom<String> for Environment {
     type Error = String;
+
     fn try_from(s: String) -> Result<Self, Self::Error> {
-        match s.to_lowercase().as_str() {
-            "local" => Ok(Self::Local),
-            "production" => Ok(Self::Production),
-            other => Err(format!(
-                "{other} is not a supported environment. \
-                Use either `local` or `production`."
-            )),
+        let name = s.to_lowercase();
+        let valid_characters = name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if name.is_empty() || !valid_characters || name == "base" {
+            return Err(format!(
+                "{s:?} is not a valid environment name. \
+                Use letters, digits, dashes and underscores, and not `base`."
+            ));
         }
+        Ok(Self(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ApplicationSettings, DatabaseSettings, EmailClientSettings, EmailProvider,
+        EmailThrottleSettings, Environment, Problems, Settings, Sources, TenantSettings,
+        TlsSettings,
+    };
+    use claims::{assert_err, assert_ok};
+    use secrecy::{ExposeSecret, Secret};
+
+    fn email_client(sender_email: &str) -> EmailClientSettings {
+        EmailClientSettings {
+            base_url: "https://api.mailgun.net/v3".into(),
+            sender_email: sender_email.into(),
+            timeout_milliseconds: 10_000,
+            authorization_token: Secret::new("token".into()),
+            authorization_token_file: None,
+            provider: EmailProvider::Mailgun,
+            max_attachments_bytes: 1024 * 1024,
+            attachment_content_types: vec!["image/png".into()],
+            circuit_breaker_failures: 5,
+            circuit_breaker_open_seconds: 30,
+        }
+    }
+
+    fn tenant(slug: &str, host: &str) -> TenantSettings {
+        TenantSettings {
+            slug: slug.into(),
+            hosts: vec![host.into()],
+            base_url: format!("https://{host}"),
+            email_client: email_client(&format!("{slug}@newsletter.com")),
+        }
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            database: DatabaseSettings {
+                port: 5432,
+                host: "127.0.0.1".into(),
+                username: "postgres".into(),
+                require_ssl: false,
+                database_name: "newsletter".into(),
+                password: Secret::new("password".into()),
+                password_file: None,
+            },
+            application: ApplicationSettings {
+                port: 8000,
+                host: "127.0.0.1".into(),
+                base_url: "http://127.0.0.1".into(),
+                admin_token: Secret::new("admin".into()),
+                admin_token_file: None,
+                tls: None,
+                disposable_domains_file: None,
+                digest_check_interval_seconds: 60,
+                subject_test_window_seconds: 60,
+                subject_test_check_interval_seconds: 60,
+                webhook_check_interval_seconds: 5,
+                webhook_retry_delay_seconds: 30,
+                email_outbox_check_interval_seconds: 5,
+                email_outbox_retry_delay_seconds: 30,
+                newsletter_delivery_check_interval_seconds: 5,
+                newsletter_delivery_retry_delay_seconds: 60,
+            },
+            email_client: email_client("test@gmail.com"),
+            tenants: vec![tenant("rust", "rust.localhost")],
+            email_throttle: EmailThrottleSettings::default(),
+        }
+    }
+
+    #[test]
+    fn valid_settings_are_accepted() {
+        let settings = assert_ok!(settings().validate());
+        let slugs: Vec<_> = settings.tenants.iter().map(|t| t.slug.as_str()).collect();
+        assert_eq!(slugs, vec!["default", "rust"]);
+        assert_eq!(settings.address, "127.0.0.1:8000");
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let mut settings = settings();
+        settings.application.base_url = "127.0.0.1".into();
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.timeout_milliseconds = 0;
+        settings.tenants[0].email_client.base_url = "ftp://mailgun.net".into();
+        settings.tenants[0].email_client.attachment_content_types = vec!["image/*".into()];
+        settings.tenants[0].email_client.circuit_breaker_failures = 0;
+        settings
+            .email_throttle
+            .domains
+            .insert("gmail.com".into(), 0);
+
+        let error = assert_err!(settings.validate());
+
+        let keys: Vec<_> = error
+            .0
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "application.base_url",
+                "email_client.sender_email",
+                "email_client.timeout_milliseconds",
+                "tenants[0].email_client.base_url",
+                "tenants[0].email_client.attachment_content_types",
+                "tenants[0].email_client.circuit_breaker_failures",
+                "email_throttle.domains.gmail.com",
+            ]
+        );
+    }
+
+    #[test]
+    fn slugs_and_hosts_must_be_unique() {
+        let mut settings = settings();
+        settings.tenants.push(tenant("rust", "RUST.localhost"));
+        settings
+            .tenants
+            .push(tenant("default", "default.localhost"));
+
+        let error = assert_err!(settings.validate());
+
+        assert_eq!(error.0.len(), 3, "{:?}", error.0);
+    }
+
+    #[test]
+    fn tls_needs_a_certificate_and_a_port_of_its_own() {
+        let mut settings = settings();
+        settings.application.tls = Some(TlsSettings {
+            certificate_file: "/does/not/exist.crt".into(),
+            key_file: "/does/not/exist.key".into(),
+            redirect_http_port: Some(settings.application.port),
+        });
+
+        let error = assert_err!(settings.validate());
+
+        assert_eq!(error.0.len(), 3, "{:?}", error.0);
+    }
+
+    #[test]
+    fn secret_files_override_inline_secrets() {
+        let path = std::env::temp_dir().join(format!("secret-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "from-a-file\n").unwrap();
+        let mut settings = settings();
+        settings.database.password_file = Some(path.clone());
+        let mut problems = Problems::default();
+
+        settings.read_secret_files(&mut problems);
+
+        std::fs::remove_file(path).unwrap();
+        assert!(problems.0.is_empty());
+        assert_eq!(settings.database.password.expose_secret(), "from-a-file");
+    }
+
+    #[test]
+    fn missing_secret_files_are_reported() {
+        let mut settings = settings();
+        settings.tenants[0].email_client.authorization_token_file = Some("/does/not/exist".into());
+        let mut problems = Problems::default();
+
+        settings.read_secret_files(&mut problems);
+
+        assert_eq!(problems.0.len(), 1);
+        assert!(problems.0[0].starts_with("tenants[0].email_client.authorization_token_file:"));
+    }
+
+    #[test]
+    fn production_refuses_placeholders_and_committed_secrets() {
+        let committed = config::Config::builder()
+            .add_source(config::File::from_str(
+                "application:\n  admin_token: admin\nemail_client:\n  authorization_token: token",
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap();
+        let mut problems = Problems::default();
+
+        // `admin` and `token` are committed, `password` is a placeholder.
+        settings().check_production_secrets(&committed, &mut problems);
+
+        let keys: Vec<_> = problems
+            .0
+            .iter()
+            .map(|problem| problem.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                "application.admin_token",
+                "database.password",
+                "email_client.authorization_token",
+            ]
+        );
+    }
+
+    #[test]
+    fn production_accepts_overridden_secrets() {
+        let committed = config::Config::builder()
+            .add_source(config::File::from_str(
+                "database:\n  password: committed",
+                config::FileFormat::Yaml,
+            ))
+            .build()
+            .unwrap();
+        let mut settings = settings();
+        settings.database.password = Secret::new("a-real-password".into());
+        settings.application.admin_token = Secret::new("a-real-token".into());
+        let mut problems = Problems::default();
+
+        settings.check_production_secrets(&committed, &mut problems);
+
+        assert!(problems.0.is_empty(), "{:?}", problems.0);
+    }
+
+    #[test]
+    fn invalid_slugs_are_rejected() {
+        for slug in ["", "with space", "with/slash"] {
+            let mut settings = settings();
+            settings.tenants[0].slug = slug.into();
+            assert_err!(settings.validate());
+        }
+    }
+
+    #[test]
+    fn any_environment_name_is_accepted() {
+        for name in ["local", "staging", "Preview-42", "eu_west"] {
+            assert_ok!(Environment::try_from(name.to_owned()), "{name}");
+        }
+        assert!(Environment::try_from("PRODUCTION".to_owned())
+            .unwrap()
+            .is_production());
+    }
+
+    #[test]
+    fn environment_names_cannot_escape_the_configuration_directory() {
+        for name in ["", "../secrets", "base", "a b"] {
+            assert_err!(Environment::try_from(name.to_owned()), "{name}");
+        }
+    }
+
+    fn layer(yaml: &str) -> config::Config {
+        config::Config::builder()
+            .add_source(config::File::from_str(yaml, config::FileFormat::Yaml))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn effective_values_are_redacted_and_attributed_to_their_source() {
+        let variables = config::Config::builder()
+            .add_source(
+                config::Environment::with_prefix("APP")
+                    .prefix_separator("_")
+                    .separator("__")
+                    .source(Some(
+                        [("APP_APPLICATION__PORT".to_owned(), "9000".to_owned())].into(),
+                    )),
+            )
+            .build()
+            .unwrap();
+        let sources = Sources {
+            environment: Environment::try_from("staging".to_owned()).unwrap(),
+            files: vec![
+                (
+                    "configuration/base.yaml".into(),
+                    layer("application: {port: 8000, host: 0.0.0.0, admin_token: hunter2}"),
+                ),
+                (
+                    "configuration/staging.yaml".into(),
+                    layer(
+                        "application: {host: staging.local}\ndatabase: {password_file: /run/db}\n\
+                        tls: {certificate_file: /run/tls/cert.pem}",
+                    ),
+                ),
+            ],
+            variables,
+        };
+
+        let values = sources.effective_values().unwrap();
+        let find = |key: &str| {
+            let value = values.iter().find(|v| v.key == key).unwrap();
+            (value.value.as_str(), value.source.as_str())
+        };
+        assert_eq!(
+            find("application.port"),
+            ("9000", "environment variable APP_APPLICATION__PORT")
+        );
+        assert_eq!(
+            find("application.host"),
+            ("staging.local", "configuration/staging.yaml")
+        );
+        assert_eq!(
+            find("application.admin_token"),
+            ("[REDACTED]", "configuration/base.yaml")
+        );
+        assert_eq!(find("database.password"), ("[REDACTED]", "file /run/db"));
+        assert_eq!(
+            find("tls.certificate_file"),
+            ("/run/tls/cert.pem", "configuration/staging.yaml")
+        );
+        assert!(values.iter().all(|v| v.key != "tls.certificate"));
     }
 }
@@ -0,0 +1,224 @@
+//! The body of a newsletter issue, as HTML and as plain text.
+//!
+//! Issues are written either as HTML, optionally along with their plain text version, or as
+//! Markdown, rendered into our layout. Either way the HTML goes through [`prepare_html`] before
+//! it is stored and sent. When there is no plain text version, it is derived from the HTML.
+
+use ammonia::UrlRelative;
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::inline_css::inline_css;
+
+/// Plain text lines are wrapped at this width.
+const TEXT_WIDTH: usize = 78;
+
+/// Gmail clips emails whose HTML is over 102KB, hiding the rest behind a link. Issues have to
+/// stay below that with room to spare for the footer and the tracking of subject tests.
+pub const MAX_HTML_SIZE: usize = 96 * 1024;
+
+/// Our layout around issues written in Markdown: a single column that reads well in every
+/// mail client.
+const LAYOUT: &str = r#"<div style="max-width: 600px; margin: 0 auto; font-family: Helvetica, Arial, sans-serif; font-size: 16px; line-height: 1.5; color: #222222;">{content}</div>"#;
+
+/// Attributes allowed on any element on top of ammonia's defaults, for layouts built with
+/// tables as mail clients expect.
+const PRESENTATION_ATTRIBUTES: [&str; 9] = [
+    "style",
+    "align",
+    "valign",
+    "bgcolor",
+    "width",
+    "height",
+    "border",
+    "cellpadding",
+    "cellspacing",
+];
+
+/// An issue's HTML and plain text, ready to be stored and sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssueContent {
+    pub html: String,
+    pub text: String,
+}
+
+impl IssueContent {
+    /// Render Markdown into HTML within our layout, and into plain text.
+    pub fn from_markdown(markdown: &str, base_url: &str) -> Result<Self, String> {
+        let mut rendered = String::with_capacity(markdown.len() * 3 / 2);
+        let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+        html::push_html(&mut rendered, Parser::new_ext(markdown, options));
+        let html = prepare_html(&LAYOUT.replace("{content}", &rendered), base_url)?;
+        let text = html_to_text(&html)?;
+        Ok(Self { html, text })
+    }
+
+    /// Prepare HTML for sending, deriving the plain text version from it if there is none.
+    pub fn from_html(html: &str, text: Option<String>, base_url: &str) -> Result<Self, String> {
+        let html = prepare_html(html, base_url)?;
+        let text = match text {
+            Some(text) => text,
+            None => html_to_text(&html)?,
+        };
+        Ok(Self { html, text })
+    }
+}
+
+/// Make HTML fit for every inbox: inline its stylesheets, strip whatever isn't on our
+/// allowlist (scripts, forms, event handlers...), make its links absolute, relative to
+/// `base_url`, and check it won't be clipped.
+pub fn prepare_html(html: &str, base_url: &str) -> Result<String, String> {
+    let base_url = url::Url::parse(&format!("{base_url}/"))
+        .map_err(|e| format!("{base_url} is not a valid base URL: {e}"))?;
+    let inlined = inline_css(html)?;
+    let html = ammonia::Builder::default()
+        .add_generic_attributes(PRESENTATION_ATTRIBUTES)
+        .attribute_filter(|_, attribute, value| match attribute {
+            "style" => Some(safe_declarations(value).into()),
+            _ => Some(value.into()),
+        })
+        .url_relative(UrlRelative::RewriteWithBase(base_url))
+        .clean(&inlined)
+        .to_string();
+
+    if html.len() > MAX_HTML_SIZE {
+        return Err(format!(
+            "The issue's HTML is {}KB once prepared for sending, over the {}KB that fit in an \
+            email without Gmail clipping it.",
+            html.len().div_ceil(1024),
+            MAX_HTML_SIZE / 1024
+        ));
+    }
+    Ok(html)
+}
+
+/// `style` minus the declarations that could run code in older mail clients.
+fn safe_declarations(style: &str) -> String {
+    const UNSAFE: [&str; 3] = ["expression(", "javascript:", "behavior:"];
+
+    style
+        .split(';')
+        .map(str::trim)
+        .filter(|declaration| !declaration.is_empty())
+        .filter(|declaration| {
+            let lowercase = declaration.to_ascii_lowercase().replace(' ', "");
+            !UNSAFE.iter().any(|pattern| lowercase.contains(pattern))
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// A readable plain text version of `html`, links listed at the end as footnotes.
+pub fn html_to_text(html: &str) -> Result<String, String> {
+    html2text::from_read(html.as_bytes(), TEXT_WIDTH)
+        .map(|text| text.trim_end().to_owned())
+        .map_err(|e| format!("The HTML can't be rendered as plain text: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{html_to_text, prepare_html, IssueContent, MAX_HTML_SIZE};
+    use claims::{assert_err, assert_ok, assert_ok_eq};
+
+    const BASE_URL: &str = "https://newsletter.com/t/rust";
+
+    #[test]
+    fn markdown_is_rendered_within_the_layout() {
+        let content = assert_ok!(IssueContent::from_markdown(
+            "# Release notes\n\nRead **the post** at [our blog](https://example.com/post).",
+            BASE_URL,
+        ));
+        assert!(content.html.starts_with("<div style="));
+        assert!(content.html.contains("<h1>Release notes</h1>"));
+        assert!(content.html.contains("<strong>the post</strong>"));
+        assert!(content.html.contains(r#"href="https://example.com/post""#));
+        assert!(content.text.contains("Release notes"));
+        assert!(content.text.contains("https://example.com/post"));
+        assert!(!content.text.contains('<'));
+    }
+
+    #[test]
+    fn html_embedded_in_markdown_is_sanitised() {
+        let content = assert_ok!(IssueContent::from_markdown(
+            "Hello <script>alert(1)</script><img src=\"x.png\" onerror=\"alert(2)\">",
+            BASE_URL,
+        ));
+        assert!(!content.html.contains("<script"));
+        assert!(!content.html.contains("onerror"));
+        assert!(content.html.contains("Hello"));
+    }
+
+    #[test]
+    fn a_missing_plain_text_version_is_derived_from_the_html() {
+        let content = assert_ok!(IssueContent::from_html(
+            "<h1>Title</h1><p>First paragraph.</p><p>Second paragraph.</p>",
+            None,
+            BASE_URL,
+        ));
+        assert!(content.text.contains("Title"));
+        assert!(content
+            .text
+            .contains("First paragraph.\n\nSecond paragraph."));
+    }
+
+    #[test]
+    fn a_given_plain_text_version_is_kept() {
+        let content = assert_ok!(IssueContent::from_html(
+            "<p>HTML</p>",
+            Some("Plain text".to_owned()),
+            BASE_URL,
+        ));
+        assert_eq!(content.text, "Plain text");
+        assert_eq!(content.html, "<p>HTML</p>");
+    }
+
+    #[test]
+    fn links_are_kept_in_plain_text() {
+        let text = assert_ok!(html_to_text(
+            r#"<p>Read <a href="https://example.com/post">the post</a></p>"#
+        ));
+        assert!(text.contains("the post"));
+        assert!(text.contains("https://example.com/post"));
+    }
+
+    #[test]
+    fn stylesheets_are_inlined_and_scripts_stripped() {
+        assert_ok_eq!(
+            prepare_html(
+                "<html><head><style>p { color: red }</style><script>alert(1)</script></head>\
+                 <body><p onclick=\"alert(2)\">Hello</p><form><input></form></body></html>",
+                BASE_URL,
+            ),
+            "<p style=\"color: red\">Hello</p>"
+        );
+    }
+
+    #[test]
+    fn relative_links_are_made_absolute() {
+        let html = assert_ok!(prepare_html(
+            r#"<a href="posts/1">Post</a><img src="/logo.png"><a href="https://example.com">Out</a>"#,
+            BASE_URL,
+        ));
+        assert!(html.contains(r#"href="https://newsletter.com/t/rust/posts/1""#));
+        assert!(html.contains(r#"src="https://newsletter.com/logo.png""#));
+        assert!(html.contains(r#"href="https://example.com""#));
+    }
+
+    #[test]
+    fn styles_that_could_run_code_are_dropped() {
+        assert_ok_eq!(
+            prepare_html(
+                r#"<p style="color: red; width: expression(alert(1)); background: url(javascript:alert(2))">Hi</p>"#,
+                BASE_URL,
+            ),
+            r#"<p style="color: red">Hi</p>"#
+        );
+    }
+
+    #[test]
+    fn html_that_gmail_would_clip_is_rejected() {
+        let paragraph = "<p>All work and no play makes Jack a dull boy.</p>";
+        let html = paragraph.repeat(MAX_HTML_SIZE / paragraph.len() + 1);
+        assert_err!(prepare_html(&html, BASE_URL));
+        assert_ok!(prepare_html(&paragraph.repeat(100), BASE_URL));
+    }
+}
@@ -0,0 +1,347 @@
+//! The weekly digest: one email rolling up the issues published since a subscriber's previous
+//! digest, for subscribers who chose it over getting every issue as it is published.
+//!
+//! Every instance runs [`send_on_schedule`]. A subscriber's row stays locked while their digest
+//! is sent, so instances never send the same digest twice. A digest that fails to send is
+//! retried with exponential backoff, while the other subscribers get theirs, and skipped for the
+//! week after [`MAX_ATTEMPTS`]. While the tenant's circuit breaker is open, digests are put off
+//! without counting as attempts.
+
+use std::{fmt::Write, time::Duration};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::subscriber_email::SubscriberEmail,
+    error::SendEmailError,
+    i18n::{translate, Locale},
+    routes::{escape_html, preferences_link, with_footer},
+    segment::Segment,
+    tenant::{Tenant, Tenants},
+    webhooks::backoff,
+};
+
+/// A digest that failed to send is first retried after this long, doubling with each attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Digests are given up on for the week after this many attempts, about ten hours.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// Look for due digests every `interval`, sending them. Never returns.
+pub async fn send_on_schedule(pool: PgPool, tenants: Tenants, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match send_due_digests(&pool, &tenants).await {
+            Ok(0) => {}
+            Ok(sent) => tracing::info!(sent, "Sent weekly digests"),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to send weekly digests, retrying on the next check"
+            ),
+        }
+    }
+}
+
+/// Send every digest that is due, returning how many subscribers got one.
+#[tracing::instrument(name = "Send due digests", skip_all)]
+pub async fn send_due_digests(pool: &PgPool, tenants: &Tenants) -> Result<usize, anyhow::Error> {
+    let mut sent = 0;
+    while let Some(outcome) = send_next_digest(pool, tenants).await? {
+        if outcome == Outcome::Sent {
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Sent,
+    /// The email provider failed, the digest is retried later.
+    Failed,
+    /// The circuit breaker is open so nothing was tried, the digest is retried later without
+    /// counting as an attempt.
+    Deferred,
+    /// Nothing was published since their previous digest, or we can't email them.
+    Skipped,
+}
+
+struct Issue {
+    title: String,
+    text_content: String,
+    html_content: String,
+    segment: Option<String>,
+}
+
+/// Send the digest of one subscriber whose previous digest is at least a week old, `None` if
+/// there is no such subscriber left.
+#[tracing::instrument(skip_all, fields(subscriber_id = tracing::field::Empty))]
+async fn send_next_digest(
+    pool: &PgPool,
+    tenants: &Tenants,
+) -> Result<Option<Outcome>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // Issues published while they were paused aren't rolled up once the pause is over, like
+    // subscribers getting every issue don't get them either.
+    let Some(subscriber) = sqlx::query!(
+        r#"
+    SELECT id, tenant, email, locale, preferences_token, unsubscribed_lists, digest_attempts,
+      GREATEST(COALESCE(last_digest_at, subscribed_at), paused_until) AS "since!"
+    FROM subscriptions
+    WHERE status = 'confirmed' AND frequency = 'weekly_digest'
+      AND (paused_until IS NULL OR paused_until <= now())
+      AND COALESCE(last_digest_at, subscribed_at) <= now() - interval '7 days'
+      AND (next_digest_attempt_at IS NULL OR next_digest_attempt_at <= now())
+    ORDER BY COALESCE(last_digest_at, subscribed_at)
+    LIMIT 1
+    FOR UPDATE SKIP LOCKED
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for a subscriber due a digest")?
+    else {
+        return Ok(None);
+    };
+    tracing::Span::current().record("subscriber_id", tracing::field::display(subscriber.id));
+
+    let mut issues = sqlx::query_as!(
+        Issue,
+        r#"
+    SELECT title, text_content, html_content, segment FROM newsletter_issues
+    WHERE tenant = $1 AND published_at > $2
+      AND (list IS NULL OR NOT (list = ANY($3)))
+    ORDER BY published_at
+        "#,
+        subscriber.tenant,
+        subscriber.since,
+        &subscriber.unsubscribed_lists,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to collect the issues for a digest")?;
+    let mut in_segment = Vec::with_capacity(issues.len());
+    for issue in &issues {
+        in_segment.push(match &issue.segment {
+            Some(segment) => is_in_segment(&mut transaction, subscriber.id, segment).await?,
+            None => true,
+        });
+    }
+    let mut in_segment = in_segment.into_iter();
+    issues.retain(|_| in_segment.next().unwrap_or_default());
+
+    let outcome = if issues.is_empty() {
+        Outcome::Skipped
+    } else {
+        send(
+            tenants.get(&subscriber.tenant).as_deref(),
+            subscriber.email,
+            Locale::from(subscriber.locale.as_str()),
+            &subscriber.preferences_token,
+            &issues,
+        )
+        .await
+    };
+
+    record_outcome(
+        &mut transaction,
+        subscriber.id,
+        subscriber.digest_attempts + 1,
+        &outcome,
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(Some(outcome))
+}
+
+/// Email a subscriber the digest of `issues`, unless their tenant is gone or their address is
+/// invalid.
+async fn send(
+    tenant: Option<&Tenant>,
+    email: String,
+    locale: Locale,
+    preferences_token: &str,
+    issues: &[Issue],
+) -> Outcome {
+    match (tenant, SubscriberEmail::try_from(email)) {
+        (Some(tenant), Ok(email)) => {
+            let link = preferences_link(&tenant.base_url, preferences_token);
+            let (html, text) = compose(locale, &link, issues);
+            let subject = translate(locale, "digest-subject", &[]);
+            match tenant
+                .email_client
+                .send_email(&email, &subject, &html, &text)
+                .await
+            {
+                Ok(()) => Outcome::Sent,
+                Err(SendEmailError::Unavailable) => Outcome::Deferred,
+                Err(e) => {
+                    tracing::error!(error.cause_chain = ?e, "Failed to send a digest");
+                    Outcome::Failed
+                }
+            }
+        }
+        (None, _) => {
+            tracing::warn!("Skipping a digest for a tenant that is no longer configured");
+            Outcome::Skipped
+        }
+        (_, Err(error)) => {
+            tracing::warn!(
+                %error,
+                "Skipping a digest. Their stored contact details are invalid"
+            );
+            Outcome::Skipped
+        }
+    }
+}
+
+/// Whether the subscriber is in the segment an issue was published to.
+async fn is_in_segment(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+    segment: &str,
+) -> Result<bool, anyhow::Error> {
+    let segment = Segment::parse(segment).map_err(anyhow::Error::msg)?;
+    let mut query =
+        sqlx::QueryBuilder::new("SELECT EXISTS (SELECT 1 FROM subscriptions WHERE id = ");
+    query.push_bind(subscriber_id).push(" AND ");
+    segment.push_predicate(&mut query);
+    query.push(")");
+    query
+        .build_query_scalar()
+        .fetch_one(&mut **transaction)
+        .await
+        .context("Failed to check whether a subscriber is in a segment")
+}
+
+/// Record the `attempts`th attempt at the subscriber's digest.
+async fn record_outcome(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+    attempts: i32,
+    outcome: &Outcome,
+) -> Result<(), anyhow::Error> {
+    match outcome {
+        Outcome::Failed if attempts < MAX_ATTEMPTS => {
+            let delay = backoff(RETRY_DELAY, attempts);
+            retry_digest_later(transaction, subscriber_id, attempts, delay).await
+        }
+        Outcome::Deferred => {
+            retry_digest_later(transaction, subscriber_id, attempts - 1, RETRY_DELAY).await
+        }
+        Outcome::Failed => {
+            tracing::error!(attempts, "Giving up on this week's digest");
+            mark_digest_sent(transaction, subscriber_id).await
+        }
+        Outcome::Sent | Outcome::Skipped => mark_digest_sent(transaction, subscriber_id).await,
+    }
+}
+
+/// Even when nothing was sent, so the subscriber isn't looked at again for another week.
+async fn mark_digest_sent(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE subscriptions
+    SET last_digest_at = now(), digest_attempts = 0, next_digest_attempt_at = NULL
+    WHERE id = $1
+        "#,
+        subscriber_id,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the digest")?;
+    Ok(())
+}
+
+/// Record that `attempts` attempts at the subscriber's digest failed so far, trying again after
+/// `delay`.
+async fn retry_digest_later(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    subscriber_id: Uuid,
+    attempts: i32,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE subscriptions
+    SET digest_attempts = $2,
+      next_digest_attempt_at = now() + $3::float8 * interval '1 second'
+    WHERE id = $1
+        "#,
+        subscriber_id,
+        attempts,
+        delay.as_secs_f64(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the failed digest")?;
+    Ok(())
+}
+
+/// The digest template: an introduction, then every issue under its title, oldest first.
+fn compose(locale: Locale, preferences_link: &str, issues: &[Issue]) -> (String, String) {
+    let intro = translate(locale, "digest-intro", &[]);
+    let mut html = format!("<p>{intro}</p>");
+    let mut text = intro;
+    for issue in issues {
+        let _ = write!(
+            html,
+            "<hr /><h2>{}</h2>{}",
+            escape_html(&issue.title),
+            issue.html_content
+        );
+        let _ = write!(text, "\n\n== {} ==\n\n{}", issue.title, issue.text_content);
+    }
+
+    with_footer(
+        locale,
+        preferences_link,
+        Some(&translate(locale, "newsletter-footer", &[])),
+        &html,
+        &text,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compose, Issue};
+    use crate::i18n::Locale;
+
+    fn issue(title: &str) -> Issue {
+        Issue {
+            title: title.into(),
+            text_content: format!("{title} as text"),
+            html_content: format!("<p>{title} as HTML</p>"),
+            segment: None,
+        }
+    }
+
+    #[test]
+    fn issues_are_rolled_up_oldest_first_under_their_title() {
+        let (html, text) = compose(
+            Locale::En,
+            "https://example.com/preferences",
+            &[issue("First"), issue("Second & last")],
+        );
+
+        assert!(html.starts_with("<p>Here is what we published this week.</p>"));
+        assert!(html.contains("<h2>First</h2><p>First as HTML</p>"));
+        assert!(html.contains("<h2>Second &amp; last</h2>"));
+        assert!(html.find("First").unwrap() < html.find("Second").unwrap());
+        assert!(text.contains("== Second & last ==\n\nSecond & last as text"));
+        assert!(text.ends_with("https://example.com/preferences"));
+    }
+}
@@ -0,0 +1,59 @@
+/// How often a subscriber wants to hear from us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryFrequency {
+    /// Every issue, as soon as it is published.
+    EveryIssue,
+    /// A single email a week, summing up the issues published since the last one.
+    WeeklyDigest,
+}
+
+impl DeliveryFrequency {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryFrequency::EveryIssue => "every_issue",
+            DeliveryFrequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+}
+
+impl TryFrom<&str> for DeliveryFrequency {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "every_issue" => Ok(Self::EveryIssue),
+            "weekly_digest" => Ok(Self::WeeklyDigest),
+            other => Err(format!(
+                "{other} is not a valid frequency. Use either `every_issue` or `weekly_digest`."
+            )),
+        }
+    }
+}
+
+impl AsRef<str> for DeliveryFrequency {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryFrequency;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn frequencies_round_trip_through_their_string_form() {
+        for frequency in [
+            DeliveryFrequency::EveryIssue,
+            DeliveryFrequency::WeeklyDigest,
+        ] {
+            assert_ok_eq!(DeliveryFrequency::try_from(frequency.as_str()), frequency);
+        }
+    }
+
+    #[test]
+    fn unknown_frequencies_are_rejected() {
+        assert_err!(DeliveryFrequency::try_from("daily"));
+    }
+}
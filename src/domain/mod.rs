@@ -1,5 +1,10 @@
+pub mod delivery_frequency;
+pub mod subscriber_attributes;
 pub mod subscriber_email;
 pub mod subscriber_name;
+pub mod subscription_status;
+pub mod webhook_event_type;
+pub mod winner_metric;
 
 use self::{subscriber_email::SubscriberEmail, subscriber_name::SubscriberName};
 
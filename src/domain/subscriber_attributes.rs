@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use crate::i18n::Message;
+
+/// The type of the values an attribute takes.
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, utoipa::ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeType {
+    String,
+    Number,
+    Boolean,
+}
+
+impl AttributeType {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeType::String => "string",
+            AttributeType::Number => "number",
+            AttributeType::Boolean => "boolean",
+        }
+    }
+
+    #[must_use]
+    pub fn accepts(&self, value: &serde_json::Value) -> bool {
+        matches!(
+            (self, value),
+            (AttributeType::String, serde_json::Value::String(_))
+                | (AttributeType::Number, serde_json::Value::Number(_))
+                | (AttributeType::Boolean, serde_json::Value::Bool(_))
+        )
+    }
+}
+
+/// The attributes we collect about subscribers, by name: those declared by any of the lists.
+pub type AttributeSchema = BTreeMap<String, AttributeType>;
+
+/// Attribute names double as identifiers in segments, e.g. `plan`, `signup_year`.
+#[must_use]
+pub fn is_valid_attribute_name(name: &str) -> bool {
+    const MAXIMUM_LENGTH: usize = 64;
+
+    name.len() <= MAXIMUM_LENGTH
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// What a subscriber told us about themselves, checked against the [`AttributeSchema`].
+#[derive(Debug, Default)]
+pub struct SubscriberAttributes(serde_json::Map<String, serde_json::Value>);
+
+impl SubscriberAttributes {
+    pub fn parse(
+        attributes: serde_json::Map<String, serde_json::Value>,
+        schema: &AttributeSchema,
+    ) -> Result<Self, Message> {
+        for (name, value) in &attributes {
+            let Some(attribute_type) = schema.get(name) else {
+                return Err(Message::new("unknown-attribute").arg("name", name));
+            };
+            if !attribute_type.accepts(value) {
+                return Err(Message::new("invalid-attribute")
+                    .arg("name", name)
+                    .arg("type", attribute_type.as_str()));
+            }
+        }
+        Ok(Self(attributes))
+    }
+
+    #[must_use]
+    pub fn into_json(self) -> serde_json::Value {
+        serde_json::Value::Object(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_attribute_name, AttributeSchema, AttributeType, SubscriberAttributes};
+    use claims::{assert_err, assert_ok};
+
+    fn schema() -> AttributeSchema {
+        [
+            ("country".to_owned(), AttributeType::String),
+            ("seats".to_owned(), AttributeType::Number),
+            ("trial".to_owned(), AttributeType::Boolean),
+        ]
+        .into()
+    }
+
+    fn attributes(value: serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+        let serde_json::Value::Object(attributes) = value else {
+            panic!("{value} is not an object")
+        };
+        attributes
+    }
+
+    #[test]
+    fn declared_attributes_of_the_right_type_are_accepted() {
+        let parsed = assert_ok!(SubscriberAttributes::parse(
+            attributes(serde_json::json!({ "country": "US", "seats": 3, "trial": false })),
+            &schema(),
+        ));
+        assert_eq!(parsed.into_json()["seats"], 3);
+        assert_ok!(SubscriberAttributes::parse(
+            attributes(serde_json::json!({})),
+            &schema()
+        ));
+    }
+
+    #[test]
+    fn undeclared_attributes_are_rejected() {
+        assert_err!(SubscriberAttributes::parse(
+            attributes(serde_json::json!({ "plan": "pro" })),
+            &schema(),
+        ));
+    }
+
+    #[test]
+    fn values_of_the_wrong_type_are_rejected() {
+        for value in [
+            serde_json::json!({ "country": 1 }),
+            serde_json::json!({ "seats": "3" }),
+            serde_json::json!({ "trial": null }),
+            serde_json::json!({ "country": ["US"] }),
+        ] {
+            assert_err!(SubscriberAttributes::parse(attributes(value), &schema()));
+        }
+    }
+
+    #[test]
+    fn attribute_names_are_lowercase_identifiers() {
+        assert!(is_valid_attribute_name("signup_year"));
+        assert!(is_valid_attribute_name("_internal"));
+        assert!(!is_valid_attribute_name("Country"));
+        assert!(!is_valid_attribute_name("2fa"));
+        assert!(!is_valid_attribute_name("plan-name"));
+        assert!(!is_valid_attribute_name(""));
+    }
+}
@@ -1,41 +1,84 @@
 use validator::validate_email;
 
+use crate::i18n::Message;
+
+/// A valid email address, as the subscriber typed it.
+///
+/// Two addresses belong to the same subscriber when their [`canonical`](Self::canonical) forms
+/// are equal, whatever their case or the encoding of their domain.
 #[derive(Debug, Clone, Default)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    email: String,
+    canonical: String,
+}
+
+impl SubscriberEmail {
+    /// Lowercase, with an ASCII (punycode) domain: what we match subscribers on.
+    #[must_use]
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+
+    /// The canonical local part, before the `@`.
+    #[must_use]
+    pub fn local_part(&self) -> &str {
+        self.canonical
+            .rsplit_once('@')
+            .map_or("", |(local, _)| local)
+    }
+
+    /// The canonical domain, after the `@`.
+    #[must_use]
+    pub fn domain(&self) -> &str {
+        self.canonical
+            .rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
+    }
+}
+
+/// Lowercase the address and encode an internationalised domain as punycode.
+///
+/// The local part is lowercased too: it is case-sensitive in theory, but no mailbox provider
+/// we send to treats it that way.
+fn canonicalize(email: &str) -> Option<String> {
+    let (local, domain) = email.rsplit_once('@')?;
+    // Address literals such as `[127.0.0.1]` aren't hosts `url` can parse.
+    let domain =
+        url::Host::parse(domain).map_or_else(|_| domain.to_lowercase(), |host| host.to_string());
+    Some(format!("{}@{domain}", local.to_lowercase()))
+}
 
 impl TryFrom<&str> for SubscriberEmail {
-    type Error = String;
+    type Error = Message;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        if validate_email(value) {
-            Ok(Self(value.into()))
-        } else {
-            Err(format!("{value} is not a valid subscriber email."))
-        }
+        Self::try_from(value.to_owned())
     }
 }
 
 impl TryFrom<String> for SubscriberEmail {
-    type Error = String;
+    type Error = Message;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        if validate_email(&value) {
-            Ok(Self(value))
-        } else {
-            Err(format!("{value} is not a valid subscriber email."))
+        match canonicalize(&value) {
+            Some(canonical) if validate_email(&value) => Ok(Self {
+                email: value,
+                canonical,
+            }),
+            _ => Err(Message::new("invalid-email").arg("value", value)),
         }
     }
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.email
     }
 }
 
 impl std::fmt::Display for SubscriberEmail {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.0.fmt(f)
+        self.email.fmt(f)
     }
 }
 
@@ -63,6 +106,21 @@ mod tests {
         assert_err!(SubscriberEmail::try_from(email.as_str()));
     }
 
+    #[test]
+    fn case_is_ignored_in_the_canonical_form() {
+        let email = SubscriberEmail::try_from("Alice@Example.COM").unwrap();
+        assert_eq!(email.as_ref(), "Alice@Example.COM");
+        assert_eq!(email.canonical(), "alice@example.com");
+    }
+
+    #[test]
+    fn internationalised_domains_are_canonicalised_to_punycode() {
+        let unicode = SubscriberEmail::try_from("ursula@Bücher.example").unwrap();
+        let punycode = SubscriberEmail::try_from("ursula@xn--bcher-kva.example").unwrap();
+        assert_eq!(unicode.canonical(), "ursula@xn--bcher-kva.example");
+        assert_eq!(unicode.canonical(), punycode.canonical());
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmail(String);
 
@@ -1,10 +1,12 @@
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::i18n::Message;
+
 #[derive(Debug)]
 pub struct SubscriberName(String);
 
 impl TryFrom<&str> for SubscriberName {
-    type Error = String;
+    type Error = Message;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         const MAXIMUM_LENGTH: usize = 256;
@@ -16,7 +18,7 @@ impl TryFrom<&str> for SubscriberName {
             value.chars().any(|c| FORBIDDEN_CHARACTERS.contains(&c));
 
         if is_empty_or_whitespace || is_tool_long || contains_forbidden_characters {
-            Err(format!("{value} is not a valid subscriber name."))
+            Err(Message::new("invalid-name").arg("value", value))
         } else {
             Ok(Self(value.into()))
         }
@@ -0,0 +1,59 @@
+/// Where a subscriber stands in the double opt-in flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+}
+
+impl SubscriptionStatus {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+        }
+    }
+}
+
+impl TryFrom<&str> for SubscriptionStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "pending_confirmation" => Ok(Self::PendingConfirmation),
+            "confirmed" => Ok(Self::Confirmed),
+            other => Err(format!(
+                "{other} is not a valid subscription status. \
+                Use either `pending_confirmation` or `confirmed`."
+            )),
+        }
+    }
+}
+
+impl AsRef<str> for SubscriptionStatus {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn statuses_round_trip_through_their_string_form() {
+        for status in [
+            SubscriptionStatus::PendingConfirmation,
+            SubscriptionStatus::Confirmed,
+        ] {
+            assert_ok_eq!(SubscriptionStatus::try_from(status.as_str()), status);
+        }
+    }
+
+    #[test]
+    fn unknown_statuses_are_rejected() {
+        assert_err!(SubscriptionStatus::try_from("unsubscribed"));
+        assert_err!(SubscriptionStatus::try_from("Confirmed"));
+    }
+}
@@ -0,0 +1,74 @@
+/// What happened, as told to webhook endpoints subscribed to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEventType {
+    /// Someone subscribed, or was imported. They may still have to confirm.
+    SubscriberCreated,
+    SubscriberConfirmed,
+    /// A subscriber left, erasing their data.
+    SubscriberUnsubscribed,
+    IssuePublished,
+}
+
+impl WebhookEventType {
+    pub const ALL: [WebhookEventType; 4] = [
+        WebhookEventType::SubscriberCreated,
+        WebhookEventType::SubscriberConfirmed,
+        WebhookEventType::SubscriberUnsubscribed,
+        WebhookEventType::IssuePublished,
+    ];
+
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::SubscriberCreated => "subscriber.created",
+            WebhookEventType::SubscriberConfirmed => "subscriber.confirmed",
+            WebhookEventType::SubscriberUnsubscribed => "subscriber.unsubscribed",
+            WebhookEventType::IssuePublished => "issue.published",
+        }
+    }
+}
+
+impl TryFrom<&str> for WebhookEventType {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|event_type| event_type.as_str() == value)
+            .ok_or_else(|| {
+                let known: Vec<_> = Self::ALL
+                    .iter()
+                    .map(|e| format!("`{}`", e.as_str()))
+                    .collect();
+                format!(
+                    "{value} is not a valid event. Use one of {}.",
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+impl AsRef<str> for WebhookEventType {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WebhookEventType;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn event_types_round_trip_through_their_string_form() {
+        for event_type in WebhookEventType::ALL {
+            assert_ok_eq!(WebhookEventType::try_from(event_type.as_str()), event_type);
+        }
+    }
+
+    #[test]
+    fn unknown_event_types_are_rejected() {
+        assert_err!(WebhookEventType::try_from("subscriber.deleted"));
+        assert_err!(WebhookEventType::try_from("*"));
+    }
+}
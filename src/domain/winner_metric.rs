@@ -0,0 +1,56 @@
+/// How the winner of a subject line test is picked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WinnerMetric {
+    /// The share of the cohort who opened the issue.
+    OpenRate,
+    /// The share of the cohort who followed one of its links.
+    ClickRate,
+}
+
+impl WinnerMetric {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WinnerMetric::OpenRate => "open_rate",
+            WinnerMetric::ClickRate => "click_rate",
+        }
+    }
+}
+
+impl TryFrom<&str> for WinnerMetric {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "open_rate" => Ok(Self::OpenRate),
+            "click_rate" => Ok(Self::ClickRate),
+            other => Err(format!(
+                "{other} is not a valid metric. Use either `open_rate` or `click_rate`."
+            )),
+        }
+    }
+}
+
+impl AsRef<str> for WinnerMetric {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WinnerMetric;
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn metrics_round_trip_through_their_string_form() {
+        for metric in [WinnerMetric::OpenRate, WinnerMetric::ClickRate] {
+            assert_ok_eq!(WinnerMetric::try_from(metric.as_str()), metric);
+        }
+    }
+
+    #[test]
+    fn unknown_metrics_are_rejected() {
+        assert_err!(WinnerMetric::try_from("revenue"));
+    }
+}
@@ -1,9 +1,196 @@
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
 
-use reqwest::Client;
+use base64::Engine;
+use reqwest::{multipart, Client, RequestBuilder};
 use secrecy::{ExposeSecret, Secret};
 
-use crate::domain::subscriber_email::SubscriberEmail;
+use crate::{
+    circuit_breaker::{CircuitBreaker, CircuitState},
+    domain::subscriber_email::SubscriberEmail,
+    error::SendEmailError,
+    throttle::Throttle,
+};
+
+/// The content types attachments may have unless configured otherwise.
+pub const DEFAULT_ATTACHMENT_CONTENT_TYPES: [&str; 7] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "application/pdf",
+    "text/calendar",
+    "text/csv",
+    "text/plain",
+];
+
+/// Postmark refuses emails whose attachments weigh more than 10MB together.
+pub const DEFAULT_MAX_ATTACHMENTS_BYTES: usize = 10 * 1024 * 1024;
+
+/// Mailgun takes up to 1,000 recipients per request.
+const MAILGUN_MAX_BATCH_SIZE: usize = 1000;
+
+/// Postmark takes up to 500 messages per batch.
+const POSTMARK_MAX_BATCH_SIZE: usize = 500;
+
+/// The API the email client talks to at its base URL.
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailProvider {
+    /// Form posts, or multipart ones when there are attachments.
+    #[default]
+    Mailgun,
+    /// JSON, attachments included as base64.
+    Postmark,
+}
+
+impl EmailProvider {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmailProvider::Mailgun => "mailgun",
+            EmailProvider::Postmark => "postmark",
+        }
+    }
+}
+
+/// A file sent along with an email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub content: Vec<u8>,
+    /// Set for images shown within the HTML, where `<img src="cid:{content_id}">` refers to
+    /// them, rather than listed as attachments.
+    pub content_id: Option<String>,
+}
+
+impl Attachment {
+    #[must_use]
+    pub fn new(filename: &str, content_type: &str, content: Vec<u8>) -> Self {
+        Self {
+            filename: filename.to_owned(),
+            content_type: content_type.to_owned(),
+            content,
+            content_id: None,
+        }
+    }
+
+    /// An image for the HTML to refer to as `cid:{content_id}`.
+    #[must_use]
+    pub fn inline(content_id: &str, content_type: &str, content: Vec<u8>) -> Self {
+        Self {
+            filename: content_id.to_owned(),
+            content_type: content_type.to_owned(),
+            content,
+            content_id: Some(content_id.to_owned()),
+        }
+    }
+}
+
+/// One of the recipients of a batch, with the values their email gets in place of
+/// [`recipient_variable`]s.
+#[derive(Debug, Clone)]
+pub struct BatchRecipient {
+    pub email: SubscriberEmail,
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Where the recipient's value of `name` goes in the subject or content of a batch, e.g.
+/// `%recipient.preferences_link%`.
+#[must_use]
+pub fn recipient_variable(name: &str) -> String {
+    format!("%recipient.{name}%")
+}
+
+/// `template` with the variables of `recipient` filled in, as Mailgun does.
+fn fill_in(template: &str, recipient: &BatchRecipient) -> String {
+    recipient
+        .variables
+        .iter()
+        .fold(template.to_owned(), |filled, (name, value)| {
+            filled.replace(&recipient_variable(name), value)
+        })
+}
+
+/// What attachments an email may have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentLimits {
+    /// The most the attachments of an email may weigh together.
+    pub max_bytes: usize,
+    /// Lowercase, without parameters, e.g. `image/png`.
+    pub content_types: Vec<String>,
+}
+
+impl Default for AttachmentLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_ATTACHMENTS_BYTES,
+            content_types: DEFAULT_ATTACHMENT_CONTENT_TYPES
+                .iter()
+                .map(|&content_type| content_type.to_owned())
+                .collect(),
+        }
+    }
+}
+
+impl AttachmentLimits {
+    /// Why `attachments` can't be sent, if they can't.
+    pub fn check(&self, attachments: &[Attachment]) -> Result<(), String> {
+        for attachment in attachments {
+            if attachment.filename.trim().is_empty() || attachment.filename.contains(['/', '\\']) {
+                return Err(format!(
+                    "{:?} is not a valid attachment name.",
+                    attachment.filename
+                ));
+            }
+            let content_type = essence(&attachment.content_type);
+            if !self.content_types.contains(&content_type) {
+                return Err(format!(
+                    "{} can't be attached, {content_type} is not an allowed content type.",
+                    attachment.filename
+                ));
+            }
+            if attachment.content_id.is_some() && !content_type.starts_with("image/") {
+                return Err(format!(
+                    "{} can't be shown inline, only images can.",
+                    attachment.filename
+                ));
+            }
+        }
+        let size: usize = attachments.iter().map(|a| a.content.len()).sum();
+        if size > self.max_bytes {
+            return Err(format!(
+                "The attachments weigh {}KB, over the {}KB an email may carry.",
+                size.div_ceil(1024),
+                self.max_bytes / 1024
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `e` says the provider is down or overloaded, rather than that it turned down our
+/// request: it timed out or was unreachable, answered with a 5xx, or asked us to slow down.
+pub(crate) fn is_provider_failure(e: &reqwest::Error) -> bool {
+    match e.status() {
+        Some(status) => {
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        }
+        None => !e.is_builder() && !e.is_decode(),
+    }
+}
+
+/// `content_type` lowercased, without parameters such as `charset`.
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
 
 #[derive(serde::Serialize)]
 #[serde(rename_all = "PascalCase")]
@@ -13,6 +200,27 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<PostmarkAttachment<'a>>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkAttachment<'a> {
+    name: &'a str,
+    /// Base64-encoded.
+    content: String,
+    content_type: &'a str,
+    #[serde(rename = "ContentID", skip_serializing_if = "Option::is_none")]
+    content_id: Option<String>,
+}
+
+/// Postmark's verdict on one message of a batch.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PostmarkBatchResult {
+    error_code: i64,
+    message: String,
 }
 
 #[derive(Clone, Debug)]
@@ -21,6 +229,12 @@ pub struct EmailClient {
     http_client: Client,
     sender: SubscriberEmail,
     authorization_token: Secret<String>,
+    provider: EmailProvider,
+    attachment_limits: AttachmentLimits,
+    /// Sends wait for their turn here, when set.
+    throttle: Option<Arc<Throttle>>,
+    /// Sends fail fast while it is open, when set.
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
 }
 
 impl EmailClient {
@@ -37,6 +251,76 @@ impl EmailClient {
             http_client,
             sender,
             authorization_token,
+            provider: EmailProvider::default(),
+            attachment_limits: AttachmentLimits::default(),
+            throttle: None,
+            circuit_breaker: None,
+        }
+    }
+
+    #[must_use]
+    pub fn provider(mut self, provider: EmailProvider) -> Self {
+        self.provider = provider;
+        self
+    }
+
+    #[must_use]
+    pub fn attachment_limits(mut self, attachment_limits: AttachmentLimits) -> Self {
+        self.attachment_limits = attachment_limits;
+        self
+    }
+
+    #[must_use]
+    pub fn throttle(mut self, throttle: Arc<Throttle>) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    #[must_use]
+    pub fn circuit_breaker(mut self, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// The state of the circuit breaker, closed when there is none.
+    #[must_use]
+    pub fn circuit_state(&self) -> CircuitState {
+        self.circuit_breaker
+            .as_ref()
+            .map_or(CircuitState::Closed, |breaker| breaker.state())
+    }
+
+    /// Run `send` to `recipients` once it is their turn, unless the circuit breaker is open,
+    /// recording whether the provider failed it.
+    ///
+    /// The wait comes first, so a half-open breaker's single trial isn't held up by it.
+    async fn guarded<T>(
+        &self,
+        recipients: &[&SubscriberEmail],
+        send: impl std::future::Future<Output = Result<T, reqwest::Error>>,
+    ) -> Result<T, SendEmailError> {
+        self.wait_for_turn(recipients).await;
+        self.through_breaker(send).await
+    }
+
+    /// Run `send` unless the circuit breaker is open, recording whether the provider failed it.
+    async fn through_breaker<T>(
+        &self,
+        send: impl std::future::Future<Output = Result<T, reqwest::Error>>,
+    ) -> Result<T, SendEmailError> {
+        let Some(breaker) = &self.circuit_breaker else {
+            return Ok(send.await?);
+        };
+        let attempt = breaker.attempt().ok_or(SendEmailError::Unavailable)?;
+        let outcome = send.await;
+        attempt.record(!matches!(&outcome, Err(e) if is_provider_failure(e)));
+        Ok(outcome?)
+    }
+
+    /// Wait for our turn to send an email to each of `recipients`, in a single request.
+    pub async fn wait_for_turn(&self, recipients: &[&SubscriberEmail]) {
+        if let Some(throttle) = &self.throttle {
+            throttle.wait(self.provider, recipients).await;
         }
     }
 
@@ -46,41 +330,291 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        self.guarded(
+            &[recipient],
+            self.send(recipient, subject, html_content, text_content, &[]),
+        )
+        .await
+    }
+
+    /// Send an email along with files, and images its HTML refers to by content id, once they
+    /// were checked against our [`AttachmentLimits`].
+    pub async fn send_email_with_attachments(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        attachments: &[Attachment],
+    ) -> Result<(), SendEmailError> {
+        self.attachment_limits
+            .check(attachments)
+            .map_err(SendEmailError::InvalidAttachment)?;
+        self.guarded(
+            &[recipient],
+            self.send(recipient, subject, html_content, text_content, attachments),
+        )
+        .await
+    }
+
+    async fn send(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        attachments: &[Attachment],
     ) -> Result<(), reqwest::Error> {
+        let request = match self.provider {
+            EmailProvider::Mailgun => {
+                self.mailgun_request(recipient, subject, html_content, text_content, attachments)?
+            }
+            EmailProvider::Postmark => {
+                self.postmark_request(recipient, subject, html_content, text_content, attachments)
+            }
+        };
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+
+    /// How many recipients [`EmailClient::send_batch`] takes at once.
+    #[must_use]
+    pub fn max_batch_size(&self) -> usize {
+        match self.provider {
+            EmailProvider::Mailgun => MAILGUN_MAX_BATCH_SIZE,
+            EmailProvider::Postmark => POSTMARK_MAX_BATCH_SIZE,
+        }
+    }
+
+    /// Which of `recipients`, by index, to send the next batch to: up to
+    /// [`EmailClient::max_batch_size`] of them, and no more than the throttle lets out at once.
+    #[must_use]
+    pub fn next_batch(&self, recipients: &[&SubscriberEmail]) -> Vec<usize> {
+        let recipients = &recipients[..recipients.len().min(self.max_batch_size())];
+        self.throttle.as_ref().map_or_else(
+            || (0..recipients.len()).collect(),
+            |throttle| throttle.at_once(recipients),
+        )
+    }
+
+    /// Send an email to each of up to [`EmailClient::max_batch_size`] recipients, in a single
+    /// request, filling in their [`recipient_variable`]s. Returns, in the order of `recipients`,
+    /// why the email API turned down each recipient it turned down.
+    ///
+    /// Unlike other sends, this doesn't wait for its turn: callers pick the recipients with
+    /// [`EmailClient::next_batch`] and wait with [`EmailClient::wait_for_turn`] first, so they
+    /// needn't hold anything while they wait.
+    pub async fn send_batch(
+        &self,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        recipients: &[BatchRecipient],
+    ) -> Result<Vec<Result<(), String>>, SendEmailError> {
+        self.through_breaker(async {
+            match self.provider {
+                EmailProvider::Mailgun => {
+                    self.send_mailgun_batch(subject, html_content, text_content, recipients)
+                        .await
+                }
+                EmailProvider::Postmark => {
+                    self.send_postmark_batch(subject, html_content, text_content, recipients)
+                        .await
+                }
+            }
+        })
+        .await
+    }
+
+    /// Mailgun fills in the variables itself, and sends every recipient their own email.
+    async fn send_mailgun_batch(
+        &self,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        recipients: &[BatchRecipient],
+    ) -> Result<Vec<Result<(), String>>, reqwest::Error> {
+        let to = recipients
+            .iter()
+            .map(|recipient| recipient.email.as_ref())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let variables: HashMap<_, _> = recipients
+            .iter()
+            .map(|recipient| (recipient.email.as_ref(), &recipient.variables))
+            .collect();
+        let variables = serde_json::to_string(&variables).unwrap_or_default();
+
         let mut hmap = HashMap::new();
-        hmap.insert("to", recipient.as_ref());
+        hmap.insert("to", to.as_str());
         hmap.insert("from", self.sender.as_ref());
         hmap.insert("subject", subject);
-        hmap.insert("html", text_content);
-        hmap.insert("text", html_content);
-
-        let url = format!(
-            "{}/sandboxccf80f2f71824c318e2e1554ec96f417.mailgun.org/messages",
-            self.base_url
-        );
+        hmap.insert("html", html_content);
+        hmap.insert("text", text_content);
+        hmap.insert("recipient-variables", &variables);
 
         self.http_client
-            .post(url)
+            .post(self.mailgun_url())
             .basic_auth("api", Some(self.authorization_token.expose_secret()))
             .form(&hmap)
             .send()
             .await?
             .error_for_status()?;
 
-        Ok(())
+        Ok(vec![Ok(()); recipients.len()])
+    }
+
+    async fn send_postmark_batch(
+        &self,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        recipients: &[BatchRecipient],
+    ) -> Result<Vec<Result<(), String>>, reqwest::Error> {
+        let filled_in: Vec<_> = recipients
+            .iter()
+            .map(|recipient| {
+                (
+                    fill_in(subject, recipient),
+                    fill_in(html_content, recipient),
+                    fill_in(text_content, recipient),
+                )
+            })
+            .collect();
+        let body: Vec<_> = recipients
+            .iter()
+            .zip(&filled_in)
+            .map(|(recipient, (subject, html, text))| SendEmailRequest {
+                from: self.sender.as_ref(),
+                to: recipient.email.as_ref(),
+                subject,
+                html_body: html,
+                text_body: text,
+                attachments: vec![],
+            })
+            .collect();
+
+        let results: Vec<PostmarkBatchResult> = self
+            .http_client
+            .post(format!("{}/email/batch", self.base_url))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok((0..recipients.len())
+            .map(|i| match results.get(i) {
+                Some(result) if result.error_code == 0 => Ok(()),
+                Some(result) => Err(format!("{} ({})", result.message, result.error_code)),
+                None => Err("Postmark didn't say whether it was sent".to_owned()),
+            })
+            .collect())
+    }
+
+    fn mailgun_url(&self) -> String {
+        format!(
+            "{}/sandboxccf80f2f71824c318e2e1554ec96f417.mailgun.org/messages",
+            self.base_url
+        )
+    }
+
+    fn mailgun_request(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        attachments: &[Attachment],
+    ) -> Result<RequestBuilder, reqwest::Error> {
+        let mut hmap = HashMap::new();
+        hmap.insert("to", recipient.as_ref());
+        hmap.insert("from", self.sender.as_ref());
+        hmap.insert("subject", subject);
+        hmap.insert("html", html_content);
+        hmap.insert("text", text_content);
+
+        let request = self
+            .http_client
+            .post(self.mailgun_url())
+            .basic_auth("api", Some(self.authorization_token.expose_secret()));
+        if attachments.is_empty() {
+            return Ok(request.form(&hmap));
+        }
+
+        let mut form = multipart::Form::new();
+        for (name, value) in hmap {
+            form = form.text(name, value.to_owned());
+        }
+        for attachment in attachments {
+            // Mailgun uses the name of inline files as their content id.
+            let part = multipart::Part::bytes(attachment.content.clone())
+                .file_name(attachment.filename.clone())
+                .mime_str(&attachment.content_type)?;
+            let name = match attachment.content_id {
+                Some(_) => "inline",
+                None => "attachment",
+            };
+            form = form.part(name, part);
+        }
+        Ok(request.multipart(form))
+    }
+
+    fn postmark_request(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+        attachments: &[Attachment],
+    ) -> RequestBuilder {
+        let body = SendEmailRequest {
+            from: self.sender.as_ref(),
+            to: recipient.as_ref(),
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            attachments: attachments
+                .iter()
+                .map(|attachment| PostmarkAttachment {
+                    name: &attachment.filename,
+                    content: base64::engine::general_purpose::STANDARD.encode(&attachment.content),
+                    content_type: &attachment.content_type,
+                    content_id: attachment.content_id.as_ref().map(|id| format!("cid:{id}")),
+                })
+                .collect(),
+        };
+        self.http_client
+            .post(format!("{}/email", self.base_url))
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&body)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::domain::subscriber_email::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{
+        recipient_variable, Attachment, AttachmentLimits, BatchRecipient, EmailClient,
+        EmailProvider,
+    };
+    use crate::error::SendEmailError;
     use claims::{assert_err, assert_ok};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use secrecy::Secret;
-    use wiremock::matchers::any;
+    use wiremock::matchers::{any, header, header_exists, method, path};
     use wiremock::{Mock, MockServer, Request, ResponseTemplate};
 
     struct SendEmailBodyMatcher;
@@ -125,6 +659,25 @@ mod tests {
         )
     }
 
+    /// Recipients whose `name` variable is their address.
+    fn batch(emails: &[&str]) -> Vec<BatchRecipient> {
+        emails
+            .iter()
+            .map(|&email| BatchRecipient {
+                email: SubscriberEmail::try_from(email.to_owned()).unwrap(),
+                variables: [("name".to_owned(), email.to_owned())].into(),
+            })
+            .collect()
+    }
+
+    /// A PDF and an inline image.
+    fn attachments() -> Vec<Attachment> {
+        vec![
+            Attachment::new("report.pdf", "application/pdf", b"%PDF-1.4".to_vec()),
+            Attachment::inline("logo", "image/png", vec![0x89, b'P', b'N', b'G']),
+        ]
+    }
+
     #[tokio::test]
     async fn send_email_succeeds_if_the_server_returns_200() {
         // Arrange
@@ -169,4 +722,275 @@ mod tests {
         // Assert
         assert_err!(outcome);
     }
+
+    #[tokio::test]
+    async fn send_email_sends_the_html_and_plain_text_in_their_own_fields_to_mailgun() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), "<p>As HTML</p>", "As plain text")
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_urlencoded::from_bytes(&request.body).unwrap();
+        assert_eq!(body["html"], "<p>As HTML</p>");
+        assert_eq!(body["text"], "As plain text");
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request_to_postmark() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).provider(EmailProvider::Postmark);
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn attachments_are_sent_as_multipart_to_mailgun() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_attachments(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &attachments(),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let content_type = request.headers.get(&"Content-Type".into()).unwrap();
+        assert!(content_type.as_str().starts_with("multipart/form-data"));
+        let body = String::from_utf8_lossy(&request.body);
+        assert!(body.contains(r#"name="subject""#));
+        assert!(body.contains(r#"name="attachment"; filename="report.pdf""#));
+        assert!(body.contains("Content-Type: application/pdf"));
+        assert!(body.contains("%PDF-1.4"));
+        assert!(body.contains(r#"name="inline"; filename="logo""#));
+    }
+
+    #[tokio::test]
+    async fn attachments_are_sent_as_base64_to_postmark() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).provider(EmailProvider::Postmark);
+
+        Mock::given(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email_with_attachments(
+                &email(),
+                &subject(),
+                &content(),
+                &content(),
+                &attachments(),
+            )
+            .await;
+
+        // Assert
+        assert_ok!(outcome);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            body["Attachments"],
+            serde_json::json!([
+                { "Name": "report.pdf", "Content": "JVBERi0xLjQ=", "ContentType": "application/pdf" },
+                { "Name": "logo", "Content": "iVBORw==", "ContentType": "image/png", "ContentID": "cid:logo" },
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn attachments_outside_the_limits_are_not_sent() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).attachment_limits(AttachmentLimits {
+            max_bytes: 1024,
+            content_types: vec!["image/png".into()],
+        });
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+        let test_cases = [
+            (
+                Attachment::new("report.pdf", "application/pdf", vec![0; 10]),
+                "a content type that isn't allowed",
+            ),
+            (
+                Attachment::new("huge.png", "image/png", vec![0; 1025]),
+                "too many bytes",
+            ),
+        ];
+
+        for (attachment, description) in test_cases {
+            // Act
+            let outcome = email_client
+                .send_email_with_attachments(
+                    &email(),
+                    &subject(),
+                    &content(),
+                    &content(),
+                    &[attachment],
+                )
+                .await;
+
+            // Assert
+            assert!(
+                matches!(assert_err!(outcome), SendEmailError::InvalidAttachment(_)),
+                "The attachment was not rejected when it had {description}."
+            );
+        }
+    }
+
+    #[test]
+    fn only_well_named_images_can_be_shown_inline() {
+        let limits = AttachmentLimits::default();
+        assert_ok!(limits.check(&attachments()));
+        assert_ok!(limits.check(&[Attachment::new(
+            "notes.txt",
+            "Text/Plain; charset=utf-8",
+            b"Notes".to_vec()
+        )]));
+        assert_err!(limits.check(&[Attachment::inline(
+            "report",
+            "application/pdf",
+            b"%PDF-1.4".to_vec()
+        )]));
+        assert_err!(limits.check(&[Attachment::new(
+            "../report.pdf",
+            "application/pdf",
+            b"%PDF-1.4".to_vec()
+        )]));
+    }
+
+    #[tokio::test]
+    async fn batches_are_sent_to_mailgun_in_one_request_with_recipient_variables() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let greeting = format!("Hello {}", recipient_variable("name"));
+
+        // Act
+        let outcome = email_client
+            .send_batch(
+                &subject(),
+                &format!("<p>{greeting}</p>"),
+                &greeting,
+                &batch(&["ursula@example.com", "octavia@example.com"]),
+            )
+            .await;
+
+        // Assert
+        assert_eq!(assert_ok!(outcome), vec![Ok(()), Ok(())]);
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_urlencoded::from_bytes(&request.body).unwrap();
+        assert_eq!(body["to"], "ursula@example.com, octavia@example.com");
+        assert_eq!(body["html"], "<p>Hello %recipient.name%</p>");
+        assert_eq!(body["text"], "Hello %recipient.name%");
+        let variables: serde_json::Value =
+            serde_json::from_str(body["recipient-variables"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            variables,
+            serde_json::json!({
+                "ursula@example.com": { "name": "ursula@example.com" },
+                "octavia@example.com": { "name": "octavia@example.com" },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn batches_sent_to_postmark_are_filled_in_and_report_each_recipient() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri()).provider(EmailProvider::Postmark);
+
+        Mock::given(path("/email/batch"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "ErrorCode": 0, "Message": "OK", "To": "ursula@example.com" },
+                { "ErrorCode": 406, "Message": "Inactive recipient", "To": "octavia@example.com" },
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let greeting = format!("Hello {}", recipient_variable("name"));
+
+        // Act
+        let outcome = email_client
+            .send_batch(
+                &greeting,
+                &greeting,
+                &content(),
+                &batch(&["ursula@example.com", "octavia@example.com"]),
+            )
+            .await;
+
+        // Assert
+        let results = assert_ok!(outcome);
+        assert_ok!(&results[0]);
+        assert_eq!(
+            results[1],
+            Err("Inactive recipient (406)".to_owned()),
+            "The recipient Postmark turned down wasn't reported"
+        );
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body[0]["To"], "ursula@example.com");
+        assert_eq!(body[0]["Subject"], "Hello ursula@example.com");
+        assert_eq!(body[1]["HtmlBody"], "Hello octavia@example.com");
+    }
 }
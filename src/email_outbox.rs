@@ -0,0 +1,229 @@
+//! Emails that couldn't be sent as the email provider was down, to be sent once it is back up.
+//!
+//! [`send_or_defer`] sends an email right away, and stores it in the outbox instead when its
+//! tenant's circuit breaker is open or the provider failed. Every instance runs
+//! [`deliver_on_schedule`], which sends due emails and retries failed ones with exponential
+//! backoff, until [`MAX_ATTEMPTS`]. Emails are deleted once sent or given up on.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::subscriber_email::SubscriberEmail,
+    error::SendEmailError,
+    tenant::{Tenant, Tenants},
+    webhooks::backoff,
+};
+
+/// Emails are given up on after this many attempts, about an hour with the default retry delay.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// Send an email through `tenant`'s email client, or defer it to the outbox when the provider
+/// is down. Errors when the provider turned the email down, or it couldn't be deferred.
+#[tracing::instrument(
+    name = "Send an email or defer it to the outbox",
+    skip(pool, tenant, subject, html_content, text_content),
+    fields(tenant = %tenant.slug)
+)]
+pub async fn send_or_defer(
+    pool: &PgPool,
+    tenant: &Tenant,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<(), anyhow::Error> {
+    match tenant
+        .email_client
+        .send_email(recipient, subject, html_content, text_content)
+        .await
+    {
+        Ok(()) => Ok(()),
+        Err(e) if e.is_transient() => {
+            tracing::warn!(
+                error.cause_chain = ?e,
+                "The email provider is down, deferring the email to the outbox"
+            );
+            sqlx::query!(
+                r#"
+    INSERT INTO email_outbox (id, tenant, recipient, subject, html_content, text_content, last_error)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+                Uuid::new_v4(),
+                tenant.slug,
+                recipient.as_ref(),
+                subject,
+                html_content,
+                text_content,
+                e.to_string(),
+            )
+            .execute(pool)
+            .await
+            .context("Failed to defer the email to the outbox")?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Drop the emails waiting to be sent to `recipient`, e.g. as they erased their data.
+#[tracing::instrument(name = "Drop deferred emails to a recipient", skip_all)]
+pub async fn discard(
+    executor: impl sqlx::PgExecutor<'_>,
+    tenant: &str,
+    recipient: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM email_outbox WHERE tenant = $1 AND lower(recipient) = lower($2)",
+        tenant,
+        recipient,
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Look for due emails every `interval`, sending them. Never returns.
+pub async fn deliver_on_schedule(
+    pool: PgPool,
+    tenants: Tenants,
+    interval: Duration,
+    retry_delay: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match deliver_due(&pool, &tenants, retry_delay).await {
+            Ok(0) => {}
+            Ok(sent) => tracing::info!(sent, "Sent deferred emails"),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to send deferred emails, retrying on the next check"
+            ),
+        }
+    }
+}
+
+/// Attempt every email that is due, returning how many were sent.
+#[tracing::instrument(name = "Send due deferred emails", skip_all)]
+async fn deliver_due(
+    pool: &PgPool,
+    tenants: &Tenants,
+    retry_delay: Duration,
+) -> Result<usize, anyhow::Error> {
+    let mut sent = 0;
+    while let Some(succeeded) = deliver_next(pool, tenants, retry_delay).await? {
+        if succeeded {
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Attempt one due email, `None` if there is no such email left.
+#[tracing::instrument(skip_all, fields(email_id = tracing::field::Empty))]
+async fn deliver_next(
+    pool: &PgPool,
+    tenants: &Tenants,
+    retry_delay: Duration,
+) -> Result<Option<bool>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let Some(email) = sqlx::query!(
+        r#"
+    SELECT id, tenant, recipient, subject, html_content, text_content, attempts
+    FROM email_outbox
+    WHERE next_attempt_at <= now()
+    ORDER BY next_attempt_at
+    LIMIT 1
+    FOR UPDATE SKIP LOCKED
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for a due deferred email")?
+    else {
+        return Ok(None);
+    };
+    tracing::Span::current().record("email_id", tracing::field::display(email.id));
+
+    // `None` when the email was dropped rather than sent.
+    let outcome = match (
+        tenants.get(&email.tenant),
+        SubscriberEmail::try_from(email.recipient),
+    ) {
+        (Some(tenant), Ok(recipient)) => Some(
+            tenant
+                .email_client
+                .send_email(
+                    &recipient,
+                    &email.subject,
+                    &email.html_content,
+                    &email.text_content,
+                )
+                .await,
+        ),
+        (None, _) => {
+            tracing::warn!(
+                tenant = email.tenant,
+                "Dropping a deferred email of a tenant that is no longer configured"
+            );
+            None
+        }
+        (_, Err(error)) => {
+            tracing::warn!(%error, "Dropping a deferred email to an invalid address");
+            None
+        }
+    };
+
+    let attempts = email.attempts + 1;
+    let retry = match &outcome {
+        None | Some(Ok(())) => None,
+        // Nothing was tried: wait for the circuit breaker to let sends through again.
+        Some(Err(e @ SendEmailError::Unavailable)) => Some((email.attempts, retry_delay, e)),
+        Some(Err(e)) if e.is_transient() && attempts < MAX_ATTEMPTS => {
+            tracing::warn!(attempts, error.cause_chain = ?e, "Failed to send a deferred email");
+            Some((attempts, backoff(retry_delay, attempts), e))
+        }
+        Some(Err(e)) => {
+            tracing::error!(
+                attempts,
+                error.cause_chain = ?e,
+                "Giving up on a deferred email"
+            );
+            None
+        }
+    };
+    match retry {
+        Some((attempts, delay, error)) => sqlx::query!(
+            r#"
+    UPDATE email_outbox
+    SET attempts = $2, last_error = $3, next_attempt_at = now() + $4::float8 * interval '1 second'
+    WHERE id = $1
+            "#,
+            email.id,
+            attempts,
+            error.to_string(),
+            delay.as_secs_f64(),
+        )
+        .execute(&mut *transaction)
+        .await
+        .context("Failed to reschedule the deferred email")?,
+        None => sqlx::query!("DELETE FROM email_outbox WHERE id = $1", email.id)
+            .execute(&mut *transaction)
+            .await
+            .context("Failed to remove the deferred email from the outbox")?,
+    };
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(Some(matches!(outcome, Some(Ok(())))))
+}
@@ -0,0 +1,296 @@
+//! Which email addresses we accept signups from.
+//!
+//! In order of precedence:
+//! - role accounts such as `noreply@` or `postmaster@` are always rejected, nobody reads them;
+//! - the tenant's own rules, managed through `/admin/email_domains`, allow or block a domain
+//!   and its subdomains;
+//! - throwaway mailbox providers are rejected, from a bundled list or from
+//!   `application.disposable_domains_file`, which is reloaded when it changes.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::SystemTime,
+};
+
+use sqlx::PgPool;
+
+use crate::{domain::subscriber_email::SubscriberEmail, i18n::Message};
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// How often to look for an updated disposable domain list.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Local parts of shared or unattended mailboxes.
+const ROLE_ACCOUNTS: &[&str] = &[
+    "abuse",
+    "do-not-reply",
+    "donotreply",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "webmaster",
+];
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum PolicyViolation {
+    #[error("{0} is a role account, please subscribe with a personal address.")]
+    RoleAccount(String),
+    #[error("We don't accept subscriptions from {0} addresses.")]
+    BlockedDomain(String),
+    #[error("{0} is a disposable email provider, please subscribe with a permanent address.")]
+    DisposableDomain(String),
+}
+
+impl PolicyViolation {
+    #[must_use]
+    pub fn message(&self) -> Message {
+        match self {
+            PolicyViolation::RoleAccount(email) => Message::new("role-account").arg("email", email),
+            PolicyViolation::BlockedDomain(domain) => {
+                Message::new("blocked-domain").arg("domain", domain)
+            }
+            PolicyViolation::DisposableDomain(domain) => {
+                Message::new("disposable-domain").arg("domain", domain)
+            }
+        }
+    }
+}
+
+/// A tenant's decision about a domain and its subdomains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DomainRule {
+    Allow,
+    Block,
+}
+
+impl DomainRule {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DomainRule::Allow => "allow",
+            DomainRule::Block => "block",
+        }
+    }
+}
+
+impl TryFrom<&str> for DomainRule {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "allow" => Ok(DomainRule::Allow),
+            "block" => Ok(DomainRule::Block),
+            other => Err(format!(
+                "{other} is not a domain rule. Use either `allow` or `block`."
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct EmailPolicy {
+    disposable_domains: RwLock<Arc<HashSet<String>>>,
+    /// Read instead of the bundled list when set.
+    disposable_domains_file: Option<PathBuf>,
+}
+
+impl EmailPolicy {
+    pub fn new(disposable_domains_file: Option<PathBuf>) -> io::Result<Self> {
+        let disposable_domains = match &disposable_domains_file {
+            Some(path) => parse_domain_list(&std::fs::read_to_string(path)?),
+            None => parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS),
+        };
+        Ok(Self {
+            disposable_domains: RwLock::new(Arc::new(disposable_domains)),
+            disposable_domains_file,
+        })
+    }
+
+    /// Check `email` against the policy of `tenant`.
+    #[tracing::instrument(name = "Check the email policy", skip(self, pool))]
+    pub async fn check(
+        &self,
+        pool: &PgPool,
+        tenant: &str,
+        email: &SubscriberEmail,
+    ) -> Result<Result<(), PolicyViolation>, sqlx::Error> {
+        let domains: Vec<String> = parent_domains(email.domain()).map(str::to_owned).collect();
+        // The most specific rule wins, so `allow` on `team.example.com` beats `block` on
+        // `example.com`.
+        let rule = sqlx::query!(
+            r#"
+    SELECT rule FROM email_domain_rules
+    WHERE tenant = $1 AND domain = ANY($2)
+    ORDER BY length(domain) DESC
+    LIMIT 1
+            "#,
+            tenant,
+            &domains,
+        )
+        .fetch_optional(pool)
+        .await?
+        .and_then(|r| DomainRule::try_from(r.rule.as_str()).ok());
+
+        let disposable_domains = self.disposable_domains.read().unwrap().clone();
+        Ok(evaluate(email, rule, &disposable_domains))
+    }
+
+    /// Check each of `emails` against the policy of `tenant`, reading the tenant's rules once
+    /// rather than once per email, e.g. for an import.
+    #[tracing::instrument(name = "Check the email policy of many emails", skip_all)]
+    pub async fn check_all<'a>(
+        &self,
+        pool: &PgPool,
+        tenant: &str,
+        emails: impl IntoIterator<Item = &'a SubscriberEmail>,
+    ) -> Result<Vec<Result<(), PolicyViolation>>, sqlx::Error> {
+        let rules: HashMap<String, DomainRule> = sqlx::query!(
+            "SELECT domain, rule FROM email_domain_rules WHERE tenant = $1",
+            tenant,
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .filter_map(|r| Some((r.domain, DomainRule::try_from(r.rule.as_str()).ok()?)))
+        .collect();
+
+        let disposable_domains = self.disposable_domains.read().unwrap().clone();
+        Ok(emails
+            .into_iter()
+            .map(|email| {
+                // The most specific rule wins, as in `check`.
+                let rule = parent_domains(email.domain()).find_map(|d| rules.get(d).copied());
+                evaluate(email, rule, &disposable_domains)
+            })
+            .collect())
+    }
+
+    /// Reload the disposable domain list whenever its file changes. Never returns.
+    ///
+    /// A file that can't be read is logged and the previous list is kept.
+    pub async fn reload_on_change(self: Arc<Self>) {
+        let Some(path) = self.disposable_domains_file.clone() else {
+            return;
+        };
+        let modified = || async {
+            tokio::fs::metadata(&path)
+                .await
+                .ok()
+                .and_then(|metadata| metadata.modified().ok())
+        };
+
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut loaded: Option<SystemTime> = modified().await;
+        loop {
+            interval.tick().await;
+            let current = modified().await;
+            if current == loaded {
+                continue;
+            }
+
+            match tokio::fs::read_to_string(&path).await {
+                Ok(contents) => {
+                    let domains = parse_domain_list(&contents);
+                    tracing::info!(count = domains.len(), "Reloaded the disposable domain list");
+                    *self.disposable_domains.write().unwrap() = Arc::new(domains);
+                    loaded = current;
+                }
+                Err(e) => tracing::error!(
+                    error.cause_chain = ?e,
+                    "Failed to reload the disposable domain list, keeping the previous one"
+                ),
+            }
+        }
+    }
+}
+
+fn evaluate(
+    email: &SubscriberEmail,
+    rule: Option<DomainRule>,
+    disposable_domains: &HashSet<String>,
+) -> Result<(), PolicyViolation> {
+    // Tags don't make a role account personal: `noreply+news@` is still `noreply@`.
+    let mailbox = email
+        .local_part()
+        .split_once('+')
+        .map_or(email.local_part(), |(mailbox, _tag)| mailbox);
+    if ROLE_ACCOUNTS.contains(&mailbox) {
+        return Err(PolicyViolation::RoleAccount(email.to_string()));
+    }
+
+    let disposable = || parent_domains(email.domain()).any(|d| disposable_domains.contains(d));
+    match rule {
+        Some(DomainRule::Block) => Err(PolicyViolation::BlockedDomain(email.domain().into())),
+        None if disposable() => Err(PolicyViolation::DisposableDomain(email.domain().into())),
+        Some(DomainRule::Allow) | None => Ok(()),
+    }
+}
+
+/// `mail.example.com`, then `example.com`, then `com`.
+fn parent_domains(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(domain), |domain| {
+        domain.split_once('.').map(|(_, parent)| parent)
+    })
+}
+
+/// One domain per line, `#` starting a comment.
+fn parse_domain_list(contents: &str) -> HashSet<String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, parse_domain_list, DomainRule, PolicyViolation};
+    use crate::domain::subscriber_email::SubscriberEmail;
+    use claims::{assert_err, assert_ok};
+
+    fn check(email: &str, rule: Option<DomainRule>) -> Result<(), PolicyViolation> {
+        let disposable = parse_domain_list("# comment\nmailinator.com\nYOPmail.com # trailing\n");
+        evaluate(
+            &SubscriberEmail::try_from(email).unwrap(),
+            rule,
+            &disposable,
+        )
+    }
+
+    #[test]
+    fn disposable_domains_and_their_subdomains_are_rejected() {
+        for email in ["ursula@mailinator.com", "ursula@eu.YOPMAIL.com"] {
+            assert!(
+                matches!(
+                    check(email, None),
+                    Err(PolicyViolation::DisposableDomain(_))
+                ),
+                "{email}"
+            );
+        }
+        assert_ok!(check("ursula@notmailinator.com", None));
+    }
+
+    #[test]
+    fn role_accounts_are_rejected_even_on_allowed_domains() {
+        for email in ["noreply@example.com", "PostMaster+news@example.com"] {
+            assert_eq!(
+                check(email, Some(DomainRule::Allow)),
+                Err(PolicyViolation::RoleAccount(email.into())),
+            );
+        }
+    }
+
+    #[test]
+    fn tenant_rules_override_the_disposable_list() {
+        assert_ok!(check("ursula@mailinator.com", Some(DomainRule::Allow)));
+        assert_err!(check("ursula@example.com", Some(DomainRule::Block)));
+    }
+}
@@ -1,8 +1,19 @@
 #![allow(clippy::module_name_repetitions)]
 
-use axum::response::IntoResponse;
+use axum::{
+    extract::rejection::JsonRejection,
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    Json,
+};
 use hyper::http;
 
+use crate::{
+    configuration::ConfigurationError,
+    email_policy::PolicyViolation,
+    i18n::{Locale, Message},
+};
+
 pub fn error_chain_fmt(
     e: &impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
@@ -18,16 +29,103 @@ pub fn error_chain_fmt(
     Ok(())
 }
 
+// ===================================== Content Negotiation ===================================== //
+
+/// The representation a client asked for through its `Accept` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResponseFormat {
+    Json,
+    PlainText,
+}
+
+impl ResponseFormat {
+    /// Pick a representation from the `Accept` header, falling back to `default`
+    /// when the client didn't express a preference we can serve.
+    #[must_use]
+    pub fn negotiate(headers: &HeaderMap, default: Self) -> Self {
+        let Some(accept) = headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return default;
+        };
+
+        let accepts = |mime: &str| {
+            accept
+                .split(',')
+                .filter_map(|media_range| media_range.split(';').next())
+                .any(|media_type| media_type.trim().eq_ignore_ascii_case(mime))
+        };
+
+        if accepts("application/json") {
+            Self::Json
+        } else if accepts("text/plain") || accepts("text/html") {
+            Self::PlainText
+        } else {
+            default
+        }
+    }
+}
+
+/// Body returned to clients that asked for a JSON error.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct ErrorBody {
+    pub error: String,
+}
+
 // ===================================== Subscribe Errors ===================================== //
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error("{0}")]
-    ValidationError(String),
+    ValidationError(Message),
+    #[error(transparent)]
+    EmailNotAccepted(#[from] PolicyViolation),
+    #[error("{}", .0.body_text())]
+    InvalidPayload(#[from] JsonRejection),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl SubscribeError {
+    #[must_use]
+    pub fn status_code(&self) -> http::StatusCode {
+        match self {
+            SubscribeError::ValidationError(_) | SubscribeError::EmailNotAccepted(_) => {
+                http::StatusCode::BAD_REQUEST
+            }
+            SubscribeError::InvalidPayload(rejection) => rejection.status(),
+            SubscribeError::UnexpectedError(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Render the error in the representation negotiated with the client, and in their
+    /// language when we have a translation.
+    ///
+    /// Unexpected errors never leak their cause chain to the caller.
+    #[must_use]
+    pub fn render(self, format: ResponseFormat, locale: Locale) -> axum::response::Response {
+        let status = self.status_code();
+        let message = match &self {
+            SubscribeError::ValidationError(message) => Some(message.translate(locale)),
+            SubscribeError::EmailNotAccepted(violation) => {
+                Some(violation.message().translate(locale))
+            }
+            SubscribeError::InvalidPayload(_) => Some(self.to_string()),
+            SubscribeError::UnexpectedError(_) => None,
+        };
+
+        match (format, message) {
+            (ResponseFormat::Json, message) => {
+                let error = message.unwrap_or_else(|| "An unexpected error occurred.".into());
+                (status, Json(ErrorBody { error })).into_response()
+            }
+            (ResponseFormat::PlainText, Some(message)) => (status, message).into_response(),
+            (ResponseFormat::PlainText, None) => status.into_response(),
+        }
+    }
+}
+
 impl std::fmt::Debug for SubscribeError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
@@ -36,14 +134,7 @@ impl std::fmt::Debug for SubscribeError {
 
 impl IntoResponse for SubscribeError {
     fn into_response(self) -> axum::response::Response {
-        match self {
-            SubscribeError::ValidationError(e) => {
-                (http::StatusCode::BAD_REQUEST, e.to_string()).into_response()
-            }
-            SubscribeError::UnexpectedError(_) => {
-                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            }
-        }
+        self.render(ResponseFormat::PlainText, Locale::default())
     }
 }
 
@@ -74,6 +165,8 @@ impl std::fmt::Display for StoreTokenError {
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -88,9 +181,192 @@ impl std::fmt::Debug for PublishError {
 impl IntoResponse for PublishError {
     fn into_response(self) -> axum::response::Response {
         match self {
+            PublishError::ValidationError(e) => (http::StatusCode::BAD_REQUEST, e).into_response(),
             PublishError::UnexpectedError(_) => {
                 http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
             }
         }
     }
 }
+
+// ===================================== Tracking Errors ===================================== //
+
+#[derive(thiserror::Error)]
+pub enum TrackingError {
+    #[error("There is no such link in the emails we sent.")]
+    UnknownLink,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TrackingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for TrackingError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            TrackingError::UnknownLink => http::StatusCode::NOT_FOUND.into_response(),
+            TrackingError::UnexpectedError(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+// ===================================== Subscriber Data Errors ===================================== //
+
+#[derive(thiserror::Error)]
+pub enum DataRequestError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The data request token is unknown or has expired.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataRequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for DataRequestError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            DataRequestError::ValidationError(e) => {
+                (http::StatusCode::BAD_REQUEST, e).into_response()
+            }
+            DataRequestError::UnknownToken => {
+                (http::StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+            }
+            DataRequestError::UnexpectedError(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+// ===================================== Preferences Errors ===================================== //
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The preferences token is unknown.")]
+    UnknownToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for PreferencesError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            PreferencesError::ValidationError(e) => {
+                (http::StatusCode::BAD_REQUEST, e).into_response()
+            }
+            PreferencesError::UnknownToken => {
+                (http::StatusCode::UNAUTHORIZED, self.to_string()).into_response()
+            }
+            PreferencesError::UnexpectedError(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+// ===================================== Admin Errors ===================================== //
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Not found.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            AdminError::ValidationError(e) => (http::StatusCode::BAD_REQUEST, e).into_response(),
+            AdminError::NotFound => http::StatusCode::NOT_FOUND.into_response(),
+            AdminError::UnexpectedError(_) => {
+                http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+// ===================================== Email Errors ===================================== //
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    #[error("{0}")]
+    InvalidAttachment(String),
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+    #[error("The email provider is unavailable, its circuit breaker is open")]
+    Unavailable,
+}
+
+impl SendEmailError {
+    /// Whether sending again later may succeed, as the provider is down rather than turning down
+    /// the email.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        match self {
+            SendEmailError::InvalidAttachment(_) => false,
+            SendEmailError::Request(e) => crate::email_client::is_provider_failure(e),
+            SendEmailError::Unavailable => true,
+        }
+    }
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+// ===================================== Startup Errors ===================================== //
+
+#[derive(thiserror::Error)]
+pub enum StartupError {
+    #[error(transparent)]
+    InvalidConfiguration(#[from] ConfigurationError),
+    #[error("Failed to listen on {address}")]
+    Bind {
+        address: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Failed to read {}", .path.display())]
+    ReadFile {
+        path: std::path::PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl std::fmt::Debug for StartupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
@@ -0,0 +1,222 @@
+//! Translations of what we show or send to subscribers, from the Fluent catalogs in `locales/`.
+//!
+//! A subscriber's locale is picked when they subscribe, from the form or from their browser's
+//! `Accept-Language`, and stored with them so every later email is sent in the same language.
+
+use std::{collections::HashMap, sync::LazyLock};
+
+use axum::http::{header, HeaderMap};
+use fluent_bundle::{concurrent::FluentBundle, FluentArgs, FluentResource};
+use fluent_langneg::{negotiate_languages, parse_accepted_languages, NegotiationStrategy};
+use unic_langid::LanguageIdentifier;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Locale {
+    #[default]
+    En,
+    Fr,
+    De,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 3] = [Locale::En, Locale::Fr, Locale::De];
+
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Fr => "fr",
+            Locale::De => "de",
+        }
+    }
+
+    fn catalog(self) -> &'static str {
+        match self {
+            Locale::En => include_str!("../locales/en.ftl"),
+            Locale::Fr => include_str!("../locales/fr.ftl"),
+            Locale::De => include_str!("../locales/de.ftl"),
+        }
+    }
+
+    fn language_identifier(self) -> LanguageIdentifier {
+        self.as_str().parse().expect("Invalid locale")
+    }
+
+    /// The supported locale closest to `requested`, in order of preference.
+    fn negotiate(requested: &[LanguageIdentifier]) -> Option<Self> {
+        let available: Vec<LanguageIdentifier> = Self::ALL
+            .iter()
+            .map(|locale| locale.language_identifier())
+            .collect();
+        let supported =
+            negotiate_languages(requested, &available, None, NegotiationStrategy::Filtering);
+        let first = supported.first()?;
+        Self::ALL
+            .into_iter()
+            .find(|locale| locale.language_identifier() == **first)
+    }
+
+    /// Parse a language tag such as `fr` or `fr-CA`, `None` if we don't support it.
+    #[must_use]
+    pub fn parse(tag: &str) -> Option<Self> {
+        Self::negotiate(&[tag.parse().ok()?])
+    }
+
+    /// The locale asked for by a form field, else by the `Accept-Language` header, else English.
+    #[must_use]
+    pub fn resolve(requested: Option<&str>, headers: &HeaderMap) -> Self {
+        requested
+            .and_then(Self::parse)
+            .or_else(|| {
+                let accept_language = headers.get(header::ACCEPT_LANGUAGE)?.to_str().ok()?;
+                Self::negotiate(&parse_accepted_languages(accept_language))
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Locales stored before we supported them, or edited by hand, fall back to English.
+impl From<&str> for Locale {
+    fn from(value: &str) -> Self {
+        Self::parse(value).unwrap_or_default()
+    }
+}
+
+static BUNDLES: LazyLock<HashMap<Locale, FluentBundle<FluentResource>>> = LazyLock::new(|| {
+    Locale::ALL
+        .into_iter()
+        .map(|locale| {
+            let resource = FluentResource::try_new(locale.catalog().to_owned())
+                .unwrap_or_else(|(_, errors)| panic!("Invalid {locale:?} catalog: {errors:?}"));
+            let mut bundle = FluentBundle::new_concurrent(vec![locale.language_identifier()]);
+            // Unicode isolation marks around arguments would end up in links and plain text.
+            bundle.set_use_isolating(false);
+            bundle
+                .add_resource(resource)
+                .unwrap_or_else(|errors| panic!("Invalid {locale:?} catalog: {errors:?}"));
+            (locale, bundle)
+        })
+        .collect()
+});
+
+/// Translate message `id` of the catalogs, falling back to English if it isn't translated.
+#[must_use]
+pub fn translate(locale: Locale, id: &str, args: &[(&str, &str)]) -> String {
+    let args: FluentArgs = args.iter().map(|(name, value)| (*name, *value)).collect();
+    [locale, Locale::En]
+        .into_iter()
+        .find_map(|locale| {
+            let bundle = &BUNDLES[&locale];
+            let pattern = bundle.get_message(id)?.value()?;
+            let mut errors = vec![];
+            let message = bundle.format_pattern(pattern, Some(&args), &mut errors);
+            if !errors.is_empty() {
+                tracing::warn!(?errors, id, locale = locale.as_str(), "Invalid translation");
+            }
+            Some(message.into_owned())
+        })
+        .unwrap_or_else(|| id.to_owned())
+}
+
+/// A message for a user, translated once we know which locale they want it in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    id: &'static str,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Message {
+    #[must_use]
+    pub fn new(id: &'static str) -> Self {
+        Self { id, args: vec![] }
+    }
+
+    #[must_use]
+    pub fn arg(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.args.push((name, value.into()));
+        self
+    }
+
+    #[must_use]
+    pub fn translate(&self, locale: Locale) -> String {
+        let args: Vec<(&str, &str)> = self
+            .args
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+            .collect();
+        translate(locale, self.id, &args)
+    }
+}
+
+/// In English, for logs and for callers that don't know who they're talking to.
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.translate(Locale::En))
+    }
+}
+
+impl From<Message> for String {
+    fn from(message: Message) -> Self {
+        message.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{translate, Locale, Message};
+    use axum::http::{header, HeaderMap, HeaderValue};
+
+    /// Messages start unindented, `id = ...`.
+    fn message_ids(locale: Locale) -> Vec<&'static str> {
+        let mut ids: Vec<&str> = locale
+            .catalog()
+            .lines()
+            .filter(|line| line.starts_with(|c: char| c.is_ascii_lowercase()))
+            .filter_map(|line| line.split_once(" =").map(|(id, _)| id))
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn every_catalog_translates_every_message() {
+        let english = message_ids(Locale::En);
+        for locale in Locale::ALL {
+            assert_eq!(message_ids(locale), english, "{locale:?}");
+        }
+    }
+
+    #[test]
+    fn arguments_are_interpolated_without_isolation_marks() {
+        let message = Message::new("invalid-name").arg("value", "{Ursula}");
+        assert_eq!(
+            message.translate(Locale::Fr),
+            "{Ursula} n'est pas un nom valide."
+        );
+        assert_eq!(
+            message.to_string(),
+            "{Ursula} is not a valid subscriber name."
+        );
+    }
+
+    #[test]
+    fn unknown_messages_are_shown_by_id() {
+        assert_eq!(
+            translate(Locale::De, "no-such-message", &[]),
+            "no-such-message"
+        );
+    }
+
+    #[test]
+    fn the_form_field_wins_over_the_accept_language_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT_LANGUAGE,
+            HeaderValue::from_static("de-CH, de;q=0.9, en;q=0.5"),
+        );
+        assert_eq!(Locale::resolve(None, &headers), Locale::De);
+        assert_eq!(Locale::resolve(Some("fr-CA"), &headers), Locale::Fr);
+        assert_eq!(Locale::resolve(Some("klingon"), &headers), Locale::De);
+        assert_eq!(Locale::resolve(None, &HeaderMap::new()), Locale::En);
+    }
+}
@@ -0,0 +1,259 @@
+//! Moving `<style>` blocks into `style` attributes, as Gmail and most other mail clients drop
+//! them.
+//!
+//! Rules are applied in the order of the cascade: by specificity, then in the order they were
+//! written, with the element's own `style` attribute last. Rules that can't be inlined, such as
+//! `@media` queries and selectors with state like `:hover`, are dropped.
+
+use std::borrow::Cow;
+
+use lol_html::{
+    element, html_content::Element, rewrite_str, text, ElementContentHandlers, RewriteStrSettings,
+    Selector,
+};
+
+/// A style rule with a single selector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    selector: String,
+    /// Separated by `; `, without a trailing one.
+    declarations: String,
+    specificity: (u32, u32, u32),
+}
+
+/// Remove the `<style>` elements of `html`, applying their rules to the `style` attribute of
+/// the elements they match.
+pub fn inline_css(html: &str) -> Result<String, String> {
+    let mut stylesheet = String::new();
+    let html = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                text!("style", |chunk| {
+                    stylesheet.push_str(chunk.as_str());
+                    Ok(())
+                }),
+                element!("style", |style| {
+                    style.remove();
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::new()
+        },
+    )
+    .map_err(|e| format!("The HTML can't be processed: {e}"))?;
+
+    let mut rules: Vec<(Selector, Rule)> = parse_stylesheet(&stylesheet)
+        .into_iter()
+        .filter_map(|rule| Some((rule.selector.parse().ok()?, rule)))
+        .collect();
+    if rules.is_empty() {
+        return Ok(html);
+    }
+    // Each rule prepends its declarations to what the rules handled before it, so handling the
+    // most specific rules first leaves them last, overriding the less specific ones.
+    rules.reverse();
+    rules.sort_by_key(|(_, rule)| std::cmp::Reverse(rule.specificity));
+
+    let handlers = rules
+        .into_iter()
+        .map(|(selector, rule)| {
+            let handler = move |element: &mut Element<'_, '_>| {
+                let style = match element.get_attribute("style") {
+                    Some(style) if !style.trim().is_empty() => {
+                        format!("{}; {}", rule.declarations, style.trim())
+                    }
+                    _ => rule.declarations.clone(),
+                };
+                element.set_attribute("style", &style)?;
+                Ok(())
+            };
+            (
+                Cow::Owned(selector),
+                ElementContentHandlers::default().element(handler),
+            )
+        })
+        .collect();
+    rewrite_str(
+        &html,
+        RewriteStrSettings {
+            element_content_handlers: handlers,
+            ..RewriteStrSettings::new()
+        },
+    )
+    .map_err(|e| format!("The HTML can't be processed: {e}"))
+}
+
+/// The style rules of `stylesheet`, one per selector, in the order they were written.
+fn parse_stylesheet(stylesheet: &str) -> Vec<Rule> {
+    let stylesheet = strip_comments(stylesheet);
+    let mut rules = vec![];
+    let mut rest = stylesheet.as_str();
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with('@') {
+            rest = skip_at_rule(rest);
+            continue;
+        }
+        let Some((selectors, after)) = rest.split_once('{') else {
+            break;
+        };
+        let Some((declarations, after)) = after.split_once('}') else {
+            break;
+        };
+        rest = after;
+
+        let declarations = declarations
+            .split(';')
+            .map(str::trim)
+            .filter(|declaration| !declaration.is_empty())
+            .collect::<Vec<_>>()
+            .join("; ");
+        if declarations.is_empty() {
+            continue;
+        }
+        for selector in selectors.split(',').map(str::trim) {
+            if !selector.is_empty() {
+                rules.push(Rule {
+                    selector: selector.to_owned(),
+                    declarations: declarations.clone(),
+                    specificity: specificity(selector),
+                });
+            }
+        }
+    }
+    rules
+}
+
+fn strip_comments(stylesheet: &str) -> String {
+    let mut stripped = String::with_capacity(stylesheet.len());
+    let mut rest = stylesheet;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = rest[start + 2..]
+            .split_once("*/")
+            .map_or("", |(_, after)| after);
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// What follows the at-rule `rest` starts with, either a statement like `@import` or a block
+/// like `@media`.
+fn skip_at_rule(rest: &str) -> &str {
+    let Some(end) = rest.find([';', '{']) else {
+        return "";
+    };
+    if rest[end..].starts_with(';') {
+        return &rest[end + 1..];
+    }
+    let mut depth = 0;
+    for (i, c) in rest.char_indices().skip(end) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return &rest[i + 1..],
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// The number of ids, of classes, attributes and pseudo-classes, and of element names in
+/// `selector`.
+fn specificity(selector: &str) -> (u32, u32, u32) {
+    let (mut ids, mut classes, mut elements) = (0, 0, 0);
+    let mut starts_compound = true;
+    let mut chars = selector.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => ids += 1,
+            '.' | ':' => classes += 1,
+            '[' => {
+                classes += 1;
+                chars.by_ref().find(|&c| c == ']');
+            }
+            ' ' | '>' | '+' | '~' | '(' => {
+                starts_compound = true;
+                continue;
+            }
+            c if starts_compound && c.is_ascii_alphabetic() => elements += 1,
+            _ => {}
+        }
+        starts_compound = false;
+    }
+    (ids, classes, elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{inline_css, parse_stylesheet, specificity};
+    use claims::assert_ok_eq;
+
+    #[test]
+    fn rules_are_moved_into_style_attributes() {
+        assert_ok_eq!(
+            inline_css(
+                "<style>p { color: red; } .note { font-size: 12px }</style>\
+                 <p>First</p><p class=\"note\">Second</p>"
+            ),
+            "<p style=\"color: red\">First</p>\
+             <p class=\"note\" style=\"color: red; font-size: 12px\">Second</p>"
+        );
+    }
+
+    #[test]
+    fn more_specific_rules_override_less_specific_ones() {
+        assert_ok_eq!(
+            inline_css(
+                "<style>#intro { color: blue } p.lead { color: green } p { color: red }</style>\
+                 <p id=\"intro\" class=\"lead\">Hello</p>"
+            ),
+            "<p id=\"intro\" class=\"lead\" style=\"color: red; color: green; color: blue\">\
+             Hello</p>"
+        );
+    }
+
+    #[test]
+    fn style_attributes_override_every_rule() {
+        assert_ok_eq!(
+            inline_css("<style>p { color: red }</style><p style=\"color: blue;\">Hello</p>"),
+            "<p style=\"color: red; color: blue;\">Hello</p>"
+        );
+    }
+
+    #[test]
+    fn rules_that_cannot_be_inlined_are_dropped() {
+        assert_ok_eq!(
+            inline_css(
+                "<style>@media (max-width: 600px) { p { color: red } } a:hover { color: red }\
+                 /* p { color: red } */</style><p><a href=\"#\">Hello</a></p>"
+            ),
+            "<p><a href=\"#\">Hello</a></p>"
+        );
+    }
+
+    #[test]
+    fn html_without_stylesheets_is_left_alone() {
+        assert_ok_eq!(
+            inline_css("<p style=\"color: red\">Hello</p>"),
+            "<p style=\"color: red\">Hello</p>"
+        );
+    }
+
+    #[test]
+    fn every_selector_of_a_rule_gets_its_declarations() {
+        let rules = parse_stylesheet("@import url(x.css); h1, h2 { margin: 0; ; }");
+        let selectors: Vec<_> = rules.iter().map(|rule| rule.selector.as_str()).collect();
+        assert_eq!(selectors, ["h1", "h2"]);
+        assert!(rules.iter().all(|rule| rule.declarations == "margin: 0"));
+    }
+
+    #[test]
+    fn specificity_counts_ids_then_classes_then_elements() {
+        assert_eq!(specificity("p"), (0, 0, 1));
+        assert_eq!(specificity("div > p.lead"), (0, 1, 2));
+        assert_eq!(specificity("#intro a[href=\"x.y\"]"), (1, 1, 1));
+        assert_eq!(specificity("*"), (0, 0, 0));
+    }
+}
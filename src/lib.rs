@@ -5,11 +5,29 @@
     clippy::missing_errors_doc
 )]
 
+pub mod audit;
+pub mod canonical_email;
+pub mod circuit_breaker;
 pub mod configuration;
+pub mod content;
 pub mod db;
+pub mod digest;
 pub mod domain;
 pub mod email_client;
+pub mod email_outbox;
+pub mod email_policy;
 pub mod error;
+pub mod i18n;
+pub mod inline_css;
+pub mod newsletter_delivery;
+pub mod openapi;
 pub mod routes;
+pub mod segment;
 pub mod startup;
+pub mod subject_test;
+pub mod subscriber_csv;
 pub mod telemetry;
+pub mod tenant;
+pub mod throttle;
+pub mod tls;
+pub mod webhooks;
@@ -1,18 +1,118 @@
-use zero2prod::startup::Application;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::{
-    configuration::get_configuration,
+    audit::Actor,
+    configuration::{get_configuration, show_configuration},
+    email_policy::EmailPolicy,
+    subscriber_csv,
     telemetry::{get_subscriber, init_subscriber},
+    tenant::DEFAULT_TENANT,
 };
 
-async fn run() -> hyper::Result<()> {
-    let configuration = get_configuration().expect("Failed to read configuration");
-    Application::build(configuration).run().await
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server (the default).
+    Serve,
+    /// Import subscribers from a CSV file with columns `email,name,status,subscribed_at`.
+    Import {
+        /// Path to the CSV file.
+        path: PathBuf,
+        /// Slug of the newsletter to import the subscribers into.
+        #[arg(long, default_value = DEFAULT_TENANT)]
+        tenant: String,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration, secrets redacted, and where each value comes from.
+    Show,
+}
+
+async fn serve() -> anyhow::Result<()> {
+    let configuration = get_configuration().context("Failed to read configuration")?;
+    Application::build(configuration)?.run().await?;
+    Ok(())
+}
+
+async fn import(path: PathBuf, tenant: &str) -> anyhow::Result<()> {
+    let configuration = get_configuration()
+        .context("Failed to read configuration")?
+        .validate()?;
+    if !configuration.tenants.iter().any(|t| t.slug == tenant) {
+        anyhow::bail!("There is no tenant called {tenant}");
+    }
+    let db_pool = get_connection_pool(&configuration.database);
+    let email_policy = EmailPolicy::new(configuration.disposable_domains_file.clone())
+        .context("Failed to read the disposable domain list")?;
+
+    let file =
+        std::fs::File::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let report = subscriber_csv::import(
+        &db_pool,
+        &email_policy,
+        tenant,
+        file,
+        Actor::System("import command"),
+    )
+    .await?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+fn show_config() -> anyhow::Result<()> {
+    let (environment, values) = show_configuration()?;
+    let key_width = values.iter().map(|v| v.key.len()).max().unwrap_or_default();
+    let value_width = values
+        .iter()
+        .map(|v| v.value.len())
+        .max()
+        .unwrap_or_default();
+
+    println!("# environment: {}", environment.as_str());
+    for value in values {
+        println!(
+            "{:key_width$}  {:value_width$}  # {}",
+            value.key, value.value, value.source
+        );
+    }
+    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> hyper::Result<()> {
-    let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
-    init_subscriber(subscriber);
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
 
-    run().await
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => {
+            let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stdout);
+            init_subscriber(subscriber);
+            serve().await
+        }
+        Command::Import { path, tenant } => {
+            // Keep stdout for the report.
+            let subscriber = get_subscriber("zero2prod".into(), "info".into(), std::io::stderr);
+            init_subscriber(subscriber);
+            import(path, &tenant).await
+        }
+        Command::Config {
+            command: ConfigCommand::Show,
+        } => show_config(),
+    }
 }
@@ -0,0 +1,478 @@
+//! Sending newsletter issues to their recipients, as recorded in `newsletter_deliveries`.
+//!
+//! Publishing an issue only records who it goes to, each delivery due right away. Every
+//! instance runs [`deliver_on_schedule`], which sends due deliveries in batches of recipients
+//! sharing a subject and locale, and retries those whose batch failed with exponential backoff,
+//! until [`MAX_ATTEMPTS`]. Batches are as large as the email throttle lets out at once, and wait
+//! for their turn before their deliveries are locked. While the tenant's circuit breaker is open, deliveries are put off
+//! without counting as attempts. Recipients the provider turned down, and deliveries given up
+//! on, have their `error` recorded instead.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Context;
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    domain::subscriber_email::SubscriberEmail,
+    email_client::{recipient_variable, BatchRecipient},
+    error::SendEmailError,
+    i18n::{translate, Locale},
+    routes::{preferences_link, with_footer},
+    subject_test,
+    tenant::{Tenant, Tenants},
+    webhooks::backoff,
+};
+
+/// Deliveries are given up on after this many attempts, about two hours with the default retry
+/// delay.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// Recipients of an issue who get it under the same subject, in the same locale.
+struct Batch<'a> {
+    newsletter_issue_id: Uuid,
+    subject: &'a str,
+    locale: Locale,
+    /// The issue's HTML and plain text, before the footer.
+    html: &'a str,
+    text: &'a str,
+    /// Whether `subject` is under test, the recipients' opens and clicks being tracked.
+    tracked: bool,
+    recipients: &'a [Recipient],
+}
+
+struct Recipient {
+    subscriber_id: Uuid,
+    email: SubscriberEmail,
+    name: String,
+    preferences_token: String,
+    /// Set when the subject is under test.
+    tracking_token: Option<String>,
+    /// Failed attempts at sending them the issue so far.
+    attempts: i32,
+}
+
+impl Recipient {
+    /// As one of the recipients of a batch, with their name, preference page and tracking
+    /// token.
+    fn batch_recipient(&self, base_url: &str) -> BatchRecipient {
+        let mut variables = BTreeMap::from([
+            ("name".to_owned(), self.name.clone()),
+            (
+                "preferences_link".to_owned(),
+                preferences_link(base_url, &self.preferences_token),
+            ),
+        ]);
+        if let Some(tracking_token) = &self.tracking_token {
+            variables.insert("tracking_token".to_owned(), tracking_token.clone());
+        }
+        BatchRecipient {
+            email: self.email.clone(),
+            variables,
+        }
+    }
+}
+
+/// How many recipients were sent the issue, are to be retried, or were given up on.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tally {
+    pub sent: usize,
+    pub retrying: usize,
+    pub failed: usize,
+}
+
+impl std::ops::AddAssign for Tally {
+    fn add_assign(&mut self, other: Self) {
+        self.sent += other.sent;
+        self.retrying += other.retrying;
+        self.failed += other.failed;
+    }
+}
+
+/// Send `batch` in a single request, once it is its turn, recording on each recipient's delivery
+/// whether it was sent, when to retry it, or why it was given up on.
+#[tracing::instrument(
+    name = "Send a batch of a newsletter issue",
+    skip_all,
+    fields(newsletter_issue_id = %batch.newsletter_issue_id, recipients = batch.recipients.len())
+)]
+async fn send(
+    connection: &mut PgConnection,
+    tenant: &Tenant,
+    batch: &Batch<'_>,
+    retry_delay: Duration,
+) -> Result<Tally, anyhow::Error> {
+    let html = if batch.tracked {
+        let tracking_token = recipient_variable("tracking_token");
+        subject_test::track(batch.html, &tenant.base_url, &tracking_token)
+    } else {
+        batch.html.to_owned()
+    };
+    let (html, text) = with_footer(
+        batch.locale,
+        &recipient_variable("preferences_link"),
+        Some(&translate(batch.locale, "newsletter-footer", &[])),
+        &html,
+        batch.text,
+    );
+
+    let recipients: Vec<_> = batch
+        .recipients
+        .iter()
+        .map(|recipient| recipient.batch_recipient(&tenant.base_url))
+        .collect();
+    let outcome = tenant
+        .email_client
+        .send_batch(batch.subject, &html, &text, &recipients)
+        .await;
+    if let Err(error) = &outcome {
+        tracing::error!(
+            error.cause_chain = ?error,
+            "Failed to send a batch of a newsletter issue",
+        );
+    }
+    record(
+        connection,
+        batch.newsletter_issue_id,
+        batch.recipients,
+        &outcome,
+        retry_delay,
+    )
+    .await
+    .context("Failed to record who the newsletter issue was sent to")
+}
+
+/// Mark the deliveries to `recipients` as sent, schedule another attempt, or record why they
+/// were given up on.
+async fn record(
+    connection: &mut PgConnection,
+    newsletter_issue_id: Uuid,
+    recipients: &[Recipient],
+    outcome: &Result<Vec<Result<(), String>>, SendEmailError>,
+    retry_delay: Duration,
+) -> Result<Tally, sqlx::Error> {
+    let mut tally = Tally::default();
+    let mut sent = Vec::with_capacity(recipients.len());
+    let mut errors = Vec::with_capacity(recipients.len());
+    let mut attempts = Vec::with_capacity(recipients.len());
+    let mut delays = Vec::with_capacity(recipients.len());
+    for (i, recipient) in recipients.iter().enumerate() {
+        let (was_sent, error, attempt, delay) = match outcome {
+            Ok(results) => match results.get(i).cloned().unwrap_or(Ok(())) {
+                Ok(()) => (true, None, recipient.attempts, None),
+                Err(error) => {
+                    tracing::warn!(
+                        subscriber_id = %recipient.subscriber_id,
+                        error,
+                        "The email API turned down a recipient of a newsletter issue",
+                    );
+                    (false, Some(error), recipient.attempts, None)
+                }
+            },
+            // Nothing was tried: wait for the circuit breaker to let sends through again.
+            Err(SendEmailError::Unavailable) => {
+                (false, None, recipient.attempts, Some(retry_delay))
+            }
+            Err(e) if e.is_transient() && recipient.attempts + 1 < MAX_ATTEMPTS => {
+                let attempt = recipient.attempts + 1;
+                (false, None, attempt, Some(backoff(retry_delay, attempt)))
+            }
+            Err(e) => (false, Some(e.to_string()), recipient.attempts + 1, None),
+        };
+        match (was_sent, &delay) {
+            (true, _) => tally.sent += 1,
+            (false, Some(_)) => tally.retrying += 1,
+            (false, None) => tally.failed += 1,
+        }
+        sent.push(was_sent);
+        errors.push(error);
+        attempts.push(attempt);
+        delays.push(delay.map(|delay| delay.as_secs_f64()));
+    }
+
+    let subscriber_ids: Vec<_> = recipients.iter().map(|r| r.subscriber_id).collect();
+    sqlx::query!(
+        r#"
+    UPDATE newsletter_deliveries d
+    SET sent_at = CASE WHEN r.sent THEN now() END, error = r.error, attempts = r.attempts,
+      next_attempt_at = now() + r.delay * interval '1 second'
+    FROM UNNEST($2::uuid[], $3::bool[], $4::text[], $5::int[], $6::float8[])
+      AS r(subscriber_id, sent, error, attempts, delay)
+    WHERE d.newsletter_issue_id = $1 AND d.subscriber_id = r.subscriber_id
+        "#,
+        newsletter_issue_id,
+        &subscriber_ids,
+        &sent,
+        &errors as &[Option<String>],
+        &attempts,
+        &delays as &[Option<f64>],
+    )
+    .execute(&mut *connection)
+    .await?;
+    Ok(tally)
+}
+
+/// Look for deliveries due another attempt every `interval`, sending them. Never returns.
+pub async fn deliver_on_schedule(
+    pool: PgPool,
+    tenants: Tenants,
+    interval: Duration,
+    retry_delay: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match deliver_due(&pool, &tenants, retry_delay).await {
+            Ok(tally) if tally.sent == 0 => {}
+            Ok(tally) => tracing::info!(sent = tally.sent, "Sent newsletter deliveries"),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to send newsletter deliveries, retrying on the next check"
+            ),
+        }
+    }
+}
+
+/// Send the due deliveries in the background, e.g. those of an issue that was just published,
+/// rather than wait for the next check.
+pub fn deliver_soon(pool: PgPool, tenants: Tenants, retry_delay: Duration) {
+    tokio::spawn(async move {
+        if let Err(e) = deliver_due(&pool, &tenants, retry_delay).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to send newsletter deliveries, retrying on the next check"
+            );
+        }
+    });
+}
+
+/// Attempt every delivery that is due.
+#[tracing::instrument(name = "Send due newsletter deliveries", skip_all)]
+pub async fn deliver_due(
+    pool: &PgPool,
+    tenants: &Tenants,
+    retry_delay: Duration,
+) -> Result<Tally, anyhow::Error> {
+    let mut tally = Tally::default();
+    while let Some(batch) = deliver_next_batch(pool, tenants, retry_delay).await? {
+        tally += batch;
+    }
+    Ok(tally)
+}
+
+/// Attempt the due deliveries of one batch, `None` if no delivery is due.
+///
+/// The batch waits for its turn before its deliveries are locked, which may take seconds when
+/// its recipients' domains are over budget.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = tracing::field::Empty))]
+async fn deliver_next_batch(
+    pool: &PgPool,
+    tenants: &Tenants,
+    retry_delay: Duration,
+) -> Result<Option<Tally>, anyhow::Error> {
+    // Those waiting for the winner of a subject test are sent it by `subject_test`.
+    let Some(head) = sqlx::query!(
+        r#"
+    SELECT d.newsletter_issue_id, d.variant, s.locale, i.tenant, i.title, i.html_content,
+      i.text_content, i.subject_variants
+    FROM newsletter_deliveries d
+    JOIN newsletter_issues i USING (newsletter_issue_id)
+    JOIN subscriptions s ON s.id = d.subscriber_id
+    WHERE d.sent_at IS NULL AND d.error IS NULL AND d.next_attempt_at <= now()
+      AND (d.variant IS NOT NULL OR i.subject_variants IS NULL)
+    ORDER BY d.next_attempt_at
+    LIMIT 1
+        "#,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look for a due newsletter delivery")?
+    else {
+        return Ok(None);
+    };
+    tracing::Span::current().record(
+        "newsletter_issue_id",
+        tracing::field::display(head.newsletter_issue_id),
+    );
+    let Some(tenant) = tenants.get(&head.tenant) else {
+        tracing::warn!(
+            tenant = head.tenant,
+            "Giving up on the deliveries of a tenant that is no longer configured"
+        );
+        give_up(
+            pool,
+            head.newsletter_issue_id,
+            "The tenant is no longer configured.",
+        )
+        .await?;
+        return Ok(Some(Tally::default()));
+    };
+
+    let due = due_recipients(
+        pool,
+        head.newsletter_issue_id,
+        head.variant,
+        &head.locale,
+        tenant.email_client.max_batch_size(),
+    )
+    .await?;
+    let emails: Vec<_> = due.iter().map(|recipient| &recipient.email).collect();
+    let picked = tenant.email_client.next_batch(&emails);
+    let due: Vec<_> = due
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| picked.contains(i))
+        .map(|(_, recipient)| recipient)
+        .collect();
+    let emails: Vec<_> = due.iter().map(|recipient| &recipient.email).collect();
+    tenant.email_client.wait_for_turn(&emails).await;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let recipients = lock_recipients(&mut transaction, head.newsletter_issue_id, due).await?;
+    if recipients.is_empty() {
+        // Another instance is sending them: the rest is left to the next check.
+        return Ok(None);
+    }
+
+    let subject = head
+        .variant
+        .and_then(|variant| usize::try_from(variant).ok())
+        .and_then(|variant| head.subject_variants?.get(variant).cloned())
+        .unwrap_or(head.title);
+    let batch = Batch {
+        newsletter_issue_id: head.newsletter_issue_id,
+        subject: &subject,
+        locale: Locale::from(head.locale.as_str()),
+        html: &head.html_content,
+        text: &head.text_content,
+        tracked: head.variant.is_some(),
+        recipients: &recipients,
+    };
+    let tally = send(&mut transaction, &tenant, &batch, retry_delay).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(Some(tally))
+}
+
+/// Up to `limit` recipients of due deliveries of an issue under the same subject, in the same
+/// locale, giving up on those whose address is invalid.
+async fn due_recipients(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    variant: Option<i16>,
+    locale: &str,
+    limit: usize,
+) -> Result<Vec<Recipient>, anyhow::Error> {
+    let rows = sqlx::query!(
+        r#"
+    SELECT d.subscriber_id, d.tracking_token, d.attempts, s.email, s.name, s.preferences_token
+    FROM newsletter_deliveries d
+    JOIN subscriptions s ON s.id = d.subscriber_id
+    WHERE d.newsletter_issue_id = $1 AND d.variant IS NOT DISTINCT FROM $2 AND s.locale = $3
+      AND d.sent_at IS NULL AND d.error IS NULL AND d.next_attempt_at <= now()
+    ORDER BY d.next_attempt_at
+    LIMIT $4
+        "#,
+        newsletter_issue_id,
+        variant,
+        locale,
+        i64::try_from(limit).unwrap_or(i64::MAX),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to collect a batch of due newsletter deliveries")?;
+    let mut recipients = Vec::with_capacity(rows.len());
+    let mut invalid = vec![];
+    for row in rows {
+        match SubscriberEmail::try_from(row.email) {
+            Ok(email) => recipients.push(Recipient {
+                subscriber_id: row.subscriber_id,
+                email,
+                name: row.name,
+                preferences_token: row.preferences_token,
+                tracking_token: row.tracking_token,
+                attempts: row.attempts,
+            }),
+            Err(_) => invalid.push(row.subscriber_id),
+        }
+    }
+    if !invalid.is_empty() {
+        // Addresses stored before validation got stricter can't be sent to.
+        sqlx::query!(
+            r#"
+    UPDATE newsletter_deliveries SET error = 'The stored email address is invalid.'
+    WHERE newsletter_issue_id = $1 AND subscriber_id = ANY($2)
+            "#,
+            newsletter_issue_id,
+            &invalid,
+        )
+        .execute(pool)
+        .await
+        .context("Failed to give up on deliveries to invalid addresses")?;
+    }
+    Ok(recipients)
+}
+
+/// Lock the deliveries of an issue to those of `recipients` that are still due, returning them.
+/// Those another instance is sending, or sent since, are left out.
+async fn lock_recipients(
+    connection: &mut PgConnection,
+    newsletter_issue_id: Uuid,
+    mut recipients: Vec<Recipient>,
+) -> Result<Vec<Recipient>, anyhow::Error> {
+    let subscriber_ids: Vec<_> = recipients.iter().map(|r| r.subscriber_id).collect();
+    let locked = sqlx::query!(
+        r#"
+    SELECT subscriber_id, attempts
+    FROM newsletter_deliveries
+    WHERE newsletter_issue_id = $1 AND subscriber_id = ANY($2)
+      AND sent_at IS NULL AND error IS NULL AND next_attempt_at <= now()
+    FOR UPDATE SKIP LOCKED
+        "#,
+        newsletter_issue_id,
+        &subscriber_ids,
+    )
+    .fetch_all(connection)
+    .await
+    .context("Failed to lock a batch of due newsletter deliveries")?;
+    recipients.retain_mut(|recipient| {
+        match locked
+            .iter()
+            .find(|row| row.subscriber_id == recipient.subscriber_id)
+        {
+            Some(row) => {
+                recipient.attempts = row.attempts;
+                true
+            }
+            None => false,
+        }
+    });
+    Ok(recipients)
+}
+
+/// Record `error` on every due delivery of an issue.
+async fn give_up(
+    executor: impl PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE newsletter_deliveries SET error = $2
+    WHERE newsletter_issue_id = $1 AND sent_at IS NULL AND error IS NULL
+      AND next_attempt_at IS NOT NULL
+        "#,
+        newsletter_issue_id,
+        error,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to give up on the deliveries of a newsletter issue")?;
+    Ok(())
+}
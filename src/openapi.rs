@@ -0,0 +1,132 @@
+use axum::{response::Html, Json};
+use utoipa::{
+    openapi::security::{Http, HttpAuthScheme, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::{
+    domain::subscriber_attributes::AttributeType, error::ErrorBody, routes, subscriber_csv,
+};
+
+/// The `OpenAPI` document describing our public HTTP API.
+///
+/// It is generated from the handlers' `#[utoipa::path]` annotations and the request/response
+/// types: add new endpoints to `paths(...)` and the integration tests will check the router
+/// actually serves them.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "zero2prod",
+        description = "Subscribe to, confirm and publish our newsletters.\n\n\
+            Every path is relative to a newsletter: the one configured for the request's `Host`, \
+            or the one whose slug prefixes the path, as in `/t/{slug}/subscriptions`."
+    ),
+    paths(
+        routes::health_check,
+        routes::ready,
+        routes::subscribe,
+        routes::subscribe_json,
+        routes::confirm,
+        routes::request_data,
+        routes::manage_data,
+        routes::export_data,
+        routes::erase_data,
+        routes::import_subscribers,
+        routes::export_subscribers,
+        routes::search_subscribers,
+        routes::get_subscriber,
+        routes::put_subscriber_attributes,
+        routes::search_audit_events,
+        routes::list_email_domains,
+        routes::put_email_domain,
+        routes::delete_email_domain,
+        routes::list_mailing_lists,
+        routes::put_mailing_list,
+        routes::delete_mailing_list,
+        routes::list_webhooks,
+        routes::create_webhook,
+        routes::delete_webhook,
+        routes::list_webhook_deliveries,
+        routes::metrics,
+        routes::show_preferences,
+        routes::update_preferences,
+        routes::publish_newsletter,
+        routes::track_open,
+        routes::track_click,
+    ),
+    components(schemas(
+        routes::Readiness,
+        routes::FormData,
+        routes::SubscriptionCreated,
+        routes::BodyData,
+        routes::Content,
+        routes::SubjectTestBody,
+        routes::PublishReport,
+        routes::DataRequestForm,
+        routes::DataRequestParameters,
+        routes::SubscriberDataExport,
+        routes::SubscriberRecord,
+        routes::StatusChange,
+        routes::DeliveryRecord,
+        routes::SubscriberPage,
+        routes::SubscriberDetail,
+        routes::AuditEventRecord,
+        routes::AuditEventPage,
+        routes::EmailDomainRecord,
+        routes::EmailDomainRuleBody,
+        routes::MailingListRecord,
+        routes::MailingListBody,
+        AttributeType,
+        routes::WebhookEndpointRecord,
+        routes::WebhookEndpointCreated,
+        routes::WebhookEndpointBody,
+        routes::WebhookDeliveryRecord,
+        routes::PreferencesParameters,
+        routes::PreferencesForm,
+        subscriber_csv::ImportReport,
+        subscriber_csv::RowError,
+        ErrorBody,
+    )),
+    modifiers(&AdminSecurity)
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token guarding the `/admin` endpoints.
+struct AdminSecurity;
+
+impl Modify for AdminSecurity {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+        );
+    }
+}
+
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Interactive documentation rendered by Swagger UI on top of `/openapi.json`.
+pub async fn docs() -> Html<&'static str> {
+    Html(
+        r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>zero2prod API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+    <script>
+      window.onload = () => {
+        window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+      };
+    </script>
+  </body>
+</html>"##,
+    )
+}
@@ -0,0 +1,119 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::{error::AdminError, startup::AppState, tenant::Tenant};
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditEventSearch {
+    /// Only return events of this kind of actor: `admin`, `subscriber`, `webhook` or `system`.
+    actor: Option<String>,
+    /// Only return events for this action, e.g. `confirm` or `erase`.
+    action: Option<String>,
+    /// Only return events about this subscriber or newsletter issue.
+    target_id: Option<String>,
+    /// Only return events that occurred at or after this instant.
+    since: Option<DateTime<Utc>>,
+    /// The `next_cursor` of the previous page.
+    cursor: Option<i64>,
+    /// How many events to return, 100 by default and 1000 at most.
+    limit: Option<i64>,
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct AuditEventRecord {
+    pub id: i64,
+    pub occurred_at: DateTime<Utc>,
+    pub actor: String,
+    pub actor_id: Option<String>,
+    pub action: String,
+    pub target: String,
+    pub target_id: Option<String>,
+    pub status_before: Option<String>,
+    pub status_after: Option<String>,
+    #[schema(value_type = Object)]
+    pub details: serde_json::Value,
+}
+
+/// A page of audit events, most recent first.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEventRecord>,
+    /// Pass it back as `cursor` to get the next page. Absent on the last page.
+    pub next_cursor: Option<i64>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit_events",
+    tag = "admin",
+    params(AuditEventSearch),
+    responses(
+        (status = 200, description = "The audit events matching the filters", body = AuditEventPage),
+        (status = 400, description = "A filter is invalid"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Search audit events", skip(state, tenant))]
+pub async fn search_audit_events(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(search): Query<AuditEventSearch>,
+) -> Result<impl IntoResponse, AdminError> {
+    let limit = search
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // Fetch one extra row to know whether there is a next page.
+    let mut events = sqlx::query_as!(
+        AuditEventRecord,
+        r#"
+    SELECT id, occurred_at, actor, actor_id, action, target, target_id,
+           status_before, status_after, details
+    FROM audit_events
+    WHERE tenant = $1
+      AND ($2::text IS NULL OR actor = $2)
+      AND ($3::text IS NULL OR action = $3)
+      AND ($4::text IS NULL OR target_id = $4)
+      AND ($5::timestamptz IS NULL OR occurred_at >= $5)
+      AND ($6::bigint IS NULL OR id < $6)
+    ORDER BY id DESC
+    LIMIT $7
+        "#,
+        tenant.slug,
+        search.actor,
+        search.action,
+        search.target_id,
+        search.since,
+        search.cursor,
+        limit + 1,
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .context("Failed to search audit events")?;
+
+    let next_cursor = if events.len() > usize::try_from(limit).unwrap_or(usize::MAX) {
+        events.pop();
+        events.last().map(|last| last.id)
+    } else {
+        None
+    };
+
+    Ok(Json(AuditEventPage {
+        events,
+        next_cursor,
+    }))
+}
@@ -0,0 +1,148 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::{email_policy::DomainRule, error::AdminError, startup::AppState, tenant::Tenant};
+
+/// A domain whose addresses are allowed or blocked from subscribing, subdomains included.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct EmailDomainRecord {
+    pub domain: String,
+    /// `allow` or `block`.
+    pub rule: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct EmailDomainRuleBody {
+    /// `allow` to accept the domain even if it is a known disposable provider, `block` to
+    /// reject it.
+    pub rule: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/email_domains",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every allowed or blocked domain", body = Vec<EmailDomainRecord>),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "List email domain rules", skip(state, tenant))]
+pub async fn list_email_domains(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+) -> Result<impl IntoResponse, AdminError> {
+    let domains = sqlx::query_as!(
+        EmailDomainRecord,
+        r#"
+    SELECT domain, rule, created_at FROM email_domain_rules
+    WHERE tenant = $1
+    ORDER BY domain
+        "#,
+        tenant.slug,
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .context("Failed to list email domain rules")?;
+
+    Ok(Json(domains))
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/email_domains/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "The domain, e.g. `example.com`")),
+    request_body = EmailDomainRuleBody,
+    responses(
+        (status = 204, description = "The rule was saved, replacing any previous one"),
+        (status = 400, description = "The domain or the rule is invalid"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Save an email domain rule", skip(state, tenant))]
+pub async fn put_email_domain(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(domain): Path<String>,
+    Json(body): Json<EmailDomainRuleBody>,
+) -> Result<impl IntoResponse, AdminError> {
+    let domain = canonical_domain(&domain).map_err(AdminError::ValidationError)?;
+    let rule = DomainRule::try_from(body.rule.as_str()).map_err(AdminError::ValidationError)?;
+
+    sqlx::query!(
+        r#"
+    INSERT INTO email_domain_rules (tenant, domain, rule)
+    VALUES ($1, $2, $3)
+    ON CONFLICT (tenant, domain) DO UPDATE SET rule = EXCLUDED.rule, created_at = now()
+        "#,
+        tenant.slug,
+        domain,
+        rule.as_str(),
+    )
+    .execute(&state.db_pool)
+    .await
+    .context("Failed to save the email domain rule")?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/email_domains/{domain}",
+    tag = "admin",
+    params(("domain" = String, Path, description = "The domain, e.g. `example.com`")),
+    responses(
+        (status = 204, description = "The rule was removed"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 404, description = "There is no rule for this domain"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Delete an email domain rule", skip(state, tenant))]
+pub async fn delete_email_domain(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(domain): Path<String>,
+) -> Result<impl IntoResponse, AdminError> {
+    let domain = canonical_domain(&domain).map_err(AdminError::ValidationError)?;
+
+    let deleted = sqlx::query!(
+        r#"DELETE FROM email_domain_rules WHERE tenant = $1 AND domain = $2"#,
+        tenant.slug,
+        domain,
+    )
+    .execute(&state.db_pool)
+    .await
+    .context("Failed to delete the email domain rule")?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(AdminError::NotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Rules are matched against canonical emails, so store domains the same way.
+fn canonical_domain(domain: &str) -> Result<String, String> {
+    match url::Host::parse(domain) {
+        Ok(url::Host::Domain(domain)) => Ok(domain),
+        _ => Err(format!("{domain} is not a valid domain.")),
+    }
+}
@@ -0,0 +1,247 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+use crate::{
+    domain::subscriber_attributes::{is_valid_attribute_name, AttributeSchema, AttributeType},
+    error::AdminError,
+    startup::AppState,
+    tenant::Tenant,
+};
+
+/// A list issues can be published to, and that subscribers can opt out of.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct MailingListRecord {
+    pub slug: String,
+    /// Shown to subscribers on their preference page.
+    pub name: String,
+    /// The attributes the list collects about its subscribers, by name.
+    #[schema(value_type = BTreeMap<String, AttributeType>)]
+    pub attributes: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct MailingListBody {
+    pub name: String,
+    /// The attributes the list collects about its subscribers, by name, e.g.
+    /// `{"country": "string"}`. Another list collecting the same attribute must give it the same
+    /// type.
+    #[serde(default)]
+    pub attributes: AttributeSchema,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/lists",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every mailing list", body = Vec<MailingListRecord>),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "List mailing lists", skip(state, tenant))]
+pub async fn list_mailing_lists(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+) -> Result<impl IntoResponse, AdminError> {
+    let lists = get_mailing_lists(&state.db_pool, &tenant.slug)
+        .await
+        .context("Failed to list mailing lists")?;
+
+    Ok(Json(lists))
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/lists/{slug}",
+    tag = "admin",
+    params(("slug" = String, Path, description = "Identifies the list when publishing, e.g. `releases`")),
+    request_body = MailingListBody,
+    responses(
+        (status = 204, description = "The list was created or updated"),
+        (status = 400, description = "The slug, the name or an attribute is invalid"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Save a mailing list", skip(state, tenant))]
+pub async fn put_mailing_list(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(slug): Path<String>,
+    Json(body): Json<MailingListBody>,
+) -> Result<impl IntoResponse, AdminError> {
+    if slug.is_empty()
+        || !slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(AdminError::ValidationError(format!(
+            "{slug:?} is not a valid list slug. Use lowercase letters, digits and dashes."
+        )));
+    }
+    let name = body.name.trim();
+    if name.is_empty() {
+        return Err(AdminError::ValidationError(
+            "The list name must not be empty.".into(),
+        ));
+    }
+
+    if let Some(attribute) = body
+        .attributes
+        .keys()
+        .find(|attribute| !is_valid_attribute_name(attribute))
+    {
+        return Err(AdminError::ValidationError(format!(
+            "{attribute:?} is not a valid attribute name. Use lowercase letters, digits and \
+            underscores, starting with a letter."
+        )));
+    }
+
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    // Lock the tenant's lists, so that two lists can't give an attribute different types.
+    let others = sqlx::query!(
+        r#"
+    SELECT slug, attribute_schema FROM mailing_lists
+    WHERE tenant = $1 AND slug != $2
+    FOR UPDATE
+        "#,
+        tenant.slug,
+        slug,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to look up the other mailing lists")?;
+    for other in others {
+        let schema: AttributeSchema = serde_json::from_value(other.attribute_schema)
+            .context("Failed to parse the attributes of a mailing list")?;
+        for (attribute, attribute_type) in &body.attributes {
+            match schema.get(attribute) {
+                Some(other_type) if other_type != attribute_type => {
+                    return Err(AdminError::ValidationError(format!(
+                        "{attribute} is a {} in the {} list.",
+                        other_type.as_str(),
+                        other.slug
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    sqlx::query!(
+        r#"
+    INSERT INTO mailing_lists (tenant, slug, name, attribute_schema)
+    VALUES ($1, $2, $3, $4)
+    ON CONFLICT (tenant, slug)
+    DO UPDATE SET name = EXCLUDED.name, attribute_schema = EXCLUDED.attribute_schema
+        "#,
+        tenant.slug,
+        slug,
+        name,
+        serde_json::to_value(&body.attributes).context("Failed to serialize the attributes")?,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save the mailing list")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/lists/{slug}",
+    tag = "admin",
+    params(("slug" = String, Path, description = "The list's slug")),
+    responses(
+        (status = 204, description = "The list was removed, issues already published to it are kept"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 404, description = "There is no such list"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Delete a mailing list", skip(state, tenant))]
+pub async fn delete_mailing_list(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(slug): Path<String>,
+) -> Result<impl IntoResponse, AdminError> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM mailing_lists WHERE tenant = $1 AND slug = $2"#,
+        tenant.slug,
+        slug,
+    )
+    .execute(&state.db_pool)
+    .await
+    .context("Failed to delete the mailing list")?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(AdminError::NotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(name = "Get mailing lists", skip(pool))]
+pub(crate) async fn get_mailing_lists(
+    pool: &sqlx::PgPool,
+    tenant: &str,
+) -> Result<Vec<MailingListRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingListRecord,
+        r#"
+    SELECT slug, name, attribute_schema AS attributes, created_at
+    FROM mailing_lists
+    WHERE tenant = $1
+    ORDER BY name
+        "#,
+        tenant,
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The attributes collected by any of the tenant's lists.
+#[tracing::instrument(name = "Get the attribute schema", skip(executor))]
+pub(crate) async fn get_attribute_schema(
+    executor: impl sqlx::PgExecutor<'_>,
+    tenant: &str,
+) -> Result<AttributeSchema, anyhow::Error> {
+    let schemas = sqlx::query!(
+        r#"SELECT attribute_schema FROM mailing_lists WHERE tenant = $1"#,
+        tenant,
+    )
+    .fetch_all(executor)
+    .await
+    .context("Failed to look up the attributes of the mailing lists")?;
+
+    let mut merged = AttributeSchema::new();
+    for schema in schemas {
+        let schema: AttributeSchema = serde_json::from_value(schema.attribute_schema)
+            .context("Failed to parse the attributes of a mailing list")?;
+        merged.extend(schema);
+    }
+    Ok(merged)
+}
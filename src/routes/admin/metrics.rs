@@ -0,0 +1,120 @@
+use std::fmt::Write;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+
+use crate::{
+    startup::AppState,
+    throttle::{ProviderThrottleState, ThrottleState},
+};
+
+#[utoipa::path(
+    get,
+    path = "/admin/metrics",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String, content_type = "text/plain"),
+        (status = 401, description = "The admin token is missing or wrong"),
+    ),
+    security(("admin_token" = []))
+)]
+#[tracing::instrument(name = "Export metrics", skip(state))]
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_throttle(&state.email_throttle.state()),
+    )
+}
+
+/// The state of the email throttle in the Prometheus text format.
+fn render_throttle(state: &ThrottleState) -> String {
+    let by_provider = |value: fn(&ProviderThrottleState) -> String| -> Vec<(String, String)> {
+        state
+            .providers
+            .iter()
+            .map(|p| (format!("provider=\"{}\"", p.provider.as_str()), value(p)))
+            .collect()
+    };
+    let by_domain: Vec<_> = state
+        .domains
+        .iter()
+        .map(|(domain, backlog)| {
+            let domain = domain.replace('\\', "\\\\").replace('"', "\\\"");
+            (
+                format!("domain=\"{domain}\""),
+                backlog.as_secs_f64().to_string(),
+            )
+        })
+        .collect();
+
+    let mut metrics = String::new();
+    for (name, kind, help, samples) in [
+        (
+            "email_throttle_messages_total",
+            "counter",
+            "Emails let through by the throttle.",
+            by_provider(|p| p.messages.to_string()),
+        ),
+        (
+            "email_throttle_waited_seconds_total",
+            "counter",
+            "Time sends spent waiting for their turn.",
+            by_provider(|p| p.waited.as_secs_f64().to_string()),
+        ),
+        (
+            "email_throttle_waiting",
+            "gauge",
+            "Sends waiting for their turn.",
+            by_provider(|p| p.waiting.to_string()),
+        ),
+        (
+            "email_throttle_backlog_seconds",
+            "gauge",
+            "How far behind the budget of the email provider sends are.",
+            by_provider(|p| p.backlog.as_secs_f64().to_string()),
+        ),
+        (
+            "email_throttle_domain_backlog_seconds",
+            "gauge",
+            "How far behind their budget sends to the recipient domains over it are.",
+            by_domain,
+        ),
+    ] {
+        let _ = writeln!(metrics, "# HELP {name} {help}\n# TYPE {name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(metrics, "{name}{{{labels}}} {value}");
+        }
+    }
+    metrics
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::render_throttle;
+    use crate::{
+        email_client::EmailProvider,
+        throttle::{ProviderThrottleState, ThrottleState},
+    };
+
+    #[test]
+    fn throttle_state_is_rendered_in_the_prometheus_text_format() {
+        let metrics = render_throttle(&ThrottleState {
+            providers: vec![ProviderThrottleState {
+                provider: EmailProvider::Mailgun,
+                waiting: 2,
+                messages: 120,
+                waited: Duration::from_millis(1500),
+                backlog: Duration::from_millis(250),
+            }],
+            domains: vec![("gmail.com".to_owned(), Duration::from_secs(3))],
+        });
+
+        assert!(metrics.contains("# TYPE email_throttle_messages_total counter\n"));
+        assert!(metrics.contains("email_throttle_messages_total{provider=\"mailgun\"} 120\n"));
+        assert!(metrics.contains("email_throttle_waited_seconds_total{provider=\"mailgun\"} 1.5\n"));
+        assert!(metrics.contains("email_throttle_waiting{provider=\"mailgun\"} 2\n"));
+        assert!(metrics.contains("email_throttle_backlog_seconds{provider=\"mailgun\"} 0.25\n"));
+        assert!(metrics.contains("email_throttle_domain_backlog_seconds{domain=\"gmail.com\"} 3\n"));
+    }
+}
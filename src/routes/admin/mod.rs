@@ -0,0 +1,65 @@
+mod audit_events;
+pub use audit_events::*;
+
+mod email_domains;
+pub use email_domains::*;
+
+mod lists;
+pub use lists::*;
+
+mod metrics;
+pub use metrics::*;
+
+mod subscribers;
+pub use subscribers::*;
+
+mod subscribers_csv;
+pub use subscribers_csv::*;
+
+mod webhooks;
+pub use webhooks::*;
+
+use axum::{
+    extract::State,
+    http::{header, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use secrecy::ExposeSecret;
+
+use crate::startup::AppState;
+
+/// Reject requests that don't carry the admin bearer token.
+pub async fn require_admin<B>(
+    State(state): State<AppState>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    match provided {
+        Some(token) if constant_time_eq(token, state.admin_token.expose_secret()) => {
+            next.run(request).await
+        }
+        _ => (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+        )
+            .into_response(),
+    }
+}
+
+/// Compare two strings without short-circuiting on the first difference,
+/// so response timings don't leak how much of the token was right.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -0,0 +1,347 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::PgPool;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent, Target},
+    domain::{
+        subscriber_attributes::SubscriberAttributes, subscription_status::SubscriptionStatus,
+    },
+    error::AdminError,
+    routes::{get_attribute_schema, get_status_history, StatusChange, SubscriberRecord},
+    startup::AppState,
+    tenant::Tenant,
+};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SubscriberSearch {
+    /// Only return subscribers with this status.
+    status: Option<String>,
+    /// Only return subscribers whose email contains this string, ignoring case.
+    email: Option<String>,
+    /// Only return subscribers who signed up at or after this instant.
+    subscribed_after: Option<DateTime<Utc>>,
+    /// Only return subscribers who signed up strictly before this instant.
+    subscribed_before: Option<DateTime<Utc>>,
+    /// The `next_cursor` of the previous page.
+    cursor: Option<String>,
+    /// How many subscribers to return, 50 by default and 500 at most.
+    limit: Option<i64>,
+}
+
+/// A page of subscribers, most recent first.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct SubscriberPage {
+    pub subscribers: Vec<SubscriberRecord>,
+    /// Pass it back as `cursor` to get the next page. Absent on the last page.
+    pub next_cursor: Option<String>,
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct SubscriberDetail {
+    pub subscriber: SubscriberRecord,
+    pub subscription_tokens: Vec<String>,
+    pub status_history: Vec<StatusChange>,
+}
+
+/// Position of the last subscriber of a page in the `(subscribed_at, id)` ordering.
+///
+/// Encoded as `<microseconds since epoch>.<id>` so clients can treat it as opaque.
+#[derive(Debug, PartialEq)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        format!("{}.{}", self.subscribed_at.timestamp_micros(), self.id)
+    }
+
+    fn decode(value: &str) -> Result<Self, String> {
+        let invalid = || format!("{value} is not a valid cursor.");
+
+        let (micros, id) = value.split_once('.').ok_or_else(invalid)?;
+        let micros = micros.parse::<i64>().map_err(|_| invalid())?;
+        let subscribed_at = Utc.timestamp_micros(micros).single().ok_or_else(invalid)?;
+        let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+
+        Ok(Self { subscribed_at, id })
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers",
+    tag = "admin",
+    params(SubscriberSearch),
+    responses(
+        (status = 200, description = "The subscribers matching the filters", body = SubscriberPage),
+        (status = 400, description = "A filter or the cursor is invalid"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Search subscribers", skip(state, tenant))]
+pub async fn search_subscribers(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(search): Query<SubscriberSearch>,
+) -> Result<impl IntoResponse, AdminError> {
+    let status = search
+        .status
+        .as_deref()
+        .map(SubscriptionStatus::try_from)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+    let cursor = search
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+    let limit = search
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // Fetch one extra row to know whether there is a next page.
+    let mut subscribers = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+    SELECT id, email, name, status, subscribed_at, locale, frequency, unsubscribed_lists,
+      paused_until, last_digest_at, attributes
+    FROM subscriptions
+    WHERE tenant = $1
+      AND ($2::text IS NULL OR status = $2)
+      AND ($3::text IS NULL OR email ILIKE $3)
+      AND ($4::timestamptz IS NULL OR subscribed_at >= $4)
+      AND ($5::timestamptz IS NULL OR subscribed_at < $5)
+      AND ($6::timestamptz IS NULL OR (subscribed_at, id) < ($6, $7::uuid))
+    ORDER BY subscribed_at DESC, id DESC
+    LIMIT $8
+        "#,
+        tenant.slug,
+        status.as_ref().map(SubscriptionStatus::as_str),
+        search.email.as_deref().map(contains_pattern),
+        search.subscribed_after,
+        search.subscribed_before,
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        limit + 1,
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .context("Failed to search subscribers")?;
+
+    let next_cursor = if subscribers.len() > usize::try_from(limit).unwrap_or(usize::MAX) {
+        subscribers.pop();
+        subscribers.last().map(|last| {
+            Cursor {
+                subscribed_at: last.subscribed_at,
+                id: last.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    Ok(Json(SubscriberPage {
+        subscribers,
+        next_cursor,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers/{subscriber_id}",
+    tag = "admin",
+    params(("subscriber_id" = Uuid, Path, description = "The subscriber's id")),
+    responses(
+        (status = 200, description = "The subscriber, their tokens and status history", body = SubscriberDetail),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 404, description = "There is no such subscriber"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Get subscriber detail", skip(state, tenant))]
+pub async fn get_subscriber(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(subscriber_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let detail = get_subscriber_detail(&state.db_pool, &tenant.slug, subscriber_id)
+        .await
+        .context("Failed to fetch the subscriber")?
+        .ok_or(AdminError::NotFound)?;
+
+    Ok(Json(detail))
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/subscribers/{subscriber_id}/attributes",
+    tag = "admin",
+    params(("subscriber_id" = Uuid, Path, description = "The subscriber's id")),
+    request_body(content = Object, description = "Replaces every attribute, e.g. `{\"country\": \"US\"}`"),
+    responses(
+        (status = 204, description = "The attributes were replaced"),
+        (status = 400, description = "An attribute isn't collected by any list, or has a value of the wrong type"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 404, description = "There is no such subscriber"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Set subscriber attributes", skip(state, tenant, attributes))]
+pub async fn put_subscriber_attributes(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(subscriber_id): Path<Uuid>,
+    Json(attributes): Json<serde_json::Map<String, serde_json::Value>>,
+) -> Result<impl IntoResponse, AdminError> {
+    let schema = get_attribute_schema(&state.db_pool, &tenant.slug).await?;
+    let attributes = SubscriberAttributes::parse(attributes, &schema)
+        .map_err(|e| AdminError::ValidationError(e.to_string()))?
+        .into_json();
+    // Only the names are audited: the values are personal data.
+    let names: Vec<_> = attributes
+        .as_object()
+        .map(|attributes| attributes.keys().cloned().collect())
+        .unwrap_or_default();
+
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET attributes = $3 WHERE tenant = $1 AND id = $2"#,
+        tenant.slug,
+        subscriber_id,
+        attributes,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to save the subscriber's attributes")?
+    .rows_affected();
+    if updated == 0 {
+        return Err(AdminError::NotFound);
+    }
+    audit::record(
+        &mut transaction,
+        &tenant.slug,
+        AuditEvent::new(
+            Actor::Admin,
+            Action::UpdateAttributes,
+            Target::Subscriber(subscriber_id),
+        )
+        .details(serde_json::json!({ "attributes": names })),
+    )
+    .await
+    .context("Failed to record the attributes change in the audit log")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(name = "Collect subscriber detail", skip(pool))]
+async fn get_subscriber_detail(
+    pool: &PgPool,
+    tenant: &str,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetail>, sqlx::Error> {
+    let Some(subscriber) = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+    SELECT id, email, name, status, subscribed_at, locale, frequency, unsubscribed_lists,
+      paused_until, last_digest_at, attributes
+    FROM subscriptions
+    WHERE tenant = $1 AND id = $2
+        "#,
+        tenant,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    let subscription_tokens = sqlx::query!(
+        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.subscription_token)
+    .collect();
+
+    let status_history = get_status_history(pool, subscriber_id).await?;
+
+    Ok(Some(SubscriberDetail {
+        subscriber,
+        subscription_tokens,
+        status_history,
+    }))
+}
+
+/// Build an `ILIKE` pattern matching `value` anywhere, treating its wildcards literally.
+fn contains_pattern(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{contains_pattern, Cursor};
+    use chrono::{TimeZone, Utc};
+    use claims::{assert_err, assert_ok_eq};
+    use uuid::Uuid;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp_micros(1_688_847_425_123_456).unwrap(),
+            id: Uuid::new_v4(),
+        };
+        assert_ok_eq!(Cursor::decode(&cursor.encode()), cursor);
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for cursor in ["", "123", "abc.def", "123.not-a-uuid"] {
+            assert_err!(Cursor::decode(cursor));
+        }
+    }
+
+    #[test]
+    fn wildcards_in_email_searches_are_escaped() {
+        assert_eq!(contains_pattern("a_b%c\\"), "%a\\_b\\%c\\\\%");
+    }
+}
@@ -0,0 +1,70 @@
+use axum::{
+    body::{Bytes, StreamBody},
+    extract::State,
+    http::header,
+    response::IntoResponse,
+    Extension, Json,
+};
+use std::sync::Arc;
+
+use crate::{audit::Actor, error::AdminError, startup::AppState, subscriber_csv, tenant::Tenant};
+
+/// Largest CSV file accepted by the import endpoint.
+pub const MAX_IMPORT_SIZE: usize = 50 * 1024 * 1024;
+
+#[utoipa::path(
+    post,
+    path = "/admin/subscribers/import",
+    tag = "admin",
+    request_body(content = String, content_type = "text/csv", description = "Columns: `email,name,status,subscribed_at`"),
+    responses(
+        (status = 200, description = "How many rows were imported, and why the others weren't", body = subscriber_csv::ImportReport),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Import subscribers", skip(state, tenant, body))]
+pub async fn import_subscribers(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    body: Bytes,
+) -> Result<impl IntoResponse, AdminError> {
+    let report = subscriber_csv::import(
+        &state.db_pool,
+        &state.email_policy,
+        &tenant.slug,
+        &body[..],
+        Actor::Admin,
+    )
+    .await?;
+    Ok(Json(report))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/subscribers/export",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every subscriber, as CSV", content_type = "text/csv", body = String),
+        (status = 401, description = "The admin token is missing or wrong"),
+    ),
+    security(("admin_token" = []))
+)]
+#[tracing::instrument(name = "Export subscribers", skip(state, tenant))]
+pub async fn export_subscribers(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"subscribers.csv\"",
+            ),
+        ],
+        StreamBody::new(subscriber_csv::export(state.db_pool, tenant.slug.clone())),
+    )
+}
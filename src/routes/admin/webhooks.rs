@@ -0,0 +1,269 @@
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    domain::webhook_event_type::WebhookEventType, error::AdminError, startup::AppState,
+    tenant::Tenant,
+};
+
+/// How many deliveries the delivery log shows, most recent first.
+const DELIVERY_LOG_SIZE: i64 = 100;
+
+/// Where the events of this newsletter are sent.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct WebhookEndpointRecord {
+    pub id: Uuid,
+    pub url: String,
+    /// E.g. `subscriber.created`, `subscriber.confirmed`, `subscriber.unsubscribed` or
+    /// `issue.published`.
+    pub events: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A new endpoint, along with the secret its requests are signed with. The secret is only ever
+/// shown here.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct WebhookEndpointCreated {
+    pub id: Uuid,
+    pub url: String,
+    pub events: Vec<String>,
+    /// Key of the HMAC-SHA256 in the `Webhook-Signature` header.
+    pub secret: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct WebhookEndpointBody {
+    /// An `http` or `https` URL.
+    pub url: String,
+    /// The events to POST, at least one of `subscriber.created`, `subscriber.confirmed`,
+    /// `subscriber.unsubscribed` and `issue.published`.
+    pub events: Vec<String>,
+}
+
+/// One event sent, or still to be sent, to an endpoint.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct WebhookDeliveryRecord {
+    pub event_id: Uuid,
+    pub event_type: String,
+    /// `pending` until it is delivered, or `failed` once we gave up.
+    pub status: String,
+    pub attempts: i32,
+    /// When the next attempt is due, if `pending`.
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    /// The HTTP status the endpoint last responded with, if it responded.
+    pub last_response_status: Option<i16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// The body sent to the endpoint.
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every webhook endpoint", body = Vec<WebhookEndpointRecord>),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "List webhook endpoints", skip(state, tenant))]
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+) -> Result<impl IntoResponse, AdminError> {
+    let endpoints = sqlx::query_as!(
+        WebhookEndpointRecord,
+        r#"
+    SELECT id, url, events, created_at FROM webhook_endpoints
+    WHERE tenant = $1
+    ORDER BY created_at, id
+        "#,
+        tenant.slug,
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .context("Failed to list webhook endpoints")?;
+
+    Ok(Json(endpoints))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "admin",
+    request_body = WebhookEndpointBody,
+    responses(
+        (status = 201, description = "The endpoint was added", body = WebhookEndpointCreated),
+        (status = 400, description = "The URL or one of the events is invalid"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Add a webhook endpoint", skip(state, tenant))]
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Json(body): Json<WebhookEndpointBody>,
+) -> Result<impl IntoResponse, AdminError> {
+    match url::Url::parse(&body.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        _ => {
+            return Err(AdminError::ValidationError(format!(
+                "{} is not a valid http or https URL.",
+                body.url
+            )))
+        }
+    }
+    if body.events.is_empty() {
+        return Err(AdminError::ValidationError(
+            "Subscribe to at least one event.".to_owned(),
+        ));
+    }
+    let mut events = Vec::with_capacity(body.events.len());
+    for event in &body.events {
+        let event =
+            WebhookEventType::try_from(event.as_str()).map_err(AdminError::ValidationError)?;
+        if !events.contains(&event.as_str()) {
+            events.push(event.as_str());
+        }
+    }
+
+    let secret = generate_secret();
+    let endpoint = sqlx::query!(
+        r#"
+    INSERT INTO webhook_endpoints (id, tenant, url, secret, events)
+    VALUES ($1, $2, $3, $4, $5)
+    RETURNING id, url, events, created_at
+        "#,
+        Uuid::new_v4(),
+        tenant.slug,
+        body.url,
+        secret,
+        &events as &[&str],
+    )
+    .fetch_one(&state.db_pool)
+    .await
+    .context("Failed to save the webhook endpoint")?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(WebhookEndpointCreated {
+            id: endpoint.id,
+            url: endpoint.url,
+            events: endpoint.events,
+            secret,
+            created_at: endpoint.created_at,
+        }),
+    ))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/webhooks/{webhook_id}",
+    tag = "admin",
+    params(("webhook_id" = Uuid, Path, description = "The endpoint's id")),
+    responses(
+        (status = 204, description = "The endpoint was removed, along with its delivery log"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 404, description = "There is no such endpoint"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Delete a webhook endpoint", skip(state, tenant))]
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let deleted = sqlx::query!(
+        r#"DELETE FROM webhook_endpoints WHERE tenant = $1 AND id = $2"#,
+        tenant.slug,
+        webhook_id,
+    )
+    .execute(&state.db_pool)
+    .await
+    .context("Failed to delete the webhook endpoint")?
+    .rows_affected();
+
+    if deleted == 0 {
+        return Err(AdminError::NotFound);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks/{webhook_id}/deliveries",
+    tag = "admin",
+    params(("webhook_id" = Uuid, Path, description = "The endpoint's id")),
+    responses(
+        (status = 200, description = "The latest deliveries to the endpoint, most recent first", body = Vec<WebhookDeliveryRecord>),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 404, description = "There is no such endpoint"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "List webhook deliveries", skip(state, tenant))]
+pub async fn list_webhook_deliveries(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Path(webhook_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AdminError> {
+    let exists = sqlx::query!(
+        r#"SELECT id FROM webhook_endpoints WHERE tenant = $1 AND id = $2"#,
+        tenant.slug,
+        webhook_id,
+    )
+    .fetch_optional(&state.db_pool)
+    .await
+    .context("Failed to look up the webhook endpoint")?;
+    if exists.is_none() {
+        return Err(AdminError::NotFound);
+    }
+
+    let deliveries = sqlx::query_as!(
+        WebhookDeliveryRecord,
+        r#"
+    SELECT event_id, event_type, status, attempts, next_attempt_at, last_attempt_at,
+      last_response_status, last_error, created_at, payload
+    FROM webhook_deliveries
+    WHERE endpoint_id = $1
+    ORDER BY created_at DESC, event_id
+    LIMIT $2
+        "#,
+        webhook_id,
+        DELIVERY_LOG_SIZE,
+    )
+    .fetch_all(&state.db_pool)
+    .await
+    .context("Failed to list webhook deliveries")?;
+
+    Ok(Json(deliveries))
+}
+
+/// 32 random bytes, hex-encoded.
+fn generate_secret() -> String {
+    hex::encode(rand::thread_rng().gen::<[u8; 32]>())
+}
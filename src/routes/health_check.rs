@@ -1,5 +1,67 @@
-use axum::{http, response::IntoResponse};
+use std::collections::BTreeMap;
 
+use axum::{extract::State, http, response::IntoResponse, Json};
+
+use crate::startup::AppState;
+
+#[utoipa::path(
+    get,
+    path = "/health_check",
+    tag = "health",
+    responses((status = 200, description = "The application is up"))
+)]
 pub async fn health_check() -> impl IntoResponse {
     http::StatusCode::OK
 }
+
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct Readiness {
+    /// Whether the database answers.
+    pub database: bool,
+    /// The state of each tenant's email circuit breaker, by slug: `closed`, `open` while sends
+    /// fail fast, or `half_open` while a trial send decides. Emails that can't be sent are
+    /// deferred, so an open circuit doesn't make us unready.
+    pub email_circuits: BTreeMap<String, String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "The application can serve requests", body = Readiness),
+        (status = 503, description = "The database is unreachable", body = Readiness),
+    )
+)]
+#[tracing::instrument(name = "Check readiness", skip(state))]
+pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    let database = match sqlx::query("SELECT 1").execute(&state.db_pool).await {
+        Ok(_) => true,
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "The database is unreachable");
+            false
+        }
+    };
+    let email_circuits = state
+        .tenants
+        .iter()
+        .map(|tenant| {
+            (
+                tenant.slug.clone(),
+                tenant.email_client.circuit_state().as_str().to_owned(),
+            )
+        })
+        .collect();
+    let status = if database {
+        http::StatusCode::OK
+    } else {
+        http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(Readiness {
+            database,
+            email_circuits,
+        }),
+    )
+}
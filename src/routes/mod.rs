@@ -1,11 +1,23 @@
+mod admin;
+pub use admin::*;
+
 mod health_check;
 pub use health_check::*;
 
 mod newsletters;
 pub use newsletters::*;
 
+mod preferences;
+pub use preferences::*;
+
+mod subscriber_data;
+pub use subscriber_data::*;
+
 mod subscription;
 pub use subscription::*;
 
 mod subscription_confirm;
 pub use subscription_confirm::*;
+
+mod tracking;
+pub use tracking::*;
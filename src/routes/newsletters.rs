@@ -1,90 +1,409 @@
 use anyhow::Context;
-use axum::{extract::State, response::IntoResponse, Json};
-use hyper::StatusCode;
-use sqlx::PgPool;
+use rand::seq::SliceRandom;
+use std::sync::Arc;
 
-use crate::{domain::subscriber_email::SubscriberEmail, error::PublishError, startup::AppState};
+use axum::{extract::State, response::IntoResponse, Extension, Json};
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+use uuid::Uuid;
 
-#[derive(serde::Deserialize)]
+use crate::{
+    audit::{self, Action, Actor, AuditEvent, Target},
+    content::IssueContent,
+    domain::{subscriber_email::SubscriberEmail, webhook_event_type::WebhookEventType},
+    error::PublishError,
+    newsletter_delivery,
+    routes::{generate_subscription_token, get_attribute_schema},
+    segment::Segment,
+    startup::AppState,
+    subject_test::SubjectTest,
+    tenant::Tenant,
+    webhooks,
+};
+
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct BodyData {
+    /// `%recipient.name%`, here or in the content, is replaced by each subscriber's name.
     title: String,
-    content: Content,
+    /// The issue as HTML. Either this or `markdown`.
+    #[serde(default)]
+    content: Option<Content>,
+    /// The issue as Markdown, rendered into our layout. Either this or `content`.
+    #[serde(default)]
+    markdown: Option<String>,
+    /// The slug of the list to publish to, skipping subscribers who opted out of it. Left out,
+    /// the issue goes to every subscriber.
+    #[serde(default)]
+    list: Option<String>,
+    /// Only send the issue to the subscribers matching this filter on their attributes, e.g.
+    /// `country = "US" AND plan IN ("pro", "team")`. Comparisons are `=`, `!=`, `<`, `<=`, `>`,
+    /// `>=` and `IN`, combined with `AND`, `OR`, `NOT` and parentheses.
+    #[serde(default)]
+    segment: Option<String>,
+    /// Try other subjects than `title` on a sample of the recipients first.
+    #[serde(default)]
+    subject_test: Option<SubjectTestBody>,
 }
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, utoipa::ToSchema)]
 pub struct Content {
     html: String,
-    text: String,
+    /// Derived from `html` when left out.
+    #[serde(default)]
+    text: Option<String>,
+}
+
+/// Send each subject to a random share of the recipients, then the subject that did best to
+/// everyone else once the test window closes.
+#[derive(serde::Deserialize, utoipa::ToSchema)]
+pub struct SubjectTestBody {
+    /// Between 2 and 5 subjects.
+    pub subjects: Vec<String>,
+    /// The share of the recipients to test the subjects on, between 1 and 99.
+    pub cohort_percentage: u8,
+    /// `open_rate`, the default, or `click_rate`.
+    #[serde(default)]
+    pub winner_by: Option<String>,
 }
 
+#[utoipa::path(
+    post,
+    path = "/newsletters",
+    tag = "newsletters",
+    request_body = BodyData,
+    responses(
+        (status = 200, description = "The issue was published. It is being sent to every confirmed subscriber who wants it now, or to the test cohort", body = PublishReport),
+        (status = 400, description = "There is no such list, or the segment or the subject test is invalid"),
+        (status = 401, description = "The admin token is missing or wrong"),
+        (status = 422, description = "The payload is missing a field"),
+        (status = 500, description = "An unexpected error occurred"),
+    ),
+    security(("admin_token" = []))
+)]
 pub async fn publish_newsletter(
     State(app): State<AppState>,
-    Json(body): Json<BodyData>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Json(mut body): Json<BodyData>,
 ) -> Result<impl IntoResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&app.db_pool).await?;
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                app.email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                    // We record the error chain as a structured field
-                    // on the log record.
-                    error.cause_chain = ?error,
-                    // Using `\` to split a long string literal over
-                    // two lines, without creating a `\n` character.
-                    "Skipping a confirmed subscriber. \
-                    Their stored contact details are invalid",
-                );
-            }
-        }
+    if let Some(list) = &body.list {
+        check_list(&app.db_pool, &tenant.slug, list).await?;
+    }
+    let segment = parse_segment(&app.db_pool, &tenant.slug, body.segment.as_deref()).await?;
+    let subject_test = body
+        .subject_test
+        .take()
+        .map(SubjectTest::try_from)
+        .transpose()
+        .map_err(PublishError::ValidationError)?;
+    let content = render_content(&mut body, &tenant.base_url)?;
+    let mut subscribers = skip_invalid(
+        get_confirmed_subscribers(
+            &app.db_pool,
+            &tenant.slug,
+            body.list.as_deref(),
+            segment.as_ref(),
+        )
+        .await?,
+    );
+
+    let mut transaction = app
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let newsletter_issue_id = insert_newsletter_issue(
+        &mut transaction,
+        &tenant.slug,
+        &body,
+        &content,
+        subject_test
+            .as_ref()
+            .map(|test| (test, app.subject_test_window)),
+    )
+    .await
+    .context("Failed to store newsletter issue details")?;
+    let mut details = serde_json::json!({ "recipients": subscribers.len() });
+    if subject_test.is_some() {
+        subscribers.shuffle(&mut rand::thread_rng());
+    }
+    // Who gets which subject, `None` for those waiting for the winner, or everyone when no
+    // subject is tested.
+    let deliveries = insert_deliveries(
+        &mut transaction,
+        newsletter_issue_id,
+        subject_test.as_ref(),
+        &subscribers,
+    )
+    .await
+    .context("Failed to store who the newsletter issue goes to")?;
+    if subject_test.is_some() {
+        details["cohort"] = deliveries.iter().filter(|d| d.is_some()).count().into();
+    }
+    record_publication(
+        &mut transaction,
+        &tenant.slug,
+        newsletter_issue_id,
+        &body,
+        details,
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    newsletter_delivery::deliver_soon(
+        app.db_pool.clone(),
+        app.tenants.clone(),
+        app.newsletter_delivery_retry_delay,
+    );
+
+    Ok(Json(PublishReport {
+        recipients: subscribers.len(),
+    }))
+}
+
+/// Who the issue goes to. It is sent to them in the background.
+#[derive(serde::Serialize, utoipa::ToSchema)]
+pub struct PublishReport {
+    /// Everyone the issue goes to, those waiting for the winner of a subject test included.
+    pub recipients: usize,
+}
+
+/// The issue's HTML and plain text, from either `content` or `markdown`.
+fn render_content(body: &mut BodyData, base_url: &str) -> Result<IssueContent, PublishError> {
+    match (body.content.take(), body.markdown.take()) {
+        (Some(content), None) => IssueContent::from_html(&content.html, content.text, base_url),
+        (None, Some(markdown)) => IssueContent::from_markdown(&markdown, base_url),
+        _ => Err("Provide the issue as either `content` or `markdown`.".to_owned()),
     }
+    .map_err(PublishError::ValidationError)
+}
 
-    Ok(StatusCode::OK)
+/// Log the publication in the audit log, and tell the webhook endpoints about it.
+async fn record_publication(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    newsletter_issue_id: Uuid,
+    body: &BodyData,
+    details: serde_json::Value,
+) -> Result<(), anyhow::Error> {
+    let mut data = serde_json::json!({
+        "id": newsletter_issue_id,
+        "title": body.title,
+        "list": body.list,
+        "segment": body.segment,
+    });
+    data["recipients"] = details["recipients"].clone();
+    audit::record(
+        transaction,
+        tenant,
+        AuditEvent::new(
+            Actor::Admin,
+            Action::Publish,
+            Target::NewsletterIssue(newsletter_issue_id),
+        )
+        .details(details),
+    )
+    .await
+    .context("Failed to record the publication in the audit log")?;
+    webhooks::enqueue(transaction, tenant, WebhookEventType::IssuePublished, data)
+        .await
+        .context("Failed to enqueue the issue.published webhook event")?;
+    Ok(())
+}
+
+async fn check_list(pool: &PgPool, tenant: &str, list: &str) -> Result<(), PublishError> {
+    let exists = sqlx::query!(
+        r#"SELECT slug FROM mailing_lists WHERE tenant = $1 AND slug = $2"#,
+        tenant,
+        list,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the mailing list")?
+    .is_some();
+    if exists {
+        Ok(())
+    } else {
+        Err(PublishError::ValidationError(format!(
+            "{list} is not one of our lists."
+        )))
+    }
+}
+
+/// Parse `segment`, checking it only uses the attributes our lists collect.
+async fn parse_segment(
+    pool: &PgPool,
+    tenant: &str,
+    segment: Option<&str>,
+) -> Result<Option<Segment>, PublishError> {
+    let Some(segment) = segment else {
+        return Ok(None);
+    };
+    let segment = Segment::parse(segment).map_err(PublishError::ValidationError)?;
+    segment
+        .check(&get_attribute_schema(pool, tenant).await?)
+        .map_err(PublishError::ValidationError)?;
+    Ok(Some(segment))
+}
+
+#[tracing::instrument(name = "Save newsletter issue", skip_all)]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    body: &BodyData,
+    content: &IssueContent,
+    subject_test: Option<(&SubjectTest, std::time::Duration)>,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let (subject_variants, winner_metric, window) = match subject_test {
+        Some((test, window)) => (
+            Some(&test.subjects),
+            Some(test.winner_metric.as_str()),
+            Some(window.as_secs_f64()),
+        ),
+        None => (None, None, None),
+    };
+    sqlx::query!(
+        r#"
+    INSERT INTO newsletter_issues
+      (newsletter_issue_id, tenant, title, text_content, html_content, list, segment,
+       published_at, subject_variants, winner_metric, winner_due_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, now(), $8, $9, now() + make_interval(secs => $10))
+        "#,
+        newsletter_issue_id,
+        tenant,
+        body.title,
+        content.text,
+        content.html,
+        body.list,
+        body.segment,
+        subject_variants.map(Vec::as_slice),
+        winner_metric,
+        window,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(newsletter_issue_id)
+}
+
+/// Record who an issue goes to, returning the variant and tracking token of each of
+/// `subscribers`, `None` outside the cohort of the subject test, if there is one.
+#[tracing::instrument(name = "Save newsletter deliveries", skip_all)]
+async fn insert_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    subject_test: Option<&SubjectTest>,
+    subscribers: &[ConfirmedSubscriber],
+) -> Result<Vec<Option<(i16, String)>>, sqlx::Error> {
+    let deliveries: Vec<_> = match subject_test {
+        Some(subject_test) => subject_test
+            .assign_variants(subscribers.len())
+            .into_iter()
+            .map(|variant| variant.map(|variant| (variant, generate_subscription_token())))
+            .collect(),
+        None => vec![None; subscribers.len()],
+    };
+    let subscriber_ids: Vec<_> = subscribers.iter().map(|s| s.id).collect();
+    let variants: Vec<_> = deliveries.iter().map(|d| d.as_ref().map(|d| d.0)).collect();
+    let tracking_tokens: Vec<_> = deliveries
+        .iter()
+        .map(|d| d.as_ref().map(|d| d.1.clone()))
+        .collect();
+    // Due right away, for the delivery worker to send, unless waiting for the winner.
+    sqlx::query!(
+        r#"
+    INSERT INTO newsletter_deliveries
+      (newsletter_issue_id, subscriber_id, variant, tracking_token, next_attempt_at)
+    SELECT $1, r.*, CASE WHEN r.variant IS NOT NULL OR NOT $5 THEN now() END
+    FROM UNNEST($2::uuid[], $3::smallint[], $4::text[]) AS r(subscriber_id, variant, tracking_token)
+        "#,
+        newsletter_issue_id,
+        &subscriber_ids,
+        &variants as &[Option<i16>],
+        &tracking_tokens as &[Option<String>],
+        subject_test.is_some(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(deliveries)
+}
+
+/// Drop the subscribers we can't email, logging why.
+fn skip_invalid(
+    subscribers: Vec<Result<ConfirmedSubscriber, anyhow::Error>>,
+) -> Vec<ConfirmedSubscriber> {
+    subscribers
+        .into_iter()
+        .filter_map(|subscriber| {
+            subscriber
+                .map_err(|error| {
+                    tracing::warn!(
+                        // We record the error chain as a structured field
+                        // on the log record.
+                        error.cause_chain = ?error,
+                        // Using `\` to split a long string literal over
+                        // two lines, without creating a `\n` character.
+                        "Skipping a confirmed subscriber. \
+                        Their stored contact details are invalid",
+                    );
+                })
+                .ok()
+        })
+        .collect()
 }
 
 struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+    id: Uuid,
 }
 
+/// Confirmed subscribers who want this issue now: not on pause, not waiting for their weekly
+/// digest, not opted out of `list`, and in `segment`.
 #[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
 async fn get_confirmed_subscribers(
     pool: &PgPool,
+    tenant: &str,
+    list: Option<&str>,
+    segment: Option<&Segment>,
 ) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
     // We only need `Row` to map the data coming out of this query.
     // Nesting its definition inside the function itself is a simple way
     // to clearly communicate this coupling (and to ensure it doesn't
     // get used elsewhere by mistake).
+    #[derive(sqlx::FromRow)]
     struct Row {
+        id: Uuid,
         email: String,
     }
 
-    let rows = sqlx::query_as!(
-        Row,
-        r#"
-          SELECT email
+    let mut query = QueryBuilder::new(
+        r"
+          SELECT id, email
           FROM subscriptions
           WHERE status = 'confirmed'
-        "#,
-    )
-    .fetch_all(pool)
-    .await?;
+            AND frequency = 'every_issue'
+            AND (paused_until IS NULL OR paused_until <= now())
+            AND tenant = ",
+    );
+    query.push_bind(tenant);
+    if let Some(list) = list {
+        query
+            .push(" AND NOT (")
+            .push_bind(list)
+            .push(" = ANY(unsubscribed_lists))");
+    }
+    if let Some(segment) = segment {
+        query.push(" AND ");
+        segment.push_predicate(&mut query);
+    }
+    let rows: Vec<Row> = query.build_query_as().fetch_all(pool).await?;
 
     let confirmed_subscribers = rows
         .into_iter()
         // No longer using `filter_map`!
         .map(|r| match SubscriberEmail::try_from(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
+            Ok(_) => Ok(ConfirmedSubscriber { id: r.id }),
             Err(error) => Err(anyhow::anyhow!(error)),
         })
         .collect();
@@ -0,0 +1,396 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse},
+    Extension, Form,
+};
+use chrono::{DateTime, Utc};
+use std::{fmt::Write, sync::Arc};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent, Target},
+    domain::{delivery_frequency::DeliveryFrequency, subscriber_name::SubscriberName},
+    error::PreferencesError,
+    i18n::{translate, Locale},
+    routes::{get_mailing_lists, MailingListRecord},
+    startup::AppState,
+    tenant::Tenant,
+};
+
+/// The longest a subscriber can pause deliveries for in one go.
+const MAX_PAUSE_DAYS: u32 = 365;
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams, utoipa::ToSchema)]
+#[into_params(parameter_in = Query)]
+pub struct PreferencesParameters {
+    preferences_token: String,
+}
+
+/// Submitted by the preference page.
+#[derive(Debug, Default, utoipa::ToSchema)]
+pub struct PreferencesForm {
+    preferences_token: String,
+    name: String,
+    /// `every_issue` or `weekly_digest`.
+    frequency: String,
+    /// The slugs of the lists to receive, repeating the field for each of them. Lists left out
+    /// are unsubscribed from.
+    lists: Vec<String>,
+    /// Pause deliveries for this many days from now, or `0` to resume them. Leave it out to keep
+    /// the current pause, if any.
+    pause_days: Option<u32>,
+}
+
+/// `serde_urlencoded` can't collect repeated fields, so the checked lists are gathered by hand.
+impl TryFrom<Vec<(String, String)>> for PreferencesForm {
+    type Error = String;
+
+    fn try_from(fields: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut form = PreferencesForm::default();
+        for (key, value) in fields {
+            match key.as_str() {
+                "preferences_token" => form.preferences_token = value,
+                "name" => form.name = value,
+                "frequency" => form.frequency = value,
+                "lists" => form.lists.push(value),
+                "pause_days" if value.is_empty() => {}
+                "pause_days" => {
+                    form.pause_days = match value.parse() {
+                        Ok(days) if days <= MAX_PAUSE_DAYS => Some(days),
+                        _ => {
+                            return Err(format!(
+                                "{value} is not a valid pause. \
+                                Use a number of days between 0 and {MAX_PAUSE_DAYS}."
+                            ))
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(form)
+    }
+}
+
+/// What a subscriber chose on their preference page.
+struct Preferences {
+    subscriber_id: Uuid,
+    name: String,
+    frequency: DeliveryFrequency,
+    unsubscribed_lists: Vec<String>,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/preferences",
+    tag = "subscriber data",
+    params(PreferencesParameters),
+    responses(
+        (status = 200, description = "A page to change the subscriber's name, lists, frequency and pause", content_type = "text/html"),
+        (status = 400, description = "The `preferences_token` query parameter is missing"),
+        (status = 401, description = "The token is unknown"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Show the preferences page", skip(state, params, tenant))]
+pub async fn show_preferences(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(params): Query<PreferencesParameters>,
+) -> Result<impl IntoResponse, PreferencesError> {
+    let preferences = get_preferences(&state.db_pool, &tenant.slug, &params.preferences_token)
+        .await?
+        .ok_or(PreferencesError::UnknownToken)?;
+    let lists = get_mailing_lists(&state.db_pool, &tenant.slug)
+        .await
+        .context("Failed to list mailing lists")?;
+
+    Ok(render_page(
+        &params.preferences_token,
+        &preferences,
+        &lists,
+        None,
+    ))
+}
+
+#[utoipa::path(
+    post,
+    path = "/preferences",
+    tag = "subscriber data",
+    request_body(content = PreferencesForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The preferences were saved, the page is shown again", content_type = "text/html"),
+        (status = 400, description = "The name, frequency, lists or pause are invalid"),
+        (status = 401, description = "The token is unknown"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Update preferences", skip(state, fields, tenant))]
+pub async fn update_preferences(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, PreferencesError> {
+    let form = PreferencesForm::try_from(fields).map_err(PreferencesError::ValidationError)?;
+    let current = get_preferences(&state.db_pool, &tenant.slug, &form.preferences_token)
+        .await?
+        .ok_or(PreferencesError::UnknownToken)?;
+
+    let name = SubscriberName::try_from(form.name.as_str())
+        .map_err(|e| PreferencesError::ValidationError(e.into()))?;
+    let frequency = DeliveryFrequency::try_from(form.frequency.as_str())
+        .map_err(PreferencesError::ValidationError)?;
+    let paused_until = match form.pause_days {
+        None => current.paused_until,
+        Some(0) => None,
+        Some(days) => Some(Utc::now() + chrono::Duration::days(days.into())),
+    };
+
+    let lists = get_mailing_lists(&state.db_pool, &tenant.slug)
+        .await
+        .context("Failed to list mailing lists")?;
+    if let Some(unknown) = form
+        .lists
+        .iter()
+        .find(|slug| !lists.iter().any(|list| &list.slug == *slug))
+    {
+        return Err(PreferencesError::ValidationError(format!(
+            "{unknown} is not one of our lists."
+        )));
+    }
+    // Storing opt-outs rather than opt-ins means subscribers get lists created later on.
+    let unsubscribed_lists: Vec<String> = lists
+        .iter()
+        .filter(|list| !form.lists.contains(&list.slug))
+        .map(|list| list.slug.clone())
+        .collect();
+
+    let preferences = Preferences {
+        subscriber_id: current.subscriber_id,
+        name: name.as_ref().to_owned(),
+        frequency,
+        unsubscribed_lists,
+        paused_until,
+    };
+    save_preferences(&state.db_pool, &tenant.slug, &preferences).await?;
+
+    Ok(render_page(
+        &form.preferences_token,
+        &preferences,
+        &lists,
+        Some("Your preferences have been saved."),
+    ))
+}
+
+#[tracing::instrument(name = "Get preferences from token", skip(pool, preferences_token))]
+async fn get_preferences(
+    pool: &sqlx::PgPool,
+    tenant: &str,
+    preferences_token: &str,
+) -> Result<Option<Preferences>, anyhow::Error> {
+    let Some(row) = sqlx::query!(
+        r#"
+    SELECT id, name, frequency, unsubscribed_lists, paused_until FROM subscriptions
+    WHERE tenant = $1 AND preferences_token = $2
+        "#,
+        tenant,
+        preferences_token,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the preferences token")?
+    else {
+        return Ok(None);
+    };
+
+    Ok(Some(Preferences {
+        subscriber_id: row.id,
+        name: row.name,
+        frequency: DeliveryFrequency::try_from(row.frequency.as_str())
+            .map_err(anyhow::Error::msg)?,
+        unsubscribed_lists: row.unsubscribed_lists,
+        paused_until: row.paused_until,
+    }))
+}
+
+#[tracing::instrument(name = "Save preferences", skip(pool, preferences))]
+async fn save_preferences(
+    pool: &sqlx::PgPool,
+    tenant: &str,
+    preferences: &Preferences,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    sqlx::query!(
+        r#"
+    UPDATE subscriptions
+    SET name = $1, frequency = $2, unsubscribed_lists = $3, paused_until = $4,
+      -- The first digest rolls up what is published from now on, not what they already got.
+      last_digest_at = CASE
+        WHEN frequency = 'every_issue' AND $2 = 'weekly_digest' THEN now()
+        ELSE last_digest_at
+      END
+    WHERE id = $5
+        "#,
+        preferences.name,
+        preferences.frequency.as_str(),
+        &preferences.unsubscribed_lists,
+        preferences.paused_until,
+        preferences.subscriber_id,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to update the subscriber's preferences")?;
+
+    // The name is left out: the audit log keeps no personal data.
+    audit::record(
+        &mut transaction,
+        tenant,
+        AuditEvent::new(
+            Actor::Subscriber(preferences.subscriber_id),
+            Action::UpdatePreferences,
+            Target::Subscriber(preferences.subscriber_id),
+        )
+        .details(serde_json::json!({
+            "frequency": preferences.frequency.as_str(),
+            "unsubscribed_lists": preferences.unsubscribed_lists,
+            "paused_until": preferences.paused_until,
+        })),
+    )
+    .await
+    .context("Failed to record the new preferences in the audit log")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(())
+}
+
+fn render_page(
+    preferences_token: &str,
+    preferences: &Preferences,
+    lists: &[MailingListRecord],
+    notice: Option<&str>,
+) -> Html<String> {
+    let notice = notice.map_or(String::new(), |notice| format!("<p>{notice}</p>"));
+    let name = escape_html(&preferences.name);
+
+    let lists = if lists.is_empty() {
+        String::new()
+    } else {
+        let checkboxes = lists.iter().fold(String::new(), |mut checkboxes, list| {
+            let checked = if preferences.unsubscribed_lists.contains(&list.slug) {
+                ""
+            } else {
+                " checked"
+            };
+            let _ = write!(
+                checkboxes,
+                r#"
+        <label><input type="checkbox" name="lists" value="{}"{checked} /> {}</label><br />"#,
+                escape_html(&list.slug),
+                escape_html(&list.name),
+            );
+            checkboxes
+        });
+        format!(
+            "
+      <fieldset>
+        <legend>Lists</legend>{checkboxes}
+      </fieldset>"
+        )
+    };
+
+    let frequency = |value: DeliveryFrequency| {
+        if preferences.frequency == value {
+            " checked"
+        } else {
+            ""
+        }
+    };
+    let every_issue = frequency(DeliveryFrequency::EveryIssue);
+    let weekly_digest = frequency(DeliveryFrequency::WeeklyDigest);
+
+    let pause = match preferences.paused_until {
+        Some(until) if until > Utc::now() => format!(
+            r#"<option value="" selected>Stay paused until {}</option>
+          <option value="0">Resume now</option>"#,
+            until.format("%Y-%m-%d")
+        ),
+        _ => r#"<option value="" selected>Don't pause</option>"#.to_owned(),
+    };
+
+    // Only rendered for tokens we issued: it embeds the token verbatim.
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head><meta charset="utf-8" /><title>Your preferences</title></head>
+  <body>{notice}
+    <form action="preferences" method="post">
+      <input type="hidden" name="preferences_token" value="{preferences_token}" />
+      <label>Name <input type="text" name="name" value="{name}" /></label>{lists}
+      <fieldset>
+        <legend>Frequency</legend>
+        <label><input type="radio" name="frequency" value="every_issue"{every_issue} /> Every issue</label><br />
+        <label><input type="radio" name="frequency" value="weekly_digest"{weekly_digest} /> A weekly digest</label>
+      </fieldset>
+      <label>Pause
+        <select name="pause_days">
+          {pause}
+          <option value="7">For a week</option>
+          <option value="30">For a month</option>
+          <option value="90">For three months</option>
+        </select>
+      </label>
+      <button type="submit">Save</button>
+    </form>
+  </body>
+</html>"#
+    ))
+}
+
+pub(crate) fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// The page where a subscriber chooses what they receive from us.
+pub(crate) fn preferences_link(base_url: &str, preferences_token: &str) -> String {
+    format!("{base_url}/preferences?preferences_token={preferences_token}")
+}
+
+/// Close an email with `notice`, if any, then with the link to the subscriber's preference page:
+/// every email we send subscribers carries it.
+pub(crate) fn with_footer(
+    locale: Locale,
+    preferences_link: &str,
+    notice: Option<&str>,
+    html: &str,
+    text: &str,
+) -> (String, String) {
+    let args = [("link", preferences_link)];
+    let html_link = translate(locale, "preferences-html", &args);
+    let text_link = translate(locale, "preferences-text", &args);
+    match notice {
+        Some(notice) => (
+            format!("{html}<hr /><p>{notice}<br />{html_link}</p>"),
+            format!("{text}\n\n--\n{notice}\n{text_link}"),
+        ),
+        None => (
+            format!("{html}<hr /><p>{html_link}</p>"),
+            format!("{text}\n\n--\n{text_link}"),
+        ),
+    }
+}
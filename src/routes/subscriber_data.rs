@@ -0,0 +1,507 @@
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    response::{Html, IntoResponse},
+    Extension, Form, Json,
+};
+use chrono::{DateTime, Utc};
+use hyper::http;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent, Target},
+    domain::{
+        subscriber_email::SubscriberEmail, subscription_status::SubscriptionStatus,
+        webhook_event_type::WebhookEventType,
+    },
+    email_outbox,
+    error::DataRequestError,
+    i18n::{translate, Locale},
+    routes::{preferences_link, subscription::generate_subscription_token, with_footer},
+    startup::AppState,
+    tenant::Tenant,
+    webhooks,
+};
+
+/// How long a data request link stays valid after it was emailed.
+const DATA_REQUEST_TOKEN_TTL_HOURS: i64 = 24;
+
+#[derive(serde::Deserialize, Debug, utoipa::ToSchema)]
+pub struct DataRequestForm {
+    email: String,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams, utoipa::ToSchema)]
+#[into_params(parameter_in = Query)]
+pub struct DataRequestParameters {
+    data_request_token: String,
+}
+
+/// Everything we hold about a subscriber.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct SubscriberDataExport {
+    pub subscriber: SubscriberRecord,
+    pub subscription_tokens: Vec<String>,
+    pub status_history: Vec<StatusChange>,
+    pub data_requests: Vec<DateTime<Utc>>,
+    /// The issues we sent them, or tried to.
+    pub deliveries: Vec<DeliveryRecord>,
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct SubscriberRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    /// The language of the emails we send them, e.g. `fr`.
+    pub locale: String,
+    /// `every_issue` or `weekly_digest`.
+    pub frequency: String,
+    /// The lists they opted out of.
+    pub unsubscribed_lists: Vec<String>,
+    /// Nothing is sent to them until then.
+    pub paused_until: Option<DateTime<Utc>>,
+    /// When they were last sent their weekly digest.
+    pub last_digest_at: Option<DateTime<Utc>>,
+    /// What they told us about themselves, e.g. `{"country": "US"}`.
+    #[schema(value_type = Object)]
+    pub attributes: serde_json::Value,
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct StatusChange {
+    pub status: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct DeliveryRecord {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    /// When the email API accepted the email.
+    pub sent_at: Option<DateTime<Utc>>,
+    /// Tracked when the issue's subject was under test.
+    pub opened_at: Option<DateTime<Utc>>,
+    pub clicked_at: Option<DateTime<Utc>>,
+    pub tracking_token: Option<String>,
+    /// Why it wasn't sent.
+    pub error: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscriptions/data_requests",
+    tag = "subscriber data",
+    request_body(content = DataRequestForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "If the email belongs to a subscriber, a link to manage their data was sent to it"),
+        (status = 400, description = "The email failed validation"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Request a subscriber data link", skip(state, form, tenant))]
+pub async fn request_data(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Form(form): Form<DataRequestForm>,
+) -> Result<impl IntoResponse, DataRequestError> {
+    let email = SubscriberEmail::try_from(form.email.as_str())
+        .map_err(|e| DataRequestError::ValidationError(e.into()))?;
+
+    let mut transaction = state
+        .db_pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    // We answer the same way whether or not the email is known, to avoid
+    // leaking who is subscribed to the newsletter.
+    let Some(subscriber) = get_subscriber_from_email(&mut transaction, &tenant.slug, &email)
+        .await
+        .context("Failed to look up the subscriber")?
+    else {
+        return Ok(http::StatusCode::OK);
+    };
+
+    let data_request_token = generate_subscription_token();
+    store_data_request_token(&mut transaction, subscriber.id, &data_request_token)
+        .await
+        .context("Failed to store data request token in the database")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    send_data_request_email(
+        &state.db_pool,
+        &tenant,
+        &email,
+        &data_request_token,
+        &subscriber,
+    )
+    .await
+    .context("Failed to send data request email")?;
+
+    Ok(http::StatusCode::OK)
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/data",
+    tag = "subscriber data",
+    params(DataRequestParameters),
+    responses(
+        (status = 200, description = "A page to download or erase the subscriber's data", content_type = "text/html"),
+        (status = 400, description = "The `data_request_token` query parameter is missing"),
+        (status = 401, description = "The token is unknown or has expired"),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Show the subscriber data page", skip(state, params, tenant))]
+pub async fn manage_data(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(params): Query<DataRequestParameters>,
+) -> Result<impl IntoResponse, DataRequestError> {
+    // Only render the page for tokens we issued: it embeds the token verbatim.
+    get_subscriber_id_from_data_request_token(
+        &state.db_pool,
+        &tenant.slug,
+        &params.data_request_token,
+    )
+    .await
+    .context("Failed to look up the data request token")?
+    .ok_or(DataRequestError::UnknownToken)?;
+
+    // Links are relative, so they keep the tenant's path prefix if it has one.
+    let token = params.data_request_token;
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head><meta charset="utf-8" /><title>Your data</title></head>
+  <body>
+    <p><a href="data/export?data_request_token={token}">Download all the data we hold about you</a></p>
+    <form action="data/erase" method="post">
+      <input type="hidden" name="data_request_token" value="{token}" />
+      <button type="submit">Erase my data and unsubscribe</button>
+    </form>
+  </body>
+</html>"#
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/subscriptions/data/export",
+    tag = "subscriber data",
+    params(DataRequestParameters),
+    responses(
+        (status = 200, description = "All the data we hold about the subscriber", body = SubscriberDataExport),
+        (status = 400, description = "The `data_request_token` query parameter is missing"),
+        (status = 401, description = "The token is unknown or has expired"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Export subscriber data", skip(state, params, tenant))]
+pub async fn export_data(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(params): Query<DataRequestParameters>,
+) -> Result<impl IntoResponse, DataRequestError> {
+    let subscriber_id = get_subscriber_id_from_data_request_token(
+        &state.db_pool,
+        &tenant.slug,
+        &params.data_request_token,
+    )
+    .await
+    .context("Failed to look up the data request token")?
+    .ok_or(DataRequestError::UnknownToken)?;
+
+    let export = get_subscriber_data(&state.db_pool, subscriber_id)
+        .await
+        .context("Failed to collect the subscriber's data")?;
+
+    Ok(Json(export))
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscriptions/data/erase",
+    tag = "subscriber data",
+    request_body(content = DataRequestParameters, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The subscriber and everything tied to them were deleted"),
+        (status = 401, description = "The token is unknown or has expired"),
+        (status = 422, description = "The form is missing the token"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+#[tracing::instrument(name = "Erase subscriber data", skip(state, form, tenant))]
+pub async fn erase_data(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Form(form): Form<DataRequestParameters>,
+) -> Result<impl IntoResponse, DataRequestError> {
+    let subscriber_id = get_subscriber_id_from_data_request_token(
+        &state.db_pool,
+        &tenant.slug,
+        &form.data_request_token,
+    )
+    .await
+    .context("Failed to look up the data request token")?
+    .ok_or(DataRequestError::UnknownToken)?;
+
+    erase_subscriber(&state.db_pool, &tenant.slug, subscriber_id)
+        .await
+        .context("Failed to erase the subscriber")?;
+
+    Ok((http::StatusCode::OK, "Your data has been erased."))
+}
+
+/// What we need to email a subscriber who asked for their data.
+struct DataRequester {
+    id: Uuid,
+    locale: Locale,
+    preferences_token: String,
+}
+
+#[tracing::instrument(name = "Get subscriber from email", skip(transaction, email))]
+async fn get_subscriber_from_email(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    email: &SubscriberEmail,
+) -> Result<Option<DataRequester>, sqlx::Error> {
+    sqlx::query!(
+        r#"
+    SELECT id, locale, preferences_token FROM subscriptions
+    WHERE tenant = $1 AND canonical_email = $2
+        "#,
+        tenant,
+        email.canonical()
+    )
+    .fetch_optional(&mut **transaction)
+    .await
+    .map(|record| {
+        record.map(|r| DataRequester {
+            id: r.id,
+            locale: Locale::from(r.locale.as_str()),
+            preferences_token: r.preferences_token,
+        })
+    })
+}
+
+#[tracing::instrument(
+    name = "Saving data request token details in the database",
+    skip(transaction, data_request_token)
+)]
+async fn store_data_request_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    data_request_token: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    INSERT INTO data_request_tokens (data_request_token, subscriber_id, requested_at)
+    VALUES ($1, $2, $3)
+            "#,
+        data_request_token,
+        subscriber_id,
+        Utc::now()
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from data request token",
+    skip(data_request_token, pool)
+)]
+async fn get_subscriber_id_from_data_request_token(
+    pool: &PgPool,
+    tenant: &str,
+    data_request_token: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let oldest_valid_request = Utc::now() - chrono::Duration::hours(DATA_REQUEST_TOKEN_TTL_HOURS);
+    sqlx::query!(
+        r#"
+    SELECT subscriber_id FROM data_request_tokens
+    JOIN subscriptions ON subscriptions.id = data_request_tokens.subscriber_id
+    WHERE data_request_token = $1 AND requested_at > $2 AND tenant = $3
+        "#,
+        data_request_token,
+        oldest_valid_request,
+        tenant
+    )
+    .fetch_optional(pool)
+    .await
+    .map(|record| record.map(|r| r.subscriber_id))
+}
+
+#[tracing::instrument(name = "Collect subscriber data", skip(pool))]
+async fn get_subscriber_data(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<SubscriberDataExport, sqlx::Error> {
+    let subscriber = sqlx::query_as!(
+        SubscriberRecord,
+        r#"
+    SELECT id, email, name, status, subscribed_at, locale, frequency, unsubscribed_lists,
+      paused_until, last_digest_at, attributes
+    FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let subscription_tokens = sqlx::query!(
+        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.subscription_token)
+    .collect();
+
+    let status_history = get_status_history(pool, subscriber_id).await?;
+
+    let data_requests = sqlx::query!(
+        r#"
+    SELECT requested_at FROM data_request_tokens
+    WHERE subscriber_id = $1
+    ORDER BY requested_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?
+    .into_iter()
+    .map(|r| r.requested_at)
+    .collect();
+
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+    SELECT d.newsletter_issue_id, i.title, d.sent_at, d.opened_at, d.clicked_at,
+      d.tracking_token, d.error
+    FROM newsletter_deliveries d
+    JOIN newsletter_issues i USING (newsletter_issue_id)
+    WHERE d.subscriber_id = $1
+    ORDER BY i.published_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(SubscriberDataExport {
+        subscriber,
+        subscription_tokens,
+        status_history,
+        data_requests,
+        deliveries,
+    })
+}
+
+#[tracing::instrument(name = "Get subscriber status history", skip(pool))]
+pub(crate) async fn get_status_history(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Vec<StatusChange>, sqlx::Error> {
+    sqlx::query_as!(
+        StatusChange,
+        r#"
+    SELECT status, changed_at
+    FROM subscription_status_changes
+    WHERE subscriber_id = $1
+    ORDER BY changed_at, id
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Hard-delete a subscriber: every row referencing them goes with it (`ON DELETE CASCADE`),
+/// except the audit log which only knows them by id.
+#[tracing::instrument(name = "Delete subscriber", skip(pool))]
+async fn erase_subscriber(
+    pool: &PgPool,
+    tenant: &str,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let erased = sqlx::query!(
+        r#"DELETE FROM subscriptions WHERE id = $1 RETURNING status, email"#,
+        subscriber_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let previous =
+        SubscriptionStatus::try_from(erased.status.as_str()).map_err(anyhow::Error::msg)?;
+
+    audit::record(
+        &mut transaction,
+        tenant,
+        AuditEvent::new(
+            Actor::Subscriber(subscriber_id),
+            Action::Erase,
+            Target::Subscriber(subscriber_id),
+        )
+        .status_change(Some(previous), None),
+    )
+    .await?;
+    // Nothing about them but their id outlives the erasure, not even in what we send out.
+    webhooks::scrub_subscriber(&mut transaction, tenant, subscriber_id).await?;
+    email_outbox::discard(&mut *transaction, tenant, &erased.email).await?;
+    webhooks::enqueue(
+        &mut transaction,
+        tenant,
+        WebhookEventType::SubscriberUnsubscribed,
+        serde_json::json!({ "id": subscriber_id }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
+}
+
+#[tracing::instrument(
+    name = "Send a data request email to a subscriber",
+    skip(pool, tenant, data_request_token, subscriber)
+)]
+async fn send_data_request_email(
+    pool: &PgPool,
+    tenant: &Tenant,
+    email: &SubscriberEmail,
+    data_request_token: &str,
+    subscriber: &DataRequester,
+) -> Result<(), anyhow::Error> {
+    let base_url = &tenant.base_url;
+    let locale = subscriber.locale;
+    let data_link =
+        format!("{base_url}/subscriptions/data?data_request_token={data_request_token}");
+    let args = [("link", data_link.as_str())];
+    let subject = translate(locale, "data-request-subject", &args);
+    let (html_body, plain_body) = with_footer(
+        locale,
+        &preferences_link(base_url, &subscriber.preferences_token),
+        None,
+        &translate(locale, "data-request-html", &args),
+        &translate(locale, "data-request-text", &args),
+    );
+
+    email_outbox::send_or_defer(pool, tenant, email, &subject, &html_body, &plain_body).await
+}
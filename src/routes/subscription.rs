@@ -1,25 +1,45 @@
+use crate::audit::{self, Action, Actor, AuditEvent, Target};
+use crate::domain::subscriber_attributes::SubscriberAttributes;
 use crate::domain::subscriber_email::SubscriberEmail;
 use crate::domain::subscriber_name::SubscriberName;
+use crate::domain::subscription_status::SubscriptionStatus;
+use crate::domain::webhook_event_type::WebhookEventType;
 use crate::domain::NewSubscriber;
-use crate::email_client;
-use crate::error::{StoreTokenError, SubscribeError};
+use crate::email_outbox;
+use crate::error::{ErrorBody, ResponseFormat, StoreTokenError, SubscribeError};
+use crate::i18n::{translate, Locale, Message};
+use crate::routes::{get_attribute_schema, preferences_link, with_footer};
 use crate::startup::AppState;
+use crate::tenant::Tenant;
+use crate::webhooks;
 use anyhow::Context;
-use axum::extract::State;
-use axum::{http, response::IntoResponse, Form};
+use axum::extract::{rejection::JsonRejection, State};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::{http, Extension, Form, Json};
 use rand::{thread_rng, Rng};
 use sqlx::types::chrono::Utc;
-use sqlx::{Postgres, Transaction};
+use sqlx::{PgPool, Postgres, Transaction};
+use std::sync::Arc;
 use uuid::Uuid;
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug, utoipa::ToSchema)]
 pub struct FormData {
     email: String,
     name: String,
+    /// The language of our emails and error messages, e.g. `fr`. Defaults to the
+    /// `Accept-Language` header, then to English.
+    #[serde(default)]
+    locale: Option<String>,
+    /// What the subscriber tells us about themselves, e.g. `{"country": "US"}`: only the
+    /// attributes our lists collect are accepted. JSON only.
+    #[serde(default)]
+    #[schema(value_type = Option<Object>)]
+    attributes: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
-    type Error = String;
+    type Error = Message;
 
     fn try_from(value: FormData) -> Result<Self, Self::Error> {
         let email = SubscriberEmail::try_from(value.email.as_str())?;
@@ -29,70 +49,212 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
+/// Returned by the JSON API once a subscriber has been registered.
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct SubscriptionCreated {
+    pub id: Uuid,
+    pub status: &'static str,
+}
+
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content = FormData, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The subscriber was registered and a confirmation email sent"),
+        (status = 400, description = "The name or email failed validation, or the email isn't accepted", body = ErrorBody),
+        (status = 422, description = "The form is missing a field"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
 #[allow(clippy::async_yields_async)]
+pub async fn subscribe(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    headers: HeaderMap,
+    Form(form): Form<FormData>,
+) -> Response {
+    let format = ResponseFormat::negotiate(&headers, ResponseFormat::PlainText);
+    let locale = Locale::resolve(form.locale.as_deref(), &headers);
+
+    match register_subscriber(&state, &tenant, form, locale).await {
+        Ok(_) => http::StatusCode::OK.into_response(),
+        Err(e) => e.render(format, locale),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/subscriptions",
+    tag = "subscriptions",
+    request_body = FormData,
+    responses(
+        (status = 201, description = "The subscriber was registered and a confirmation email sent", body = SubscriptionCreated),
+        (status = 400, description = "The name or email failed validation, or the email isn't accepted", body = ErrorBody),
+        (status = 415, description = "The request body isn't JSON", body = ErrorBody),
+        (status = 422, description = "The payload is missing a field", body = ErrorBody),
+        (status = 500, description = "An unexpected error occurred", body = ErrorBody),
+    )
+)]
+#[allow(clippy::async_yields_async)]
+pub async fn subscribe_json(
+    State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    headers: HeaderMap,
+    payload: Result<Json<FormData>, JsonRejection>,
+) -> Response {
+    let format = ResponseFormat::negotiate(&headers, ResponseFormat::Json);
+
+    let (outcome, locale) = match payload {
+        Ok(Json(form)) => {
+            let locale = Locale::resolve(form.locale.as_deref(), &headers);
+            (
+                register_subscriber(&state, &tenant, form, locale).await,
+                locale,
+            )
+        }
+        Err(rejection) => (Err(rejection.into()), Locale::resolve(None, &headers)),
+    };
+
+    match outcome {
+        Ok(id) => {
+            let body = SubscriptionCreated {
+                id,
+                status: "pending_confirmation",
+            };
+            (http::StatusCode::CREATED, Json(body)).into_response()
+        }
+        Err(e) => e.render(format, locale),
+    }
+}
+
+/// Validate, persist and send a confirmation email to a new subscriber.
+///
+/// Shared by the form and the JSON entrypoints.
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, state),
+    skip(form, state, tenant),
     fields(
+        tenant = %tenant.slug,
+        locale = locale.as_str(),
         subscriber_name = %form.name,
         subscriber_email = %form.email
     )
 )]
-pub async fn subscribe(
-    State(state): State<AppState>,
-    Form(form): Form<FormData>,
-) -> Result<impl IntoResponse, SubscribeError> {
+async fn register_subscriber(
+    state: &AppState,
+    tenant: &Tenant,
+    mut form: FormData,
+    locale: Locale,
+) -> Result<Uuid, SubscribeError> {
+    let attributes = form.attributes.take().unwrap_or_default();
     let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
+    let schema = get_attribute_schema(&state.db_pool, &tenant.slug).await?;
+    let attributes = SubscriberAttributes::parse(attributes, &schema)
+        .map_err(SubscribeError::ValidationError)?;
+    state
+        .email_policy
+        .check(&state.db_pool, &tenant.slug, &new_subscriber.email)
+        .await
+        .context("Failed to check the email policy")??;
     let mut transaction = state
         .db_pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
-        .await
-        .context("Failed to insert new subscriber in the database")?;
+    let preferences_token = generate_subscription_token();
+    let subscriber_id = insert_subscriber(
+        &mut transaction,
+        &tenant.slug,
+        &new_subscriber,
+        attributes,
+        locale,
+        &preferences_token,
+    )
+    .await
+    .context("Failed to insert new subscriber in the database")?;
 
     let subscription_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_id, &subscription_token)
         .await
         .context("Failed to store subscription token in the database")?;
 
+    audit::record(
+        &mut transaction,
+        &tenant.slug,
+        AuditEvent::new(
+            Actor::Subscriber(subscriber_id),
+            Action::Subscribe,
+            Target::Subscriber(subscriber_id),
+        )
+        .status_change(None, Some(SubscriptionStatus::PendingConfirmation)),
+    )
+    .await
+    .context("Failed to record the subscription in the audit log")?;
+    webhooks::enqueue(
+        &mut transaction,
+        &tenant.slug,
+        WebhookEventType::SubscriberCreated,
+        serde_json::json!({
+            "id": subscriber_id,
+            "email": new_subscriber.email.as_ref(),
+            "name": new_subscriber.name.as_ref(),
+            "status": SubscriptionStatus::PendingConfirmation.as_str(),
+        }),
+    )
+    .await
+    .context("Failed to enqueue the subscriber.created webhook event")?;
+
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction")?;
 
     send_confirmation_email(
-        &state.email_client,
-        new_subscriber,
-        &state.base_url,
+        &state.db_pool,
+        tenant,
+        &new_subscriber,
         &subscription_token,
+        &preferences_token,
+        locale,
     )
     .await
     .context("Failed to send confirmation email")?;
 
-    Ok(http::StatusCode::OK)
+    Ok(subscriber_id)
 }
 
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
-    skip(new_subscriber, transaction)
+    skip(new_subscriber, attributes, transaction)
 )]
 async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
     new_subscriber: &NewSubscriber,
+    attributes: SubscriberAttributes,
+    locale: Locale,
+    preferences_token: &str,
 ) -> Result<Uuid, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     sqlx::query!(
         r#"
-    INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    INSERT INTO subscriptions
+      (id, tenant, email, canonical_email, name, subscribed_at, status, locale, preferences_token,
+       attributes)
+    VALUES ($1, $2, $3, $4, $5, $6, 'pending_confirmation', $7, $8, $9)
             "#,
         subscriber_id,
+        tenant,
         new_subscriber.email.as_ref(),
+        new_subscriber.email.canonical(),
         new_subscriber.name.as_ref(),
-        Utc::now()
+        Utc::now(),
+        locale.as_str(),
+        preferences_token,
+        attributes.into_json(),
     )
     .execute(&mut **transaction)
     .await
@@ -106,30 +268,40 @@ async fn insert_subscriber(
 
 #[tracing::instrument(
     name = "Send a confirmation email to new subscriber",
-    skip(new_subscriber, email_client)
+    skip(pool, tenant, new_subscriber, preferences_token)
 )]
 async fn send_confirmation_email(
-    email_client: &email_client::EmailClient,
-    new_subscriber: NewSubscriber,
-    base_url: &str,
+    pool: &PgPool,
+    tenant: &Tenant,
+    new_subscriber: &NewSubscriber,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    preferences_token: &str,
+    locale: Locale,
+) -> Result<(), anyhow::Error> {
+    let base_url = &tenant.base_url;
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
-    let plain_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-        confirmation_link
-    );
-    let html_body = format!(
-        "Welcome to our newsletter!<br />Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
+    let args = [("link", confirmation_link.as_str())];
+    let subject = translate(locale, "confirmation-subject", &args);
+    let (html_body, plain_body) = with_footer(
+        locale,
+        &preferences_link(base_url, preferences_token),
+        None,
+        &translate(locale, "confirmation-html", &args),
+        &translate(locale, "confirmation-text", &args),
     );
 
-    email_client
-        .send_email(&new_subscriber.email, "Welcome!", &html_body, &plain_body)
-        .await
+    email_outbox::send_or_defer(
+        pool,
+        tenant,
+        &new_subscriber.email,
+        &subject,
+        &html_body,
+        &plain_body,
+    )
+    .await
 }
 
 #[tracing::instrument(
@@ -160,7 +332,7 @@ async fn store_token(
     Ok(())
 }
 
-fn generate_subscription_token() -> String {
+pub(crate) fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(rand::distributions::Alphanumeric))
         .map(char::from)
@@ -1,58 +1,137 @@
+use std::sync::Arc;
+
 use axum::{
     extract::{Query, State},
     response::IntoResponse,
+    Extension,
 };
 use hyper::http;
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::startup::AppState;
+use crate::{
+    audit::{self, Action, Actor, AuditEvent, Target},
+    domain::{subscription_status::SubscriptionStatus, webhook_event_type::WebhookEventType},
+    startup::AppState,
+    tenant::Tenant,
+    webhooks,
+};
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Parameters {
     subscription_token: String,
 }
 
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscriber is now confirmed"),
+        (status = 400, description = "The `subscription_token` query parameter is missing"),
+        (status = 401, description = "The token doesn't match any subscriber"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
 #[allow(clippy::async_yields_async)]
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(params, state))]
+#[tracing::instrument(name = "Confirm a pending subscriber", skip(params, state, tenant))]
 pub async fn confirm(
     State(state): State<AppState>,
+    Extension(tenant): Extension<Arc<Tenant>>,
     Query(params): Query<Parameters>,
 ) -> impl IntoResponse {
-    let Ok(id) = get_subscriber_id_from_token(&state.db_pool, &params.subscription_token).await
+    let Ok(id) =
+        get_subscriber_id_from_token(&state.db_pool, &tenant.slug, &params.subscription_token)
+            .await
     else {
         return http::StatusCode::NOT_FOUND;
     };
 
     match id {
-        Some(subscriber_id) => match confirm_subscriber(&state.db_pool, subscriber_id).await {
-            Ok(_) => http::StatusCode::OK,
-            Err(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
-        },
+        Some(subscriber_id) => {
+            match confirm_subscriber(&state.db_pool, &tenant.slug, subscriber_id).await {
+                Ok(_) => http::StatusCode::OK,
+                Err(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+            }
+        }
         None => http::StatusCode::UNAUTHORIZED,
     }
 }
 
 #[allow(clippy::async_yields_async)]
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+async fn confirm_subscriber(
+    pool: &PgPool,
+    tenant: &str,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let subscriber = sqlx::query!(
+        r#"SELECT status, email, name FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_one(&mut *transaction)
+    .await?;
+    let previous =
+        SubscriptionStatus::try_from(subscriber.status.as_str()).map_err(anyhow::Error::msg)?;
+
+    // Following the link twice is harmless, and not worth an audit event.
+    if previous == SubscriptionStatus::Confirmed {
+        return Ok(());
+    }
+
     sqlx::query!(
         r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
         subscriber_id
     )
-    .execute(pool)
-    .await
-    .map(|_| ())
+    .execute(&mut *transaction)
+    .await?;
+
+    audit::record(
+        &mut transaction,
+        tenant,
+        AuditEvent::new(
+            Actor::Subscriber(subscriber_id),
+            Action::Confirm,
+            Target::Subscriber(subscriber_id),
+        )
+        .status_change(Some(previous), Some(SubscriptionStatus::Confirmed)),
+    )
+    .await?;
+    webhooks::enqueue(
+        &mut transaction,
+        tenant,
+        WebhookEventType::SubscriberConfirmed,
+        serde_json::json!({
+            "id": subscriber_id,
+            "email": subscriber.email,
+            "name": subscriber.name,
+            "status": SubscriptionStatus::Confirmed.as_str(),
+        }),
+    )
+    .await?;
+
+    transaction.commit().await?;
+    Ok(())
 }
 
 #[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
 async fn get_subscriber_id_from_token(
     pool: &PgPool,
+    tenant: &str,
     subscription_token: &str,
 ) -> Result<Option<Uuid>, sqlx::Error> {
     sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
-        subscription_token
+        r#"
+    SELECT subscriber_id FROM subscription_tokens
+    JOIN subscriptions ON subscriptions.id = subscription_tokens.subscriber_id
+    WHERE subscription_token = $1 AND tenant = $2
+        "#,
+        subscription_token,
+        tenant
     )
     .fetch_optional(pool)
     .await
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Redirect},
+    Extension,
+};
+use hyper::header;
+use sqlx::PgPool;
+
+use crate::{error::TrackingError, subject_test, tenant::Tenant};
+
+/// A transparent 1×1 GIF.
+const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OpenParameters {
+    tracking_token: String,
+}
+
+#[derive(serde::Deserialize, Debug, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ClickParameters {
+    tracking_token: String,
+    /// One of the links in the issue.
+    url: String,
+}
+
+#[utoipa::path(
+    get,
+    path = "/newsletters/open",
+    tag = "newsletters",
+    params(OpenParameters),
+    responses(
+        (status = 200, description = "The issue is counted as opened", content_type = "image/gif"),
+        (status = 404, description = "The token doesn't match any email we sent"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
+#[tracing::instrument(name = "Track an open", skip_all)]
+pub async fn track_open(
+    State(pool): State<PgPool>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(params): Query<OpenParameters>,
+) -> Result<impl IntoResponse, TrackingError> {
+    let tracked = sqlx::query!(
+        r#"
+    UPDATE newsletter_deliveries d SET opened_at = COALESCE(d.opened_at, now())
+    FROM newsletter_issues i
+    WHERE d.tracking_token = $1
+      AND i.newsletter_issue_id = d.newsletter_issue_id AND i.tenant = $2
+        "#,
+        params.tracking_token,
+        tenant.slug,
+    )
+    .execute(&pool)
+    .await
+    .context("Failed to record an open")?
+    .rows_affected();
+    if tracked == 0 {
+        return Err(TrackingError::UnknownLink);
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/gif"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        PIXEL,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/newsletters/click",
+    tag = "newsletters",
+    params(ClickParameters),
+    responses(
+        (status = 303, description = "The click is counted, on to `url`"),
+        (status = 404, description = "The token doesn't match any email we sent, or `url` isn't in it"),
+        (status = 500, description = "An unexpected error occurred"),
+    )
+)]
+#[tracing::instrument(name = "Track a click", skip_all)]
+pub async fn track_click(
+    State(pool): State<PgPool>,
+    Extension(tenant): Extension<Arc<Tenant>>,
+    Query(params): Query<ClickParameters>,
+) -> Result<impl IntoResponse, TrackingError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let issue = sqlx::query!(
+        r#"
+    SELECT i.html_content
+    FROM newsletter_deliveries d JOIN newsletter_issues i USING (newsletter_issue_id)
+    WHERE d.tracking_token = $1 AND i.tenant = $2
+    FOR UPDATE OF d
+        "#,
+        params.tracking_token,
+        tenant.slug,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look up a tracked link")?;
+    // Only links from the issue are followed, so we aren't an open redirect.
+    match issue {
+        Some(issue) if subject_test::links_to(&issue.html_content, &params.url) => {}
+        _ => return Err(TrackingError::UnknownLink),
+    }
+
+    // Following a link means the issue was opened, even if its images weren't loaded.
+    sqlx::query!(
+        r#"
+    UPDATE newsletter_deliveries
+    SET clicked_at = COALESCE(clicked_at, now()), opened_at = COALESCE(opened_at, now())
+    WHERE tracking_token = $1
+        "#,
+        params.tracking_token,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record a click")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    // Links are taken as written in the HTML, where `&` is escaped.
+    Ok(Redirect::to(&params.url.replace("&amp;", "&")))
+}
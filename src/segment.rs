@@ -0,0 +1,490 @@
+//! Segments narrow the recipients of an issue down by their attributes, e.g.
+//! `country = "US" AND plan IN ("pro", "team")`.
+//!
+//! ```text
+//! segment    = and ("OR" and)*
+//! and        = unary ("AND" unary)*
+//! unary      = "NOT" unary | "(" segment ")" | comparison
+//! comparison = attribute ("=" | "!=" | "<" | "<=" | ">" | ">=") value
+//!            | attribute "IN" "(" value ("," value)* ")"
+//! value      = "a string" | 42 | -1.5 | true | false
+//! ```
+//!
+//! A segment compiles to a predicate on `subscriptions` in which attribute names and values are
+//! bound parameters, never SQL. Subscribers without an attribute match none of its comparisons.
+
+use sqlx::{Postgres, QueryBuilder};
+
+use crate::domain::subscriber_attributes::{
+    is_valid_attribute_name, AttributeSchema, AttributeType,
+};
+
+pub const MAX_SEGMENT_LENGTH: usize = 1000;
+/// How deeply `NOT` and parentheses can nest.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    And(Box<Segment>, Box<Segment>),
+    Or(Box<Segment>, Box<Segment>),
+    Not(Box<Segment>),
+    Compare {
+        attribute: String,
+        operator: Operator,
+        value: serde_json::Value,
+    },
+    In {
+        attribute: String,
+        values: Vec<serde_json::Value>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    fn as_str(self) -> &'static str {
+        match self {
+            Operator::Eq => "=",
+            Operator::Ne => "!=",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+        }
+    }
+
+    fn is_ordering(self) -> bool {
+        !matches!(self, Operator::Eq | Operator::Ne)
+    }
+}
+
+impl Segment {
+    /// Parse a segment, checking its syntax only: see [`Segment::check`] for its attributes.
+    pub fn parse(segment: &str) -> Result<Self, String> {
+        if segment.len() > MAX_SEGMENT_LENGTH {
+            return Err(format!(
+                "Segments are limited to {MAX_SEGMENT_LENGTH} characters."
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(segment)?,
+            position: 0,
+            depth: 0,
+        };
+        let parsed = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(parsed),
+            Some(token) => Err(format!("Unexpected {} in the segment.", token.describe())),
+        }
+    }
+
+    /// Check that every attribute is collected, and compared to values of its type.
+    pub fn check(&self, schema: &AttributeSchema) -> Result<(), String> {
+        let attribute_type = |attribute: &str| {
+            schema
+                .get(attribute)
+                .copied()
+                .ok_or_else(|| format!("{attribute} is not an attribute any list collects."))
+        };
+        let check_value = |attribute: &str, attribute_type: AttributeType, value| {
+            if attribute_type.accepts(value) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{attribute} is a {}, it can't be compared to {value}.",
+                    attribute_type.as_str()
+                ))
+            }
+        };
+        match self {
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                left.check(schema)?;
+                right.check(schema)
+            }
+            Segment::Not(segment) => segment.check(schema),
+            Segment::Compare {
+                attribute,
+                operator,
+                value,
+            } => {
+                let attribute_type = attribute_type(attribute)?;
+                if operator.is_ordering() && attribute_type != AttributeType::Number {
+                    return Err(format!(
+                        "{attribute} is a {}, only numbers can be compared with {}.",
+                        attribute_type.as_str(),
+                        operator.as_str()
+                    ));
+                }
+                check_value(attribute, attribute_type, value)
+            }
+            Segment::In { attribute, values } => {
+                let attribute_type = attribute_type(attribute)?;
+                values
+                    .iter()
+                    .try_for_each(|value| check_value(attribute, attribute_type, value))
+            }
+        }
+    }
+
+    /// Append the segment to `query` as a predicate on `subscriptions`.
+    pub fn push_predicate(&self, query: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            Segment::And(left, right) | Segment::Or(left, right) => {
+                let operator = match self {
+                    Segment::And(..) => " AND ",
+                    _ => " OR ",
+                };
+                query.push("(");
+                left.push_predicate(query);
+                query.push(operator);
+                right.push_predicate(query);
+                query.push(")");
+            }
+            Segment::Not(segment) => {
+                query.push("NOT ");
+                segment.push_predicate(query);
+            }
+            // Values are compared as JSON, so that numbers compare as numbers. A missing
+            // attribute compares as `NULL`, which `COALESCE` turns into a mismatch.
+            Segment::Compare {
+                attribute,
+                operator,
+                value,
+            } => {
+                query
+                    .push("COALESCE(attributes -> ")
+                    .push_bind(attribute.clone())
+                    .push(format_args!(" {} ", operator.as_str()))
+                    .push_bind(value.clone())
+                    .push("::jsonb, false)");
+            }
+            Segment::In { attribute, values } => {
+                query
+                    .push_bind(serde_json::Value::Array(values.clone()))
+                    .push("::jsonb @> jsonb_build_array(attributes -> ")
+                    .push_bind(attribute.clone())
+                    .push(")");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Attribute(String),
+    Value(serde_json::Value),
+    Operator(Operator),
+    And,
+    Or,
+    Not,
+    In,
+    OpenParenthesis,
+    CloseParenthesis,
+    Comma,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Attribute(attribute) => format!("attribute {attribute}"),
+            Token::Value(value) => format!("value {value}"),
+            Token::Operator(operator) => format!("operator {}", operator.as_str()),
+            Token::And => "AND".to_owned(),
+            Token::Or => "OR".to_owned(),
+            Token::Not => "NOT".to_owned(),
+            Token::In => "IN".to_owned(),
+            Token::OpenParenthesis => "(".to_owned(),
+            Token::CloseParenthesis => ")".to_owned(),
+            Token::Comma => ",".to_owned(),
+        }
+    }
+}
+
+fn tokenize(segment: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = segment.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::OpenParenthesis,
+            ')' => Token::CloseParenthesis,
+            ',' => Token::Comma,
+            '=' => Token::Operator(Operator::Eq),
+            '!' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Ne),
+            '<' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Le),
+            '<' => Token::Operator(Operator::Lt),
+            '>' if chars.next_if_eq(&'=').is_some() => Token::Operator(Operator::Ge),
+            '>' => Token::Operator(Operator::Gt),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped @ ('"' | '\\')) => value.push(escaped),
+                            _ => return Err("Only \\\" and \\\\ can be escaped in strings.".into()),
+                        },
+                        Some(c) => value.push(c),
+                        None => {
+                            return Err(
+                                "A string in the segment is missing its closing quote.".into()
+                            )
+                        }
+                    }
+                }
+                Token::Value(value.into())
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                }
+                let number = serde_json::from_str::<serde_json::Number>(&number)
+                    .map_err(|_| format!("{number} is not a valid number."))?;
+                Token::Value(number.into())
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+                match word.to_ascii_uppercase().as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    "IN" => Token::In,
+                    _ if word == "true" => Token::Value(true.into()),
+                    _ if word == "false" => Token::Value(false.into()),
+                    _ if is_valid_attribute_name(&word) => Token::Attribute(word),
+                    _ => return Err(format!("{word} is not a valid attribute name.")),
+                }
+            }
+            c => return Err(format!("Unexpected {c:?} in the segment.")),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.tokens.get(self.position) == Some(token);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, token: &Token, context: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("Expected {} {context}.", token.describe()))
+        }
+    }
+
+    fn or(&mut self) -> Result<Segment, String> {
+        let mut segment = self.and()?;
+        while self.eat(&Token::Or) {
+            segment = Segment::Or(Box::new(segment), Box::new(self.and()?));
+        }
+        Ok(segment)
+    }
+
+    fn and(&mut self) -> Result<Segment, String> {
+        let mut segment = self.unary()?;
+        while self.eat(&Token::And) {
+            segment = Segment::And(Box::new(segment), Box::new(self.unary()?));
+        }
+        Ok(segment)
+    }
+
+    fn unary(&mut self) -> Result<Segment, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!(
+                "Segments can't nest more than {MAX_DEPTH} levels deep."
+            ));
+        }
+        if self.eat(&Token::Not) {
+            self.depth += 1;
+            let segment = self.unary()?;
+            self.depth -= 1;
+            return Ok(Segment::Not(Box::new(segment)));
+        }
+        if self.eat(&Token::OpenParenthesis) {
+            self.depth += 1;
+            let segment = self.or()?;
+            self.depth -= 1;
+            self.expect(&Token::CloseParenthesis, "to close the parenthesis")?;
+            return Ok(segment);
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Segment, String> {
+        let attribute = match self.next() {
+            Some(Token::Attribute(attribute)) => attribute,
+            Some(token) => return Err(format!("Expected an attribute, not {}.", token.describe())),
+            None => return Err("The segment ends where an attribute was expected.".into()),
+        };
+        if self.eat(&Token::In) {
+            self.expect(&Token::OpenParenthesis, &format!("after {attribute} IN"))?;
+            let mut values = vec![self.value(&attribute)?];
+            while self.eat(&Token::Comma) {
+                values.push(self.value(&attribute)?);
+            }
+            self.expect(&Token::CloseParenthesis, "to close the list of values")?;
+            return Ok(Segment::In { attribute, values });
+        }
+        let Some(Token::Operator(operator)) = self.next() else {
+            return Err(format!("Expected an operator or IN after {attribute}."));
+        };
+        let value = self.value(&attribute)?;
+        Ok(Segment::Compare {
+            attribute,
+            operator,
+            value,
+        })
+    }
+
+    fn value(&mut self, attribute: &str) -> Result<serde_json::Value, String> {
+        match self.next() {
+            Some(Token::Value(value)) => Ok(value),
+            _ => Err(format!("Expected a value to compare {attribute} to.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Operator, Segment};
+    use crate::domain::subscriber_attributes::{AttributeSchema, AttributeType};
+    use claims::{assert_err, assert_ok};
+    use sqlx::{Postgres, QueryBuilder};
+
+    fn compare(attribute: &str, operator: Operator, value: serde_json::Value) -> Segment {
+        Segment::Compare {
+            attribute: attribute.into(),
+            operator,
+            value,
+        }
+    }
+
+    fn schema() -> AttributeSchema {
+        [
+            ("country".to_owned(), AttributeType::String),
+            ("plan".to_owned(), AttributeType::String),
+            ("seats".to_owned(), AttributeType::Number),
+            ("trial".to_owned(), AttributeType::Boolean),
+        ]
+        .into()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let segment = assert_ok!(Segment::parse(
+            r#"trial = true OR country = "US" and seats >= -1.5"#
+        ));
+
+        assert_eq!(
+            segment,
+            Segment::Or(
+                Box::new(compare("trial", Operator::Eq, true.into())),
+                Box::new(Segment::And(
+                    Box::new(compare("country", Operator::Eq, "US".into())),
+                    Box::new(compare("seats", Operator::Ge, serde_json::json!(-1.5))),
+                )),
+            )
+        );
+    }
+
+    #[test]
+    fn parentheses_not_and_in_are_understood() {
+        let segment = assert_ok!(Segment::parse(
+            r#"NOT (plan IN ("pro", "team \"plus\"") OR seats < 3)"#
+        ));
+
+        assert_eq!(
+            segment,
+            Segment::Not(Box::new(Segment::Or(
+                Box::new(Segment::In {
+                    attribute: "plan".into(),
+                    values: vec!["pro".into(), r#"team "plus""#.into()],
+                }),
+                Box::new(compare("seats", Operator::Lt, 3.into())),
+            )))
+        );
+    }
+
+    #[test]
+    fn malformed_segments_are_rejected() {
+        for segment in [
+            "",
+            r#"country = "US"#,
+            r#"country = "US" plan = "pro""#,
+            r#"Country = "US""#,
+            "country =",
+            "country IN ()",
+            "(seats > 1",
+            "seats > 1 AND",
+            "seats ~ 1",
+            "seats > 1..2",
+            "country = US",
+            "; DROP TABLE subscriptions",
+            &format!("{}seats > 1{}", "(".repeat(40), ")".repeat(40)),
+            &format!("seats = {}", "1".repeat(1000)),
+        ] {
+            assert_err!(Segment::parse(segment), "{segment}");
+        }
+    }
+
+    #[test]
+    fn attributes_must_be_collected_and_compared_to_values_of_their_type() {
+        let check = |segment: &str| Segment::parse(segment).unwrap().check(&schema());
+
+        assert_ok!(check(
+            r#"country = "US" AND seats > 2 AND NOT trial = true"#
+        ));
+        assert_ok!(check(r#"plan IN ("pro", "team")"#));
+        assert_err!(check(r#"city = "Paris""#));
+        assert_err!(check("country = 1"));
+        assert_err!(check(r#"plan IN ("pro", 2)"#));
+        assert_err!(check(r#"country < "US""#));
+    }
+
+    #[test]
+    fn attributes_and_values_are_bound_as_parameters() {
+        let segment = assert_ok!(Segment::parse(
+            r#"country = "'; DROP TABLE subscriptions; --" OR NOT plan IN ("pro")"#
+        ));
+        let mut query = QueryBuilder::<Postgres>::new("");
+
+        segment.push_predicate(&mut query);
+
+        assert_eq!(
+            query.sql(),
+            "(COALESCE(attributes -> $1 = $2::jsonb, false) \
+            OR NOT $3::jsonb @> jsonb_build_array(attributes -> $4))"
+        );
+    }
+}
@@ -1,18 +1,27 @@
 use crate::{
-    configuration::{DatabaseSettings, Settings},
-    email_client::EmailClient,
-    routes,
+    canonical_email,
+    configuration::{DatabaseSettings, Settings, ValidatedTls},
+    digest, email_outbox,
+    email_policy::EmailPolicy,
+    error::StartupError,
+    newsletter_delivery, openapi, routes, subject_test,
+    tenant::{self, Tenants},
+    throttle::Throttle,
+    tls, webhooks,
 };
 use axum::{
-    extract::{FromRef, MatchedPath},
-    routing::{get, post},
-    Router,
+    extract::{DefaultBodyLimit, FromRef, MatchedPath},
+    handler::Handler,
+    middleware,
+    routing::{on, MethodFilter},
+    Router, ServiceExt,
 };
-use hyper::Request;
+use hyper::{header, Method, Request};
+use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::PgPool;
-use std::net::TcpListener;
-use tower::ServiceBuilder;
+use std::{net::TcpListener, sync::Arc};
+use tower::{Layer, ServiceBuilder};
 use tower_http::{
     request_id::MakeRequestUuid,
     trace::{DefaultOnFailure, DefaultOnRequest, DefaultOnResponse, TraceLayer},
@@ -23,8 +32,15 @@ use tracing::{info_span, Level};
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub db_pool: PgPool,
-    pub base_url: String,
-    pub email_client: EmailClient,
+    pub tenants: Tenants,
+    pub admin_token: Secret<String>,
+    pub email_policy: Arc<EmailPolicy>,
+    /// How long subject lines are tested before the winner is sent to everyone else.
+    pub subject_test_window: std::time::Duration,
+    /// Shared by the email client of every tenant.
+    pub email_throttle: Arc<Throttle>,
+    /// How long to wait before retrying a failed newsletter delivery the first time.
+    pub newsletter_delivery_retry_delay: std::time::Duration,
 }
 
 impl FromRef<AppState> for PgPool {
@@ -35,30 +51,49 @@ impl FromRef<AppState> for PgPool {
 
 pub struct Application {
     app: Router,
+    /// Every operation `app` routes, see [`Application::routed_operations`].
+    routed_operations: Vec<(Method, String)>,
+    state: AppState,
     listener: TcpListener,
+    tls: Option<ValidatedTls>,
+    /// Plain HTTP, redirected to HTTPS.
+    redirect_listener: Option<TcpListener>,
+    digest_check_interval: std::time::Duration,
+    subject_test_check_interval: std::time::Duration,
+    webhook_check_interval: std::time::Duration,
+    webhook_retry_delay: std::time::Duration,
+    email_outbox_check_interval: std::time::Duration,
+    email_outbox_retry_delay: std::time::Duration,
+    newsletter_delivery_check_interval: std::time::Duration,
 }
 
 impl Application {
-    pub fn build(settings: Settings) -> Self {
+    pub fn build(settings: Settings) -> Result<Self, StartupError> {
+        let settings = settings.validate()?;
         let db_pool = get_connection_pool(&settings.database);
-
-        let sender_email = settings
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-
-        let timeout = settings.email_client.timeout();
-        let email_client = EmailClient::new(
-            settings.email_client.base_url,
-            sender_email,
-            settings.email_client.authorization_token,
-            timeout,
-        );
+        let email_throttle = Arc::new(Throttle::new(settings.email_throttle.clone()));
+        let tenants = Tenants::new(&settings.tenants, &email_throttle);
+        let email_policy =
+            EmailPolicy::new(settings.disposable_domains_file.clone()).map_err(|source| {
+                StartupError::ReadFile {
+                    path: settings.disposable_domains_file.clone().unwrap_or_default(),
+                    source,
+                }
+            })?;
+        let listener = bind(&settings.address)?;
+        let redirect_listener = settings
+            .tls
+            .as_ref()
+            .and_then(|tls| tls.redirect_address.as_deref())
+            .map(bind)
+            .transpose()?;
 
         let request_layer = ServiceBuilder::new().layer(
             // from https://docs.rs/tower-http/0.2.5/tower_http/request_id/index.html#using-trace
             ServiceBuilder::new()
                 .set_x_request_id(MakeRequestUuid)
+                // The admin token is sent as a bearer token: keep it out of the logged headers.
+                .sensitive_request_headers(Arc::new([header::AUTHORIZATION]))
                 .layer(
                     TraceLayer::new_for_http()
                         .make_span_with(|request: &Request<_>| {
@@ -93,24 +128,36 @@ impl Application {
 
         let state = AppState {
             db_pool,
-            email_client,
-            base_url: settings.application.base_url.clone(),
+            tenants,
+            admin_token: settings.admin_token,
+            email_policy: Arc::new(email_policy),
+            subject_test_window: settings.subject_test_window,
+            email_throttle,
+            newsletter_delivery_retry_delay: settings.newsletter_delivery_retry_delay,
         };
 
-        let router = Router::new()
-            .route("/health_check", get(routes::health_check))
-            .route("/subscriptions", post(routes::subscribe))
-            .route("/subscriptions/confirm", get(routes::confirm))
-            .route("/newsletters", post(routes::publish_newsletter))
-            .with_state(state)
+        let app_routes = app_routes(state.clone());
+        let routed_operations = app_routes.operations;
+        let router = app_routes
+            .router
+            .with_state(state.clone())
             .layer(request_layer);
 
-        let listener = TcpListener::bind(settings.application.address()).unwrap();
-
-        Application {
+        Ok(Application {
             app: router,
+            routed_operations,
+            state,
             listener,
-        }
+            tls: settings.tls,
+            redirect_listener,
+            digest_check_interval: settings.digest_check_interval,
+            subject_test_check_interval: settings.subject_test_check_interval,
+            webhook_check_interval: settings.webhook_check_interval,
+            webhook_retry_delay: settings.webhook_retry_delay,
+            email_outbox_check_interval: settings.email_outbox_check_interval,
+            email_outbox_retry_delay: settings.email_outbox_retry_delay,
+            newsletter_delivery_check_interval: settings.newsletter_delivery_check_interval,
+        })
     }
 
     #[must_use]
@@ -118,18 +165,253 @@ impl Application {
         format!("{}", self.listener.local_addr().unwrap())
     }
 
+    /// Every method and path the application routes, in the notation of the `OpenAPI`
+    /// document, e.g. `GET /admin/subscribers/{subscriber_id}`.
+    #[must_use]
+    pub fn routed_operations(&self) -> &[(Method, String)] {
+        &self.routed_operations
+    }
+
     #[must_use]
     pub fn port(&self) -> u16 {
         self.listener.local_addr().unwrap().port()
     }
 
-    pub async fn run(self) -> Result<(), hyper::Error> {
-        hyper::Server::from_tcp(self.listener)?
-            .serve(self.app.into_make_service())
+    /// The port plain HTTP is redirected from, if any.
+    #[must_use]
+    pub fn redirect_port(&self) -> Option<u16> {
+        self.redirect_listener
+            .as_ref()
+            .map(|listener| listener.local_addr().unwrap().port())
+    }
+
+    pub async fn run(self) -> Result<(), std::io::Error> {
+        let https_port = self.port();
+        // Before serving, so that nobody re-subscribes as a duplicate of a legacy row.
+        if let Err(e) = canonical_email::backfill(&self.state.db_pool).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to backfill canonical emails, retrying on the next start"
+            );
+        }
+        tokio::spawn(self.state.email_policy.clone().reload_on_change());
+        tokio::spawn(digest::send_on_schedule(
+            self.state.db_pool.clone(),
+            self.state.tenants.clone(),
+            self.digest_check_interval,
+        ));
+        tokio::spawn(subject_test::send_winners_on_schedule(
+            self.state.db_pool.clone(),
+            self.state.tenants.clone(),
+            self.subject_test_check_interval,
+        ));
+        tokio::spawn(webhooks::deliver_on_schedule(
+            self.state.db_pool.clone(),
+            self.webhook_check_interval,
+            self.webhook_retry_delay,
+        ));
+        tokio::spawn(email_outbox::deliver_on_schedule(
+            self.state.db_pool.clone(),
+            self.state.tenants.clone(),
+            self.email_outbox_check_interval,
+            self.email_outbox_retry_delay,
+        ));
+        tokio::spawn(newsletter_delivery::deliver_on_schedule(
+            self.state.db_pool.clone(),
+            self.state.tenants.clone(),
+            self.newsletter_delivery_check_interval,
+            self.state.newsletter_delivery_retry_delay,
+        ));
+        // Tenants are resolved outside of the router, so that stripping their path prefix
+        // happens before routing.
+        let app =
+            middleware::from_fn_with_state(self.state, tenant::resolve_tenant).layer(self.app);
+
+        let Some(settings) = self.tls else {
+            return axum_server::from_tcp(self.listener)
+                .serve(app.into_make_service())
+                .await;
+        };
+
+        let config = tls::load(&settings).await?;
+        tokio::spawn(tls::reload_on_change(
+            config.clone(),
+            settings,
+            tls::hangups()?,
+        ));
+        if let Some(listener) = self.redirect_listener {
+            tokio::spawn(
+                axum_server::from_tcp(listener)
+                    .serve(tls::redirect_to_https(https_port).into_make_service()),
+            );
+        }
+        axum_server::from_tcp_rustls(self.listener, config)
+            .serve(app.into_make_service())
             .await
     }
 }
 
+/// Every endpoint, the `/admin` ones included.
+fn app_routes(state: AppState) -> Routes {
+    Routes::new("")
+        .route(Method::GET, "/health_check", routes::health_check)
+        .route(Method::GET, "/ready", routes::ready)
+        .route(Method::POST, "/subscriptions", routes::subscribe)
+        .route(
+            Method::POST,
+            "/api/v1/subscriptions",
+            routes::subscribe_json,
+        )
+        .route(Method::GET, "/subscriptions/confirm", routes::confirm)
+        .route(
+            Method::POST,
+            "/subscriptions/data_requests",
+            routes::request_data,
+        )
+        .route(Method::GET, "/subscriptions/data", routes::manage_data)
+        .route(
+            Method::GET,
+            "/subscriptions/data/export",
+            routes::export_data,
+        )
+        .route(
+            Method::POST,
+            "/subscriptions/data/erase",
+            routes::erase_data,
+        )
+        .route(Method::GET, "/preferences", routes::show_preferences)
+        .route(Method::POST, "/preferences", routes::update_preferences)
+        // Publishing emails every subscriber, so it takes the admin token too.
+        .route(
+            Method::POST,
+            "/newsletters",
+            routes::publish_newsletter.layer(middleware::from_fn_with_state(
+                state.clone(),
+                routes::require_admin,
+            )),
+        )
+        .route(Method::GET, "/newsletters/open", routes::track_open)
+        .route(Method::GET, "/newsletters/click", routes::track_click)
+        .route(Method::GET, "/openapi.json", openapi::openapi_json)
+        .route(Method::GET, "/docs", openapi::docs)
+        .nest(admin_routes(state))
+}
+
+/// The `/admin` endpoints, guarded by the admin token.
+fn admin_routes(state: AppState) -> Routes {
+    let admin = Routes::new("/admin")
+        .route(
+            Method::POST,
+            "/subscribers/import",
+            routes::import_subscribers.layer(DefaultBodyLimit::max(routes::MAX_IMPORT_SIZE)),
+        )
+        .route(
+            Method::GET,
+            "/subscribers/export",
+            routes::export_subscribers,
+        )
+        .route(Method::GET, "/subscribers", routes::search_subscribers)
+        .route(
+            Method::GET,
+            "/subscribers/:subscriber_id",
+            routes::get_subscriber,
+        )
+        .route(
+            Method::PUT,
+            "/subscribers/:subscriber_id/attributes",
+            routes::put_subscriber_attributes,
+        )
+        .route(Method::GET, "/audit_events", routes::search_audit_events)
+        .route(Method::GET, "/email_domains", routes::list_email_domains)
+        .route(
+            Method::PUT,
+            "/email_domains/:domain",
+            routes::put_email_domain,
+        )
+        .route(
+            Method::DELETE,
+            "/email_domains/:domain",
+            routes::delete_email_domain,
+        )
+        .route(Method::GET, "/lists", routes::list_mailing_lists)
+        .route(Method::PUT, "/lists/:slug", routes::put_mailing_list)
+        .route(Method::DELETE, "/lists/:slug", routes::delete_mailing_list)
+        .route(Method::GET, "/webhooks", routes::list_webhooks)
+        .route(Method::POST, "/webhooks", routes::create_webhook)
+        .route(
+            Method::DELETE,
+            "/webhooks/:webhook_id",
+            routes::delete_webhook,
+        )
+        .route(Method::GET, "/metrics", routes::metrics)
+        .route(
+            Method::GET,
+            "/webhooks/:webhook_id/deliveries",
+            routes::list_webhook_deliveries,
+        );
+    Routes {
+        router: admin
+            .router
+            .route_layer(middleware::from_fn_with_state(state, routes::require_admin)),
+        ..admin
+    }
+}
+
+/// A router that keeps track of the operations it routes, so that tests can check they are all
+/// documented.
+struct Routes {
+    /// Where the router is nested, e.g. `/admin`.
+    prefix: &'static str,
+    router: Router<AppState>,
+    /// Each method and prefixed path, in the notation of the `OpenAPI` document.
+    operations: Vec<(Method, String)>,
+}
+
+impl Routes {
+    fn new(prefix: &'static str) -> Self {
+        Self {
+            prefix,
+            router: Router::new(),
+            operations: vec![],
+        }
+    }
+
+    /// Route `method` requests to `path` to `handler`. Routing another method to the same path
+    /// adds to it.
+    fn route<H, T>(mut self, method: Method, path: &str, handler: H) -> Self
+    where
+        H: Handler<T, AppState>,
+        T: 'static,
+    {
+        let filter = MethodFilter::try_from(method.clone()).expect("Unsupported method");
+        self.router = self.router.route(path, on(filter, handler));
+        let path = path
+            .split('/')
+            .map(|segment| match segment.strip_prefix(':') {
+                Some(parameter) => format!("{{{parameter}}}"),
+                None => segment.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        self.operations
+            .push((method, format!("{}{path}", self.prefix)));
+        self
+    }
+
+    fn nest(mut self, routes: Routes) -> Self {
+        self.router = self.router.nest(routes.prefix, routes.router);
+        self.operations.extend(routes.operations);
+        self
+    }
+}
+
+fn bind(address: &str) -> Result<TcpListener, StartupError> {
+    TcpListener::bind(address).map_err(|source| StartupError::Bind {
+        address: address.to_owned(),
+        source,
+    })
+}
+
 #[must_use]
 pub fn get_connection_pool(settings: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
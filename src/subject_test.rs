@@ -0,0 +1,595 @@
+//! Subject line tests: an issue goes out under several subjects to a random cohort of its
+//! recipients first. Once the test window closes, the subject with the best open or click rate
+//! is sent to everyone else.
+//!
+//! Opens are tracked with an image embedded in the cohort's emails, clicks by routing their
+//! links through us. Everyone outside the cohort gets the issue as it was written.
+//!
+//! Every instance runs [`send_winners_on_schedule`]. Rows are locked while they are worked on,
+//! so instances never pick a winner or email a subscriber twice. An email that fails to send is
+//! retried with exponential backoff, while the other subscribers get theirs, and its error is
+//! recorded on the delivery after [`MAX_ATTEMPTS`]. While the tenant's circuit breaker is open,
+//! emails are put off without counting as attempts.
+
+use std::{fmt::Write, time::Duration};
+
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Action, Actor, AuditEvent, Target},
+    domain::{subscriber_email::SubscriberEmail, winner_metric::WinnerMetric},
+    email_client::recipient_variable,
+    error::SendEmailError,
+    i18n::{translate, Locale},
+    routes::{preferences_link, with_footer, SubjectTestBody},
+    tenant::Tenants,
+    webhooks::backoff,
+};
+
+/// An email that failed to send is first retried after this long, doubling with each attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Emails are given up on after this many attempts, about ten hours.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// More variants than this need cohorts larger than most lists to tell them apart.
+pub const MAX_SUBJECT_VARIANTS: usize = 5;
+
+/// A validated [`SubjectTestBody`].
+#[derive(Debug)]
+pub struct SubjectTest {
+    pub subjects: Vec<String>,
+    pub cohort_percentage: u8,
+    pub winner_metric: WinnerMetric,
+}
+
+impl TryFrom<SubjectTestBody> for SubjectTest {
+    type Error = String;
+
+    fn try_from(body: SubjectTestBody) -> Result<Self, Self::Error> {
+        if !(2..=MAX_SUBJECT_VARIANTS).contains(&body.subjects.len()) {
+            return Err(format!(
+                "A subject test needs between 2 and {MAX_SUBJECT_VARIANTS} subjects."
+            ));
+        }
+        if body
+            .subjects
+            .iter()
+            .any(|subject| subject.trim().is_empty())
+        {
+            return Err("Subjects can't be empty.".to_owned());
+        }
+        if !(1..=99).contains(&body.cohort_percentage) {
+            return Err(
+                "The cohort percentage must be between 1 and 99, leaving someone to send the \
+                winner to."
+                    .to_owned(),
+            );
+        }
+        let winner_metric = match body.winner_by.as_deref() {
+            None => WinnerMetric::OpenRate,
+            Some(metric) => WinnerMetric::try_from(metric)?,
+        };
+        Ok(Self {
+            subjects: body.subjects,
+            cohort_percentage: body.cohort_percentage,
+            winner_metric,
+        })
+    }
+}
+
+impl SubjectTest {
+    /// The variant sent to each of `recipients`, already shuffled, `None` outside the cohort.
+    ///
+    /// The cohort is rounded up, so that a test on a short list still tries something.
+    #[must_use]
+    pub fn assign_variants(&self, recipients: usize) -> Vec<Option<i16>> {
+        let cohort = (recipients * usize::from(self.cohort_percentage)).div_ceil(100);
+        (0..recipients)
+            .map(|i| {
+                (i < cohort).then(|| {
+                    i16::try_from(i % self.subjects.len()).expect("There are at most 5 variants")
+                })
+            })
+            .collect()
+    }
+}
+
+/// How one variant did.
+#[derive(Debug, Clone, Copy)]
+struct VariantResult {
+    variant: i16,
+    sent: i64,
+    opened: i64,
+    clicked: i64,
+}
+
+/// The variant with the best rate, the first one on a tie. `results` are ordered by variant.
+fn pick_winner(metric: WinnerMetric, results: &[VariantResult]) -> i16 {
+    let hits = |result: &VariantResult| match metric {
+        WinnerMetric::OpenRate => result.opened,
+        WinnerMetric::ClickRate => result.clicked,
+    };
+    let mut winner: Option<&VariantResult> = None;
+    for result in results {
+        // Rates are compared by cross-multiplying, so that no float is involved.
+        if winner
+            .is_none_or(|best| hits(result) * best.sent.max(1) > hits(best) * result.sent.max(1))
+        {
+            winner = Some(result);
+        }
+    }
+    winner.map_or(0, |winner| winner.variant)
+}
+
+/// The issue's HTML as sent to the cohort: its links go through us to count clicks, and it
+/// carries an image counting opens.
+#[must_use]
+pub fn track(html: &str, base_url: &str, tracking_token: &str) -> String {
+    let mut tracked = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some((link, start, end)) = next_link(rest) {
+        tracked.push_str(&rest[..start]);
+        if link.starts_with("http://") || link.starts_with("https://") {
+            let url: String = url::form_urlencoded::byte_serialize(link.as_bytes()).collect();
+            let _ = write!(
+                tracked,
+                "{base_url}/newsletters/click?tracking_token={tracking_token}&amp;url={url}"
+            );
+        } else {
+            tracked.push_str(link);
+        }
+        rest = &rest[end..];
+    }
+    tracked.push_str(rest);
+    let _ = write!(
+        tracked,
+        r#"<img src="{base_url}/newsletters/open?tracking_token={tracking_token}" width="1" height="1" alt="" />"#
+    );
+    tracked
+}
+
+/// Whether `url` is one of the links in `html`, so that following it from a tracked email
+/// doesn't redirect anywhere else.
+#[must_use]
+pub fn links_to(html: &str, url: &str) -> bool {
+    let mut rest = html;
+    while let Some((link, _, end)) = next_link(rest) {
+        if link == url {
+            return true;
+        }
+        rest = &rest[end..];
+    }
+    false
+}
+
+/// The next `href="…"` in `html`: its value as written, and where that value starts and ends.
+fn next_link(html: &str) -> Option<(&str, usize, usize)> {
+    const HREF: &str = "href=\"";
+    let start = html.find(HREF)? + HREF.len();
+    let end = start + html[start..].find('"')?;
+    Some((&html[start..end], start, end))
+}
+
+/// Pick the winners of the tests whose window closed, and send them, every `interval`. Never
+/// returns.
+pub async fn send_winners_on_schedule(pool: PgPool, tenants: Tenants, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        if let Err(e) = send_winners(&pool, &tenants).await {
+            tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to send the winners of subject tests, retrying on the next check"
+            );
+        }
+    }
+}
+
+#[tracing::instrument(name = "Send the winners of subject tests", skip_all)]
+async fn send_winners(pool: &PgPool, tenants: &Tenants) -> Result<(), anyhow::Error> {
+    while pick_next_winner(pool).await?.is_some() {}
+    let mut sent = 0;
+    while let Some(was_sent) = send_next_winner(pool, tenants).await? {
+        if was_sent {
+            sent += 1;
+        }
+    }
+    if sent > 0 {
+        tracing::info!(sent, "Sent the winners of subject tests");
+    }
+    Ok(())
+}
+
+/// Pick the winner of one test whose window closed, `None` if there is no such test left.
+#[tracing::instrument(skip_all, fields(newsletter_issue_id = tracing::field::Empty))]
+async fn pick_next_winner(pool: &PgPool) -> Result<Option<()>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let Some(issue) = sqlx::query!(
+        r#"
+    SELECT newsletter_issue_id, tenant, subject_variants AS "subject_variants!",
+      winner_metric AS "winner_metric!"
+    FROM newsletter_issues
+    WHERE winner_due_at <= now() AND winning_subject IS NULL
+    LIMIT 1
+    FOR UPDATE SKIP LOCKED
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for a subject test to close")?
+    else {
+        return Ok(None);
+    };
+    tracing::Span::current().record(
+        "newsletter_issue_id",
+        tracing::field::display(issue.newsletter_issue_id),
+    );
+
+    let results = sqlx::query_as!(
+        VariantResult,
+        r#"
+    SELECT variant AS "variant!", COUNT(*) AS "sent!", COUNT(opened_at) AS "opened!",
+      COUNT(clicked_at) AS "clicked!"
+    FROM newsletter_deliveries
+    WHERE newsletter_issue_id = $1 AND variant IS NOT NULL AND sent_at IS NOT NULL
+    GROUP BY variant
+    ORDER BY variant
+        "#,
+        issue.newsletter_issue_id,
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to count the opens and clicks of a subject test")?;
+
+    let metric =
+        WinnerMetric::try_from(issue.winner_metric.as_str()).map_err(anyhow::Error::msg)?;
+    let winner = pick_winner(metric, &results);
+    let subject = issue
+        .subject_variants
+        .get(usize::try_from(winner).unwrap_or_default())
+        .context("The winning variant has no subject")?;
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET winning_subject = $2 WHERE newsletter_issue_id = $1"#,
+        issue.newsletter_issue_id,
+        subject,
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the winning subject")?;
+
+    let details: Vec<_> = results
+        .iter()
+        .map(|result| {
+            serde_json::json!({
+                "subject": issue.subject_variants.get(usize::try_from(result.variant).unwrap_or_default()),
+                "sent": result.sent,
+                "opened": result.opened,
+                "clicked": result.clicked,
+            })
+        })
+        .collect();
+    audit::record(
+        &mut transaction,
+        &issue.tenant,
+        AuditEvent::new(
+            Actor::System("subject test"),
+            Action::PickSubject,
+            Target::NewsletterIssue(issue.newsletter_issue_id),
+        )
+        .details(serde_json::json!({
+            "winner_by": metric.as_str(),
+            "winning_subject": subject,
+            "variants": details,
+        })),
+    )
+    .await
+    .context("Failed to record the winning subject in the audit log")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(Some(()))
+}
+
+/// Send an issue under its winning subject to one subscriber outside its test cohort, `None` if
+/// there is no one left to send to, `Some(false)` if it wasn't sent to them.
+#[tracing::instrument(skip_all, fields(subscriber_id = tracing::field::Empty))]
+async fn send_next_winner(pool: &PgPool, tenants: &Tenants) -> Result<Option<bool>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let Some(delivery) = sqlx::query!(
+        r#"
+    SELECT d.newsletter_issue_id, d.subscriber_id, i.tenant,
+      i.winning_subject AS "winning_subject!", i.text_content, i.html_content,
+      s.email, s.name, s.locale, s.preferences_token, d.attempts
+    FROM newsletter_deliveries d
+    JOIN newsletter_issues i USING (newsletter_issue_id)
+    JOIN subscriptions s ON s.id = d.subscriber_id
+    WHERE d.variant IS NULL AND d.sent_at IS NULL AND d.error IS NULL
+      AND i.winning_subject IS NOT NULL AND (d.next_attempt_at IS NULL OR d.next_attempt_at <= now())
+    ORDER BY d.next_attempt_at NULLS FIRST, i.winner_due_at
+    LIMIT 1
+    FOR UPDATE OF d SKIP LOCKED
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for a subscriber due a winning subject")?
+    else {
+        return Ok(None);
+    };
+    tracing::Span::current().record(
+        "subscriber_id",
+        tracing::field::display(delivery.subscriber_id),
+    );
+
+    // `None` when the subscriber was skipped rather than sent to.
+    let outcome = match (
+        tenants.get(&delivery.tenant),
+        SubscriberEmail::try_from(delivery.email),
+    ) {
+        (Some(tenant), Ok(email)) => {
+            let locale = Locale::from(delivery.locale.as_str());
+            // Filled in here as this email isn't sent in a batch.
+            let with_name =
+                |template: &str| template.replace(&recipient_variable("name"), &delivery.name);
+            let (html, text) = with_footer(
+                locale,
+                &preferences_link(&tenant.base_url, &delivery.preferences_token),
+                Some(&translate(locale, "newsletter-footer", &[])),
+                &with_name(&delivery.html_content),
+                &with_name(&delivery.text_content),
+            );
+            Some(
+                tenant
+                    .email_client
+                    .send_email(&email, &with_name(&delivery.winning_subject), &html, &text)
+                    .await,
+            )
+        }
+        (None, _) => {
+            tracing::warn!(
+                tenant = delivery.tenant,
+                "Skipping a subscriber of a tenant that is no longer configured"
+            );
+            None
+        }
+        (_, Err(error)) => {
+            tracing::warn!(
+                %error,
+                "Skipping a subscriber. Their stored contact details are invalid"
+            );
+            None
+        }
+    };
+
+    record_outcome(
+        &mut transaction,
+        delivery.newsletter_issue_id,
+        delivery.subscriber_id,
+        delivery.attempts + 1,
+        outcome.as_ref(),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(Some(matches!(outcome, Some(Ok(())))))
+}
+
+/// Record the `attempts`th attempt at sending the issue to a subscriber, `None` if they were
+/// skipped.
+async fn record_outcome(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    attempts: i32,
+    outcome: Option<&Result<(), SendEmailError>>,
+) -> Result<(), anyhow::Error> {
+    match outcome {
+        Some(Err(SendEmailError::Unavailable)) => {
+            put_off(transaction, newsletter_issue_id, subscriber_id).await
+        }
+        Some(Err(e)) => {
+            retry_later(
+                transaction,
+                newsletter_issue_id,
+                subscriber_id,
+                attempts,
+                &e.to_string(),
+            )
+            .await
+        }
+        // Even when nothing was sent, so that they aren't looked at again.
+        None | Some(Ok(())) => {
+            mark_sent(&mut **transaction, newsletter_issue_id, subscriber_id).await
+        }
+    }
+}
+
+/// Record the `attempts`th failed attempt at sending the issue to a subscriber, backing off
+/// before the next one, or giving up with `error` after [`MAX_ATTEMPTS`].
+async fn retry_later(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+    attempts: i32,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    let given_up = attempts >= MAX_ATTEMPTS;
+    if given_up {
+        tracing::error!(attempts, error, "Giving up on sending the winning subject");
+    } else {
+        tracing::warn!(attempts, error, "Failed to send the winning subject");
+    }
+    sqlx::query!(
+        r#"
+    UPDATE newsletter_deliveries
+    SET attempts = $3, next_attempt_at = now() + $4::float8 * interval '1 second',
+      error = CASE WHEN $5 THEN $6 END
+    WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+        attempts,
+        backoff(RETRY_DELAY, attempts).as_secs_f64(),
+        given_up,
+        error,
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to record the failed delivery")?;
+    Ok(())
+}
+
+/// Try sending the issue to a subscriber again later, as the circuit breaker was open, without
+/// counting it as an attempt.
+async fn put_off(
+    transaction: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE newsletter_deliveries SET next_attempt_at = now() + $3::float8 * interval '1 second'
+    WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+        RETRY_DELAY.as_secs_f64(),
+    )
+    .execute(&mut **transaction)
+    .await
+    .context("Failed to put off the delivery")?;
+    Ok(())
+}
+
+/// Record that the issue was sent to a subscriber.
+pub async fn mark_sent(
+    executor: impl sqlx::PgExecutor<'_>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE newsletter_deliveries SET sent_at = now()
+    WHERE newsletter_issue_id = $1 AND subscriber_id = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_id,
+    )
+    .execute(executor)
+    .await
+    .context("Failed to record the delivery")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{links_to, pick_winner, track, SubjectTest, VariantResult};
+    use crate::{domain::winner_metric::WinnerMetric, routes::SubjectTestBody};
+    use claims::{assert_err, assert_ok};
+
+    fn body(subjects: &[&str], cohort_percentage: u8, winner_by: Option<&str>) -> SubjectTestBody {
+        SubjectTestBody {
+            subjects: subjects.iter().map(|&s| s.to_owned()).collect(),
+            cohort_percentage,
+            winner_by: winner_by.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn invalid_tests_are_rejected() {
+        assert_err!(SubjectTest::try_from(body(&["Only one"], 20, None)));
+        assert_err!(SubjectTest::try_from(body(
+            &["a", "b", "c", "d", "e", "f"],
+            20,
+            None
+        )));
+        assert_err!(SubjectTest::try_from(body(&["A", " "], 20, None)));
+        assert_err!(SubjectTest::try_from(body(&["A", "B"], 0, None)));
+        assert_err!(SubjectTest::try_from(body(&["A", "B"], 100, None)));
+        assert_err!(SubjectTest::try_from(body(
+            &["A", "B"],
+            20,
+            Some("revenue")
+        )));
+        let test = assert_ok!(SubjectTest::try_from(body(&["A", "B"], 20, None)));
+        assert_eq!(test.winner_metric, WinnerMetric::OpenRate);
+    }
+
+    #[test]
+    fn the_cohort_is_rounded_up_and_split_between_variants() {
+        let test = assert_ok!(SubjectTest::try_from(body(&["A", "B"], 25, None)));
+
+        assert_eq!(
+            test.assign_variants(10),
+            vec![
+                Some(0),
+                Some(1),
+                Some(0),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None
+            ]
+        );
+        assert_eq!(test.assign_variants(1), vec![Some(0)]);
+        assert!(test.assign_variants(0).is_empty());
+    }
+
+    #[test]
+    fn the_best_rate_wins_and_ties_go_to_the_first_variant() {
+        let result = |variant, sent, opened, clicked| VariantResult {
+            variant,
+            sent,
+            opened,
+            clicked,
+        };
+        let results = [result(0, 10, 5, 1), result(1, 4, 3, 0), result(2, 10, 5, 3)];
+
+        assert_eq!(pick_winner(WinnerMetric::OpenRate, &results), 1);
+        assert_eq!(pick_winner(WinnerMetric::ClickRate, &results), 2);
+        assert_eq!(
+            pick_winner(
+                WinnerMetric::OpenRate,
+                &[result(0, 2, 1, 0), result(1, 4, 2, 0)]
+            ),
+            0
+        );
+        assert_eq!(pick_winner(WinnerMetric::OpenRate, &[]), 0);
+    }
+
+    #[test]
+    fn web_links_go_through_us_and_an_image_counts_opens() {
+        let html =
+            r##"<a href="https://example.com/?a=1&amp;b=2">Read</a> <a href="#top">Top</a>"##;
+
+        let tracked = track(html, "https://news.example.com", "token");
+
+        assert!(tracked.starts_with(
+            r#"<a href="https://news.example.com/newsletters/click?tracking_token=token&amp;url=https%3A%2F%2Fexample.com%2F%3Fa%3D1%26amp%3Bb%3D2">Read</a>"#
+        ));
+        assert!(tracked.contains(r##"<a href="#top">"##));
+        assert!(tracked.ends_with(
+            r#"<img src="https://news.example.com/newsletters/open?tracking_token=token" width="1" height="1" alt="" />"#
+        ));
+        assert!(links_to(html, "https://example.com/?a=1&amp;b=2"));
+        assert!(!links_to(html, "https://evil.example.com"));
+    }
+}
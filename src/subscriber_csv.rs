@@ -0,0 +1,369 @@
+//! Bulk import and export of subscribers as CSV, with columns `email,name,status,subscribed_at`.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::audit::{self, Action, Actor, AuditEvent, Target};
+use crate::domain::{
+    subscriber_email::SubscriberEmail, subscriber_name::SubscriberName,
+    subscription_status::SubscriptionStatus, webhook_event_type::WebhookEventType,
+};
+use crate::email_policy::EmailPolicy;
+use crate::routes::generate_subscription_token;
+use crate::webhooks;
+
+/// Rows inserted per `INSERT` statement when importing.
+const IMPORT_BATCH_SIZE: usize = 1000;
+/// Rows serialised per chunk of the export body.
+const EXPORT_CHUNK_SIZE: usize = 500;
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct CsvRow {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: String,
+}
+
+/// A subscriber read from an import file, validated like any other signup.
+#[derive(Debug)]
+struct ImportedSubscriber {
+    line: u64,
+    email: SubscriberEmail,
+    name: SubscriberName,
+    status: SubscriptionStatus,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl ImportedSubscriber {
+    fn parse(line: u64, row: &CsvRow) -> Result<Self, String> {
+        let email = SubscriberEmail::try_from(row.email.as_str())?;
+        let name = SubscriberName::try_from(row.name.as_str())?;
+        let status = SubscriptionStatus::try_from(row.status.as_str())?;
+        let subscribed_at = if row.subscribed_at.is_empty() {
+            Utc::now()
+        } else {
+            DateTime::parse_from_rfc3339(&row.subscribed_at)
+                .map_err(|e| format!("{} is not an RFC 3339 timestamp: {e}", row.subscribed_at))?
+                .with_timezone(&Utc)
+        };
+
+        Ok(Self {
+            line,
+            email,
+            name,
+            status,
+            subscribed_at,
+        })
+    }
+}
+
+/// The outcome of an import: rows are imported independently, so one bad row
+/// doesn't prevent the others from being loaded.
+#[derive(serde::Serialize, Debug, Default, utoipa::ToSchema)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub errors: Vec<RowError>,
+}
+
+#[derive(serde::Serialize, Debug, utoipa::ToSchema)]
+pub struct RowError {
+    /// 1-based line number in the CSV file, the header being line 1.
+    pub line: u64,
+    pub error: String,
+}
+
+/// Validate every row of `csv` and insert the valid ones in batches, in a single transaction.
+///
+/// Rows whose email is already subscribed, or that `policy` rejects, are reported as errors
+/// rather than imported. Every imported subscriber is recorded in the audit log as imported by `actor`.
+#[tracing::instrument(name = "Import subscribers from CSV", skip(pool, policy, csv))]
+pub async fn import(
+    pool: &PgPool,
+    policy: &EmailPolicy,
+    tenant: &str,
+    csv: impl std::io::Read,
+    actor: Actor,
+) -> Result<ImportReport, anyhow::Error> {
+    let mut report = ImportReport::default();
+    let mut subscribers = Vec::new();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+    let headers = reader
+        .headers()
+        .context("Failed to read the CSV header")?
+        .clone();
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                report.errors.push(RowError {
+                    line: e.position().map_or(0, csv::Position::line),
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let line = record.position().map_or(0, csv::Position::line);
+        let parsed = record
+            .deserialize::<CsvRow>(Some(&headers))
+            .map_err(|e| e.to_string())
+            .and_then(|row| ImportedSubscriber::parse(line, &row));
+
+        match parsed {
+            Ok(subscriber) => subscribers.push(subscriber),
+            Err(error) => report.errors.push(RowError { line, error }),
+        }
+    }
+
+    let verdicts = policy
+        .check_all(pool, tenant, subscribers.iter().map(|s| &s.email))
+        .await
+        .context("Failed to check the emails against the email policy")?;
+    let mut verdicts = verdicts.into_iter();
+    subscribers.retain(|subscriber| match verdicts.next() {
+        Some(Err(violation)) => {
+            report.errors.push(RowError {
+                line: subscriber.line,
+                error: violation.to_string(),
+            });
+            false
+        }
+        _ => true,
+    });
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    for batch in subscribers.chunks(IMPORT_BATCH_SIZE) {
+        let mut inserted = insert_batch(&mut transaction, tenant, batch)
+            .await
+            .context("Failed to insert a batch of subscribers")?;
+
+        let mut events = Vec::with_capacity(inserted.len());
+        let mut webhook_events = Vec::with_capacity(inserted.len());
+        for subscriber in batch {
+            if let Some(id) = inserted.remove(subscriber.email.canonical()) {
+                report.imported += 1;
+                events.push(
+                    AuditEvent::new(actor.clone(), Action::Import, Target::Subscriber(id))
+                        .status_change(None, Some(subscriber.status))
+                        .details(serde_json::json!({ "line": subscriber.line })),
+                );
+                webhook_events.push(serde_json::json!({
+                    "id": id,
+                    "email": subscriber.email.as_ref(),
+                    "name": subscriber.name.as_ref(),
+                    "status": subscriber.status.as_str(),
+                }));
+            } else {
+                report.errors.push(RowError {
+                    line: subscriber.line,
+                    error: format!("{} is already subscribed.", subscriber.email),
+                });
+            }
+        }
+
+        audit::record_all(&mut transaction, tenant, &events)
+            .await
+            .context("Failed to record the imported subscribers in the audit log")?;
+        webhooks::enqueue_all(
+            &mut transaction,
+            tenant,
+            WebhookEventType::SubscriberCreated,
+            webhook_events,
+        )
+        .await
+        .context("Failed to enqueue the subscriber.created webhook events")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+
+    report.errors.sort_by_key(|e| e.line);
+    Ok(report)
+}
+
+/// Insert a batch of subscribers, returning the ids of the ones actually inserted by canonical
+/// email.
+#[tracing::instrument(name = "Insert a batch of subscribers", skip_all, fields(batch_size = batch.len()))]
+async fn insert_batch(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    batch: &[ImportedSubscriber],
+) -> Result<HashMap<String, Uuid>, sqlx::Error> {
+    let ids: Vec<Uuid> = batch.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = batch.iter().map(|s| s.email.as_ref().to_owned()).collect();
+    let canonical_emails: Vec<String> = batch
+        .iter()
+        .map(|s| s.email.canonical().to_owned())
+        .collect();
+    let names: Vec<String> = batch.iter().map(|s| s.name.as_ref().to_owned()).collect();
+    let statuses: Vec<String> = batch.iter().map(|s| s.status.as_str().to_owned()).collect();
+    let subscribed_at: Vec<DateTime<Utc>> = batch.iter().map(|s| s.subscribed_at).collect();
+    let preferences_tokens: Vec<String> = batch
+        .iter()
+        .map(|_| generate_subscription_token())
+        .collect();
+
+    let rows = sqlx::query!(
+        r#"
+    INSERT INTO subscriptions
+      (tenant, id, email, canonical_email, name, subscribed_at, status, preferences_token)
+    SELECT $1::text, * FROM UNNEST(
+      $2::uuid[], $3::text[], $4::text[], $5::text[], $6::timestamptz[], $7::text[], $8::text[]
+    )
+    ON CONFLICT (tenant, canonical_email) DO NOTHING
+    RETURNING id, canonical_email
+        "#,
+        tenant,
+        &ids,
+        &emails,
+        &canonical_emails,
+        &names,
+        &subscribed_at,
+        &statuses,
+        &preferences_tokens,
+    )
+    .fetch_all(&mut **transaction)
+    .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|r| (r.canonical_email, r.id))
+        .collect())
+}
+
+/// Stream every subscriber of `tenant` as CSV, header first.
+///
+/// Rows are read from a database cursor by a background task, so memory usage stays flat
+/// however large the list is.
+pub fn export(pool: PgPool, tenant: String) -> impl Stream<Item = Result<Vec<u8>, anyhow::Error>> {
+    let (sender, receiver) = mpsc::channel(4);
+
+    tokio::spawn(async move {
+        if let Err(e) = write_export(&pool, &tenant, &sender).await {
+            tracing::error!(error.cause_chain = ?e, "Failed to export subscribers");
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+async fn write_export(
+    pool: &PgPool,
+    tenant: &str,
+    sender: &mpsc::Sender<Result<Vec<u8>, anyhow::Error>>,
+) -> Result<(), anyhow::Error> {
+    let mut rows = sqlx::query!(
+        r#"
+    SELECT email, name, status, subscribed_at
+    FROM subscriptions
+    WHERE tenant = $1
+    ORDER BY subscribed_at, id
+        "#,
+        tenant
+    )
+    .fetch(pool)
+    .chunks(EXPORT_CHUNK_SIZE);
+
+    let header = ["email", "name", "status", "subscribed_at"];
+    if sender.send(to_csv(&[header])).await.is_err() {
+        return Ok(());
+    }
+
+    while let Some(chunk) = rows.next().await {
+        let chunk = chunk
+            .into_iter()
+            .map(|row| {
+                row.map(|row| CsvRow {
+                    email: row.email,
+                    name: row.name,
+                    status: row.status,
+                    subscribed_at: row.subscribed_at.to_rfc3339(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to read a subscriber")?;
+
+        if sender.send(to_csv(&chunk)).await.is_err() {
+            // The client went away, no point in reading further.
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+fn to_csv(records: &[impl serde::Serialize]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    for record in records {
+        writer.serialize(record)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvRow, ImportedSubscriber};
+    use claims::{assert_err, assert_ok};
+
+    fn row(email: &str, name: &str, status: &str, subscribed_at: &str) -> CsvRow {
+        CsvRow {
+            email: email.into(),
+            name: name.into(),
+            status: status.into(),
+            subscribed_at: subscribed_at.into(),
+        }
+    }
+
+    #[test]
+    fn a_valid_row_is_parsed_successfully() {
+        let row = row(
+            "ursula@domain.com",
+            "Ursula Le Guin",
+            "confirmed",
+            "2023-07-08T20:17:05Z",
+        );
+        assert_ok!(ImportedSubscriber::parse(2, &row));
+    }
+
+    #[test]
+    fn an_empty_timestamp_defaults_to_now() {
+        let row = row("ursula@domain.com", "Ursula", "confirmed", "");
+        assert_ok!(ImportedSubscriber::parse(2, &row));
+    }
+
+    #[test]
+    fn rows_with_invalid_fields_are_rejected() {
+        let test_cases = [
+            row("ursuladomain.com", "Ursula", "confirmed", ""),
+            row("ursula@domain.com", "", "confirmed", ""),
+            row("ursula@domain.com", "Ursula", "subscribed", ""),
+            row("ursula@domain.com", "Ursula", "confirmed", "yesterday"),
+        ];
+
+        for row in test_cases {
+            assert_err!(ImportedSubscriber::parse(2, &row));
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Several newsletters served by one deployment.
+//!
+//! Each request is resolved to a [`Tenant`] before routing, from a `/t/<slug>` path prefix or
+//! from its `Host` header, falling back to the default tenant. Handlers get it as an
+//! `Extension<Arc<Tenant>>` and scope every query with its slug.
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{header, uri::PathAndQuery, Request, Uri},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+
+use url::Url;
+
+use crate::{
+    circuit_breaker::CircuitBreaker, configuration::ValidatedTenant, email_client::EmailClient,
+    startup::AppState, throttle::Throttle,
+};
+
+/// Slug of the tenant described by the top-level `application` and `email_client` settings.
+pub const DEFAULT_TENANT: &str = "default";
+
+#[derive(Debug)]
+pub struct Tenant {
+    pub slug: String,
+    pub base_url: String,
+    pub email_client: EmailClient,
+}
+
+#[derive(Clone, Debug)]
+pub struct Tenants {
+    by_slug: HashMap<String, Arc<Tenant>>,
+    by_host: HashMap<String, Arc<Tenant>>,
+}
+
+impl Tenants {
+    /// Build every tenant, and its email client, all sending at the pace of `throttle`. Each
+    /// email client has a circuit breaker of its own, named after the tenant.
+    #[must_use]
+    pub fn new(tenants: &[ValidatedTenant], throttle: &Arc<Throttle>) -> Self {
+        let mut by_slug = HashMap::new();
+        let mut by_host = HashMap::new();
+        for settings in tenants {
+            let tenant = Arc::new(Tenant {
+                slug: settings.slug.clone(),
+                base_url: without_trailing_slash(&settings.base_url),
+                email_client: EmailClient::new(
+                    without_trailing_slash(&settings.email_client.base_url),
+                    settings.email_client.sender.clone(),
+                    settings.email_client.authorization_token.clone(),
+                    settings.email_client.timeout,
+                )
+                .provider(settings.email_client.provider)
+                .attachment_limits(settings.email_client.attachment_limits.clone())
+                .throttle(throttle.clone())
+                .circuit_breaker(Arc::new(CircuitBreaker::new(
+                    &settings.slug,
+                    settings.email_client.circuit_breaker_failures,
+                    settings.email_client.circuit_breaker_open,
+                ))),
+            });
+            for host in &settings.hosts {
+                by_host.insert(host.clone(), tenant.clone());
+            }
+            by_slug.insert(settings.slug.clone(), tenant);
+        }
+        Self { by_slug, by_host }
+    }
+
+    #[must_use]
+    pub fn get(&self, slug: &str) -> Option<Arc<Tenant>> {
+        self.by_slug.get(slug).cloned()
+    }
+
+    /// Every tenant, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Tenant>> {
+        self.by_slug.values()
+    }
+
+    #[must_use]
+    pub fn default_tenant(&self) -> Arc<Tenant> {
+        self.by_slug[DEFAULT_TENANT].clone()
+    }
+
+    /// The tenant serving `host`, ignoring its port.
+    #[must_use]
+    pub fn for_host(&self, host: &str) -> Option<Arc<Tenant>> {
+        let host = host.rsplit_once(':').map_or(host, |(name, _port)| name);
+        self.by_host.get(&host.to_lowercase()).cloned()
+    }
+}
+
+/// Resolve the tenant a request is for and store it in the request's extensions.
+///
+/// This runs before routing: a `/t/<slug>` prefix is stripped so the rest of the path
+/// matches the same routes as the default tenant.
+pub async fn resolve_tenant<B>(
+    State(state): State<AppState>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let tenants = &state.tenants;
+    let tenant = match split_tenant_prefix(request.uri()) {
+        Some((slug, uri)) => {
+            let Some(tenant) = tenants.get(slug) else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            *request.uri_mut() = uri;
+            tenant
+        }
+        None => request
+            .headers()
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .and_then(|host| tenants.for_host(host))
+            .unwrap_or_else(|| tenants.default_tenant()),
+    };
+
+    request.extensions_mut().insert(tenant);
+    next.run(request).await
+}
+
+/// We append paths to base URLs, `Url` always ends bare domains with a `/`.
+fn without_trailing_slash(url: &Url) -> String {
+    url.as_str().trim_end_matches('/').to_owned()
+}
+
+/// Split `/t/<slug>/rest?query` into `<slug>` and `/rest?query`.
+fn split_tenant_prefix(uri: &Uri) -> Option<(&str, Uri)> {
+    let rest = uri.path().strip_prefix("/t/")?;
+    let (slug, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_owned(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(PathAndQuery::try_from(path_and_query).ok()?);
+    Some((slug, Uri::from_parts(parts).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_tenant_prefix;
+    use axum::http::Uri;
+
+    #[test]
+    fn the_tenant_prefix_is_stripped_from_the_path() {
+        let uri: Uri = "/t/rust-weekly/subscriptions/confirm?subscription_token=abc"
+            .parse()
+            .unwrap();
+        let (slug, uri) = split_tenant_prefix(&uri).unwrap();
+        assert_eq!(slug, "rust-weekly");
+        assert_eq!(uri, "/subscriptions/confirm?subscription_token=abc");
+    }
+
+    #[test]
+    fn a_bare_prefix_maps_to_the_root() {
+        let uri: Uri = "/t/rust-weekly".parse().unwrap();
+        let (_, uri) = split_tenant_prefix(&uri).unwrap();
+        assert_eq!(uri, "/");
+    }
+
+    #[test]
+    fn paths_without_a_prefix_are_left_alone() {
+        for path in ["/subscriptions", "/tenants", "/t"] {
+            let uri: Uri = path.parse().unwrap();
+            assert!(split_tenant_prefix(&uri).is_none(), "{path}");
+        }
+    }
+}
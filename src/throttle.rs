@@ -0,0 +1,378 @@
+//! Pacing the emails we send, as mailbox providers like Gmail defer senders who send in bursts.
+//!
+//! Each email provider, and each recipient domain, has a budget of messages per second, of which
+//! a second's worth may go out at once. Sends over budget wait for their turn rather than fail:
+//! every message pushes back the time the next one is due by its share of a second, and a send
+//! waits until it is at most a second ahead of schedule. Batches are split with
+//! [`Throttle::at_once`], so that a request never carries more than a second's worth.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::{domain::subscriber_email::SubscriberEmail, email_client::EmailProvider};
+
+/// Beyond this many recipient domains, those that are back within budget are forgotten.
+const MAX_TRACKED_DOMAINS: usize = 1024;
+
+/// How many messages per second may go out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThrottleLimits {
+    /// Through each email provider.
+    pub messages_per_second: u32,
+    /// To each recipient domain, unless it has a limit in `domains`.
+    pub domain_messages_per_second: u32,
+    /// Limits of their own, by recipient domain, e.g. `gmail.com`.
+    pub domains: HashMap<String, u32>,
+}
+
+impl ThrottleLimits {
+    fn domain_messages_per_second(&self, domain: &str) -> u32 {
+        self.domains
+            .get(domain)
+            .copied()
+            .unwrap_or(self.domain_messages_per_second)
+    }
+
+    fn domain_pace(&self, domain: &str) -> Pace {
+        Pace::new(self.domain_messages_per_second(domain))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pace {
+    /// How much a message pushes back the next one.
+    interval: Duration,
+    /// How far ahead of schedule a message may go out.
+    tolerance: Duration,
+}
+
+impl Pace {
+    fn new(messages_per_second: u32) -> Self {
+        let messages_per_second = messages_per_second.max(1);
+        let interval = Duration::from_secs(1) / messages_per_second;
+        Self {
+            interval,
+            tolerance: interval * (messages_per_second - 1),
+        }
+    }
+}
+
+/// When the next message is due.
+#[derive(Debug, Clone, Copy)]
+struct Schedule {
+    next_at: Instant,
+}
+
+impl Schedule {
+    /// Book a message, returning when it may go out.
+    fn book(&mut self, pace: Pace, now: Instant) -> Instant {
+        let due_at = self.next_at.max(now);
+        self.next_at = due_at + pace.interval;
+        due_at
+            .checked_sub(pace.tolerance)
+            .map_or(now, |at| at.max(now))
+    }
+
+    fn backlog(self, now: Instant) -> Duration {
+        self.next_at.saturating_duration_since(now)
+    }
+}
+
+#[derive(Debug)]
+struct ProviderState {
+    schedule: Schedule,
+    /// Sends waiting for their turn.
+    waiting: u64,
+    messages: u64,
+    waited: Duration,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    providers: HashMap<EmailProvider, ProviderState>,
+    domains: HashMap<String, Schedule>,
+}
+
+/// Shared by the email clients of every tenant, as they may share a provider account and the
+/// mailbox providers receiving their emails.
+#[derive(Debug)]
+pub struct Throttle {
+    limits: ThrottleLimits,
+    state: Mutex<State>,
+}
+
+/// How far behind a provider's sends are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderThrottleState {
+    pub provider: EmailProvider,
+    /// Sends waiting for their turn.
+    pub waiting: u64,
+    /// Messages let through since we started.
+    pub messages: u64,
+    /// How long sends waited in total since we started.
+    pub waited: Duration,
+    /// How long until a message may go out at the pace of the provider's budget.
+    pub backlog: Duration,
+}
+
+/// The state of the throttle, for metrics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThrottleState {
+    pub providers: Vec<ProviderThrottleState>,
+    /// The recipient domains over budget, with how long until a message to them may go out at
+    /// their pace.
+    pub domains: Vec<(String, Duration)>,
+}
+
+impl Throttle {
+    #[must_use]
+    pub fn new(limits: ThrottleLimits) -> Self {
+        Self {
+            limits,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Which of `recipients`, by index, may go out at once: a second's worth through a provider,
+    /// and to each recipient domain. Always the first of them, so every batch makes progress.
+    #[must_use]
+    pub fn at_once(&self, recipients: &[&SubscriberEmail]) -> Vec<usize> {
+        let limit = self.limits.messages_per_second.max(1) as usize;
+        let mut by_domain = HashMap::new();
+        let mut picked = Vec::with_capacity(recipients.len().min(limit));
+        for (i, recipient) in recipients.iter().enumerate() {
+            if picked.len() == limit {
+                break;
+            }
+            let domain = domain_of(recipient);
+            let domain_limit = self.limits.domain_messages_per_second(&domain).max(1);
+            let count = by_domain.entry(domain).or_insert(0);
+            if *count < domain_limit {
+                *count += 1;
+                picked.push(i);
+            }
+        }
+        picked
+    }
+
+    /// Wait for our turn to send an email through `provider` to each of `recipients`, in a
+    /// single request.
+    pub async fn wait(&self, provider: EmailProvider, recipients: &[&SubscriberEmail]) {
+        let now = Instant::now();
+        let send_at = self.book(provider, recipients, now);
+        if send_at <= now {
+            return;
+        }
+        let _waiting = Waiting::new(self, provider);
+        tracing::debug!(
+            provider = provider.as_str(),
+            recipients = recipients.len(),
+            delay_ms = (send_at - now).as_millis(),
+            "Throttling outbound emails"
+        );
+        tokio::time::sleep_until(send_at).await;
+    }
+
+    /// Book a message through `provider` to each of `recipients`, returning when they may go
+    /// out together.
+    fn book(
+        &self,
+        provider: EmailProvider,
+        recipients: &[&SubscriberEmail],
+        now: Instant,
+    ) -> Instant {
+        let pace = Pace::new(self.limits.messages_per_second);
+        let mut state = self.lock();
+        let State { providers, domains } = &mut *state;
+        let provider = providers.entry(provider).or_insert(ProviderState {
+            schedule: Schedule { next_at: now },
+            waiting: 0,
+            messages: 0,
+            waited: Duration::ZERO,
+        });
+
+        let mut send_at = now;
+        for recipient in recipients {
+            let domain = domain_of(recipient);
+            let domain_pace = self.limits.domain_pace(&domain);
+            let schedule = domains.entry(domain).or_insert(Schedule { next_at: now });
+            send_at = send_at
+                .max(schedule.book(domain_pace, now))
+                .max(provider.schedule.book(pace, now));
+        }
+        provider.messages += recipients.len() as u64;
+        provider.waited += send_at - now;
+
+        if domains.len() > MAX_TRACKED_DOMAINS {
+            domains.retain(|_, schedule| schedule.next_at > now);
+        }
+        send_at
+    }
+
+    #[must_use]
+    pub fn state(&self) -> ThrottleState {
+        let now = Instant::now();
+        let state = self.lock();
+        let mut providers: Vec<_> = state
+            .providers
+            .iter()
+            .map(|(&provider, state)| ProviderThrottleState {
+                provider,
+                waiting: state.waiting,
+                messages: state.messages,
+                waited: state.waited,
+                backlog: state.schedule.backlog(now),
+            })
+            .collect();
+        providers.sort_by_key(|state| state.provider.as_str());
+        let mut domains: Vec<_> = state
+            .domains
+            .iter()
+            .filter(|(domain, schedule)| {
+                schedule.backlog(now) > self.limits.domain_pace(domain).tolerance
+            })
+            .map(|(domain, schedule)| (domain.clone(), schedule.backlog(now)))
+            .collect();
+        domains.sort();
+        ThrottleState { providers, domains }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // The state stays consistent even if a thread panicked while holding the lock.
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// Counts a send as waiting for as long as it is alive, even if the send is dropped.
+struct Waiting<'a> {
+    throttle: &'a Throttle,
+    provider: EmailProvider,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(throttle: &'a Throttle, provider: EmailProvider) -> Self {
+        if let Some(state) = throttle.lock().providers.get_mut(&provider) {
+            state.waiting += 1;
+        }
+        Self { throttle, provider }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.throttle.lock().providers.get_mut(&self.provider) {
+            state.waiting -= 1;
+        }
+    }
+}
+
+/// The lowercase domain of `email`.
+fn domain_of(email: &SubscriberEmail) -> String {
+    let email: &str = email.as_ref();
+    email
+        .rsplit_once('@')
+        .map_or(email, |(_, domain)| domain)
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{Throttle, ThrottleLimits};
+    use crate::{domain::subscriber_email::SubscriberEmail, email_client::EmailProvider};
+
+    fn throttle(messages_per_second: u32, domain_messages_per_second: u32) -> Throttle {
+        Throttle::new(ThrottleLimits {
+            messages_per_second,
+            domain_messages_per_second,
+            domains: [("gmail.com".to_owned(), 1)].into(),
+        })
+    }
+
+    fn email(email: &str) -> SubscriberEmail {
+        SubscriberEmail::try_from(email.to_owned()).unwrap()
+    }
+
+    #[test]
+    fn a_second_worth_of_messages_goes_out_at_once_then_they_are_paced() {
+        let throttle = throttle(4, 100);
+        let now = Instant::now();
+        let recipient = email("ursula@example.com");
+
+        let send_at: Vec<_> = (0..6)
+            .map(|_| throttle.book(EmailProvider::Mailgun, &[&recipient], now) - now)
+            .collect();
+
+        assert_eq!(
+            send_at,
+            [0, 0, 0, 0, 250, 500].map(Duration::from_millis),
+            "The first 4 messages should have gone out at once, then one every 250ms"
+        );
+    }
+
+    #[test]
+    fn at_once_picks_a_second_worth_through_the_provider_and_to_each_domain() {
+        let throttle = throttle(3, 100);
+        let recipients = [
+            email("ursula@gmail.com"),
+            email("octavia@gmail.com"),
+            email("ursula@example.com"),
+            email("octavia@example.com"),
+            email("nk@example.org"),
+        ];
+        let recipients: Vec<_> = recipients.iter().collect();
+
+        assert_eq!(
+            throttle.at_once(&recipients),
+            [0, 2, 3],
+            "Only one gmail.com recipient, and three in all, should have gone out at once"
+        );
+    }
+
+    #[test]
+    fn providers_have_budgets_of_their_own() {
+        let throttle = throttle(1, 100);
+        let now = Instant::now();
+        let recipient = email("ursula@example.com");
+
+        throttle.book(EmailProvider::Mailgun, &[&recipient], now);
+
+        assert_eq!(
+            throttle.book(EmailProvider::Postmark, &[&recipient], now),
+            now
+        );
+        assert_eq!(
+            throttle.book(EmailProvider::Mailgun, &[&recipient], now),
+            now + Duration::from_secs(1)
+        );
+    }
+
+    #[test]
+    fn domains_are_paced_by_their_own_limit() {
+        let throttle = throttle(100, 2);
+        let now = Instant::now();
+        let gmail = [email("ursula@gmail.com"), email("octavia@GMAIL.com")];
+        let others = [email("ursula@example.com"), email("octavia@example.org")];
+
+        let gmail_batch = throttle.book(EmailProvider::Mailgun, &[&gmail[0], &gmail[1]], now);
+        let others_batch = throttle.book(EmailProvider::Mailgun, &[&others[0], &others[1]], now);
+
+        assert_eq!(gmail_batch, now + Duration::from_secs(1));
+        assert_eq!(others_batch, now);
+        let state = throttle.state();
+        assert_eq!(state.providers[0].messages, 4);
+        assert_eq!(state.providers[0].waited, Duration::from_secs(1));
+        assert_eq!(state.domains.len(), 1);
+        assert_eq!(state.domains[0].0, "gmail.com");
+    }
+}
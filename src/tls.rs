@@ -0,0 +1,145 @@
+//! HTTPS without a reverse proxy.
+//!
+//! The certificate is loaded with rustls and reloaded in place, on SIGHUP or when its files
+//! change: established connections keep going, new handshakes get the new certificate.
+
+use std::{io, path::Path, time::SystemTime};
+
+use axum::{
+    http::{header, HeaderMap, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use axum_server::tls_rustls::RustlsConfig;
+use hyper::StatusCode;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+use crate::configuration::ValidatedTls;
+
+/// How often to look for a renewed certificate, e.g. from certbot.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+pub async fn load(settings: &ValidatedTls) -> io::Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&settings.certificate_file, &settings.key_file)
+        .await
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!(
+                    "Failed to load the TLS certificate from {} and {}: {e}",
+                    settings.certificate_file.display(),
+                    settings.key_file.display()
+                ),
+            )
+        })
+}
+
+/// Listen for SIGHUP, before the server starts so an early signal doesn't kill the process.
+pub fn hangups() -> io::Result<Signal> {
+    signal(SignalKind::hangup())
+}
+
+/// Reload `config` whenever we get a SIGHUP or the certificate files change. Never returns.
+///
+/// A certificate that fails to load is logged and the previous one is kept.
+pub async fn reload_on_change(config: RustlsConfig, settings: ValidatedTls, mut hangups: Signal) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut loaded = modified(&settings).await;
+    loop {
+        let reason = tokio::select! {
+            _ = hangups.recv() => "SIGHUP",
+            _ = interval.tick() => {
+                if modified(&settings).await == loaded {
+                    continue;
+                }
+                "file change"
+            }
+        };
+
+        match config
+            .reload_from_pem_file(&settings.certificate_file, &settings.key_file)
+            .await
+        {
+            Ok(()) => {
+                loaded = modified(&settings).await;
+                tracing::info!(reason, "Reloaded the TLS certificate");
+            }
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                reason,
+                "Failed to reload the TLS certificate, keeping the previous one"
+            ),
+        }
+    }
+}
+
+async fn modified(settings: &ValidatedTls) -> [Option<SystemTime>; 2] {
+    async fn modified(path: &Path) -> Option<SystemTime> {
+        tokio::fs::metadata(path).await.ok()?.modified().ok()
+    }
+    [
+        modified(&settings.certificate_file).await,
+        modified(&settings.key_file).await,
+    ]
+}
+
+/// Redirect every plain HTTP request to the same URL over HTTPS.
+pub fn redirect_to_https(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        redirect(&headers, &uri, https_port)
+    })
+}
+
+fn redirect(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let Some(host) = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let host = without_port(host);
+    let port = match https_port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+
+    // Permanent redirects keep the method and body, unlike `301 Moved Permanently`.
+    Redirect::permanent(&format!("https://{host}{port}{path}")).into_response()
+}
+
+fn without_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        // A bare IPv6 address, e.g. `[::1]`.
+        Some(_) if host.ends_with(']') => host,
+        Some((name, _port)) => name,
+        None => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redirect;
+    use axum::http::{header, HeaderMap, HeaderValue, Uri};
+
+    fn location(host: &str, uri: &str, https_port: u16) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_str(host).unwrap());
+        let response = redirect(&headers, &uri.parse::<Uri>().unwrap(), https_port);
+        assert_eq!(response.status(), 308);
+        response.headers()[header::LOCATION]
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn requests_are_redirected_to_the_https_port() {
+        assert_eq!(
+            location("example.com:8080", "/subscriptions?a=b", 8443),
+            "https://example.com:8443/subscriptions?a=b"
+        );
+        assert_eq!(location("example.com", "/", 443), "https://example.com/");
+        assert_eq!(location("[::1]:80", "/docs", 443), "https://[::1]/docs");
+    }
+}
@@ -0,0 +1,298 @@
+//! Outbound webhooks: telling other systems, e.g. a CRM, about subscribers and issues as they
+//! change.
+//!
+//! Events are queued in the same transaction as the change they describe, one delivery per
+//! endpoint subscribed to them. Every instance runs [`deliver_on_schedule`], which POSTs due
+//! deliveries and retries failed ones with exponential backoff, until [`MAX_ATTEMPTS`].
+//!
+//! Each request carries the event's id in `Webhook-Id`, the Unix time it was sent at in
+//! `Webhook-Timestamp`, and `Webhook-Signature: v1=<signature>`, see [`sign`]. Receivers should
+//! check the signature and the timestamp, and ignore event ids they already processed: an event
+//! is delivered at least once.
+
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::webhook_event_type::WebhookEventType;
+
+/// Deliveries are given up on after this many attempts, about two hours with the default retry
+/// delay.
+pub const MAX_ATTEMPTS: i32 = 8;
+
+/// How long an endpoint has to respond.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tracing::instrument(name = "Enqueue a webhook event", skip(transaction, data))]
+pub async fn enqueue(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    event_type: WebhookEventType,
+    data: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    enqueue_all(transaction, tenant, event_type, vec![data]).await
+}
+
+/// Enqueue several events of the same type with a single `INSERT`, e.g. for an import.
+#[tracing::instrument(
+    name = "Enqueue webhook events",
+    skip(transaction, data),
+    fields(count = data.len())
+)]
+pub async fn enqueue_all(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    event_type: WebhookEventType,
+    data: Vec<serde_json::Value>,
+) -> Result<(), sqlx::Error> {
+    let created_at = Utc::now();
+    let (event_ids, payloads): (Vec<Uuid>, Vec<serde_json::Value>) = data
+        .into_iter()
+        .map(|data| {
+            let event_id = Uuid::new_v4();
+            let payload = serde_json::json!({
+                "id": event_id,
+                "type": event_type.as_str(),
+                "created_at": created_at,
+                "data": data,
+            });
+            (event_id, payload)
+        })
+        .unzip();
+
+    sqlx::query!(
+        r#"
+    INSERT INTO webhook_deliveries (endpoint_id, event_id, event_type, payload)
+    SELECT webhook_endpoints.id, events.event_id, $2, events.payload
+    FROM webhook_endpoints, UNNEST($3::uuid[], $4::jsonb[]) AS events (event_id, payload)
+    WHERE webhook_endpoints.tenant = $1 AND $2 = ANY(webhook_endpoints.events)
+        "#,
+        tenant,
+        event_type.as_str(),
+        &event_ids,
+        &payloads,
+    )
+    .execute(&mut **transaction)
+    .await?;
+
+    Ok(())
+}
+
+/// Strip the events about an erased subscriber down to their id, delivered or not, so that the
+/// delivery log doesn't keep their email or name.
+#[tracing::instrument(
+    name = "Scrub an erased subscriber from webhook payloads",
+    skip(transaction)
+)]
+pub async fn scrub_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    tenant: &str,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+    UPDATE webhook_deliveries d
+    SET payload = jsonb_set(d.payload, '{data}', jsonb_build_object('id', d.payload #> '{data,id}'))
+    FROM webhook_endpoints e
+    WHERE e.id = d.endpoint_id AND e.tenant = $1
+      AND d.event_type LIKE 'subscriber.%' AND d.payload #>> '{data,id}' = $2
+        "#,
+        tenant,
+        subscriber_id.to_string(),
+    )
+    .execute(&mut **transaction)
+    .await?;
+    Ok(())
+}
+
+/// The hex-encoded HMAC-SHA256 of `<timestamp>.<body>`, keyed with the endpoint's secret.
+///
+/// Signing the timestamp along with the body keeps a captured request from being replayed later.
+#[must_use]
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// How long to wait before the attempt following the `attempts`th: `retry_delay`, doubling
+/// each time.
+#[must_use]
+pub fn backoff(retry_delay: Duration, attempts: i32) -> Duration {
+    let doublings = u32::try_from(attempts.saturating_sub(1)).unwrap_or_default();
+    retry_delay.saturating_mul(2u32.saturating_pow(doublings))
+}
+
+/// Look for due deliveries every `interval`, delivering them. Never returns.
+pub async fn deliver_on_schedule(pool: PgPool, interval: Duration, retry_delay: Duration) {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("Failed to build the webhook client");
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match deliver_due(&pool, &client, retry_delay).await {
+            Ok(0) => {}
+            Ok(delivered) => tracing::info!(delivered, "Delivered webhook events"),
+            Err(e) => tracing::error!(
+                error.cause_chain = ?e,
+                "Failed to deliver webhook events, retrying on the next check"
+            ),
+        }
+    }
+}
+
+/// Attempt every delivery that is due, returning how many succeeded.
+#[tracing::instrument(name = "Deliver due webhook events", skip_all)]
+async fn deliver_due(
+    pool: &PgPool,
+    client: &reqwest::Client,
+    retry_delay: Duration,
+) -> Result<usize, anyhow::Error> {
+    let mut delivered = 0;
+    while let Some(succeeded) = deliver_next(pool, client, retry_delay).await? {
+        if succeeded {
+            delivered += 1;
+        }
+    }
+    Ok(delivered)
+}
+
+/// Attempt one due delivery, `None` if there is no such delivery left.
+#[tracing::instrument(skip_all, fields(event_id = tracing::field::Empty))]
+async fn deliver_next(
+    pool: &PgPool,
+    client: &reqwest::Client,
+    retry_delay: Duration,
+) -> Result<Option<bool>, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let Some(delivery) = sqlx::query!(
+        r#"
+    SELECT endpoint_id, event_id, payload, attempts, url, secret
+    FROM webhook_deliveries
+    JOIN webhook_endpoints ON webhook_endpoints.id = webhook_deliveries.endpoint_id
+    WHERE status = 'pending' AND next_attempt_at <= now()
+    ORDER BY next_attempt_at
+    LIMIT 1
+    FOR UPDATE OF webhook_deliveries SKIP LOCKED
+        "#,
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to look for a due webhook delivery")?
+    else {
+        return Ok(None);
+    };
+    tracing::Span::current().record("event_id", tracing::field::display(delivery.event_id));
+
+    let body = delivery.payload.to_string();
+    let timestamp = Utc::now().timestamp();
+    let response = client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("Webhook-Id", delivery.event_id.to_string())
+        .header("Webhook-Timestamp", timestamp.to_string())
+        .header(
+            "Webhook-Signature",
+            format!("v1={}", sign(&delivery.secret, timestamp, &body)),
+        )
+        .body(body)
+        .send()
+        .await;
+    let (response_status, error) = match response {
+        Ok(response) if response.status().is_success() => (Some(response.status()), None),
+        Ok(response) => (
+            Some(response.status()),
+            Some(format!(
+                "The endpoint responded with {}.",
+                response.status()
+            )),
+        ),
+        Err(e) => (None, Some(e.to_string())),
+    };
+
+    let attempts = delivery.attempts + 1;
+    let status = match &error {
+        None => "delivered",
+        Some(_) if attempts >= MAX_ATTEMPTS => "failed",
+        Some(_) => "pending",
+    };
+    if let Some(error) = &error {
+        tracing::warn!(attempts, error, "Failed to deliver a webhook event");
+    }
+    sqlx::query!(
+        r#"
+    UPDATE webhook_deliveries
+    SET status = $3, attempts = $4, last_attempt_at = now(), last_response_status = $5,
+      last_error = $6, next_attempt_at = now() + $7::float8 * interval '1 second'
+    WHERE endpoint_id = $1 AND event_id = $2
+        "#,
+        delivery.endpoint_id,
+        delivery.event_id,
+        status,
+        attempts,
+        response_status.map(|status| i16::try_from(status.as_u16()).unwrap_or(i16::MAX)),
+        error,
+        backoff(retry_delay, attempts).as_secs_f64(),
+    )
+    .execute(&mut *transaction)
+    .await
+    .context("Failed to record the webhook delivery attempt")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction")?;
+    Ok(Some(status == "delivered"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff, sign, MAX_ATTEMPTS};
+    use std::time::Duration;
+
+    #[test]
+    fn signatures_cover_the_timestamp_and_the_body() {
+        let signature = sign("secret", 1_700_000_000, r#"{"id":1}"#);
+        assert_eq!(signature.len(), 64);
+        assert_eq!(signature, sign("secret", 1_700_000_000, r#"{"id":1}"#));
+        assert_ne!(signature, sign("secret", 1_700_000_001, r#"{"id":1}"#));
+        assert_ne!(signature, sign("secret", 1_700_000_000, r#"{"id":2}"#));
+        assert_ne!(signature, sign("other", 1_700_000_000, r#"{"id":1}"#));
+    }
+
+    #[test]
+    fn signatures_match_a_known_hmac() {
+        // echo -n '1.{}' | openssl dgst -sha256 -hmac key
+        assert_eq!(
+            sign("key", 1, "{}"),
+            "1ba6b8171186efc613e8bcc0cbdab2748f24984d7c5a84faa2637afa0e40d224"
+        );
+    }
+
+    #[test]
+    fn the_retry_delay_doubles_with_every_attempt() {
+        let delay = Duration::from_secs(5);
+        assert_eq!(backoff(delay, 1), Duration::from_secs(5));
+        assert_eq!(backoff(delay, 2), Duration::from_secs(10));
+        assert_eq!(backoff(delay, 4), Duration::from_secs(40));
+    }
+
+    #[test]
+    fn the_retry_delay_never_overflows() {
+        let delay = Duration::from_secs(30);
+        assert!(backoff(delay, i32::MAX) > backoff(delay, MAX_ATTEMPTS));
+        assert_eq!(backoff(Duration::MAX, 2), Duration::MAX);
+    }
+}
@@ -0,0 +1,148 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+const SUBSCRIBERS: &str = "email,name,status,subscribed_at\n\
+    ursula@domain.com,Ursula Le Guin,confirmed,2023-07-01T10:00:00Z\n\
+    octavia@domain.com,Octavia Butler,pending_confirmation,2023-07-02T10:00:00Z\n\
+    ted@other.com,Ted Chiang,confirmed,2023-07-03T10:00:00Z\n\
+    nk@domain.com,N. K. Jemisin,confirmed,2023-07-04T10:00:00Z\n";
+
+fn emails(page: &serde_json::Value) -> Vec<&str> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn search_filters_by_status_email_and_date() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.post_subscribers_import(SUBSCRIBERS).await;
+    let test_cases = vec![
+        (
+            "?status=confirmed",
+            vec!["nk@domain.com", "ted@other.com", "ursula@domain.com"],
+        ),
+        (
+            "?email=DOMAIN.com&status=confirmed",
+            vec!["nk@domain.com", "ursula@domain.com"],
+        ),
+        (
+            "?subscribed_after=2023-07-02T00:00:00Z&subscribed_before=2023-07-04T00:00:00Z",
+            vec!["ted@other.com", "octavia@domain.com"],
+        ),
+        ("?email=%25", vec![]),
+    ];
+
+    for (query, expected) in test_cases {
+        // Act
+        let response = app.get_admin(&format!("/subscribers{query}")).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let page: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(emails(&page), expected, "Unexpected results for {query}");
+    }
+}
+
+#[tokio::test]
+async fn search_pages_through_every_subscriber_with_the_cursor() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.post_subscribers_import(SUBSCRIBERS).await;
+    let mut seen = Vec::new();
+    let mut query = "/subscribers?limit=3".to_owned();
+
+    // Act
+    loop {
+        let page: serde_json::Value = app.get_admin(&query).await.json().await.unwrap();
+        seen.extend(emails(&page).into_iter().map(str::to_owned));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = format!("/subscribers?limit=3&cursor={cursor}"),
+            None => break,
+        }
+    }
+
+    // Assert
+    assert_eq!(
+        seen,
+        vec![
+            "nk@domain.com",
+            "ted@other.com",
+            "octavia@domain.com",
+            "ursula@domain.com"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn search_rejects_invalid_filters_with_a_400() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    for query in ["?status=subscribed", "?cursor=not-a-cursor"] {
+        // Act
+        let response = app.get_admin(&format!("/subscribers{query}")).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400, "{query} was accepted");
+    }
+}
+
+#[tokio::test]
+async fn the_detail_view_shows_tokens_and_status_history() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap();
+    let id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    // Act
+    let response = app.get_admin(&format!("/subscribers/{id}")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let detail: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(detail["subscriber"]["status"], "confirmed");
+    assert_eq!(detail["subscription_tokens"].as_array().unwrap().len(), 1);
+    let statuses: Vec<_> = detail["status_history"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, vec!["pending_confirmation", "confirmed"]);
+}
+
+#[tokio::test]
+async fn the_detail_view_returns_a_404_for_unknown_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = app
+        .get_admin(&format!("/subscribers/{}", uuid::Uuid::new_v4()))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
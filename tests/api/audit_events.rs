@@ -0,0 +1,169 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+async fn get_audit_events(app: &TestApp, query: &str) -> Vec<serde_json::Value> {
+    let response = app.get_admin(&format!("/audit_events{query}")).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let page: serde_json::Value = response.json().await.unwrap();
+    page["events"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn subscribing_and_confirming_are_recorded_as_done_by_the_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Confirming twice doesn't change anything.
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let events = get_audit_events(&app, "").await;
+    assert_eq!(events.len(), 2);
+    let (confirm, subscribe) = (&events[0], &events[1]);
+    assert_eq!(subscribe["action"], "subscribe");
+    assert_eq!(subscribe["status_before"], serde_json::Value::Null);
+    assert_eq!(subscribe["status_after"], "pending_confirmation");
+    assert_eq!(confirm["action"], "confirm");
+    assert_eq!(confirm["status_before"], "pending_confirmation");
+    assert_eq!(confirm["status_after"], "confirmed");
+    for event in &events {
+        assert_eq!(event["actor"], "subscriber");
+        assert_eq!(event["target"], "subscriber");
+        assert_eq!(event["actor_id"], event["target_id"]);
+    }
+}
+
+#[tokio::test]
+async fn imports_are_recorded_per_subscriber_as_done_by_the_admin() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let csv = "email,name,status,subscribed_at\n\
+        ursula@domain.com,Ursula Le Guin,confirmed,\n\
+        not-an-email,Octavia Butler,confirmed,\n";
+
+    // Act
+    app.post_subscribers_import(csv).await;
+
+    // Assert
+    let events = get_audit_events(&app, "?action=import").await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["actor"], "admin");
+    assert_eq!(events[0]["status_after"], "confirmed");
+    assert_eq!(events[0]["details"]["line"], 2);
+}
+
+#[tokio::test]
+async fn publishing_stores_the_issue_and_records_it() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    let issue = sqlx::query!("SELECT newsletter_issue_id, title FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.title, "Newsletter title");
+    let events = get_audit_events(&app, "?action=publish").await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["target"], "newsletter_issue");
+    assert_eq!(
+        events[0]["target_id"],
+        issue.newsletter_issue_id.to_string()
+    );
+    assert_eq!(events[0]["details"]["recipients"], 0);
+}
+
+#[tokio::test]
+async fn audit_events_are_paginated_most_recent_first() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let csv = "email,name,status,subscribed_at\n\
+        a@domain.com,A,confirmed,\n\
+        b@domain.com,B,confirmed,\n\
+        c@domain.com,C,confirmed,\n";
+    app.post_subscribers_import(csv).await;
+
+    // Act
+    let mut lines = Vec::new();
+    let mut query = "/audit_events?limit=2".to_owned();
+    loop {
+        let page: serde_json::Value = app.get_admin(&query).await.json().await.unwrap();
+        for event in page["events"].as_array().unwrap() {
+            lines.push(event["details"]["line"].as_u64().unwrap());
+        }
+        match page["next_cursor"].as_i64() {
+            Some(cursor) => query = format!("/audit_events?limit=2&cursor={cursor}"),
+            None => break,
+        }
+    }
+
+    // Assert
+    assert_eq!(lines, vec![4, 3, 2]);
+}
+
+#[tokio::test]
+async fn audit_events_require_the_admin_token() {
+    let app = TestApp::spawn_app().await;
+
+    let response = reqwest::get(format!("{}/admin/audit_events", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn audit_events_cannot_be_changed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.post_subscribers_import("email,name,status,subscribed_at\na@domain.com,A,confirmed,\n")
+        .await;
+
+    // Act
+    let update = sqlx::query("UPDATE audit_events SET actor = 'system'")
+        .execute(&app.db_pool)
+        .await;
+    let delete = sqlx::query("DELETE FROM audit_events")
+        .execute(&app.db_pool)
+        .await;
+
+    // Assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
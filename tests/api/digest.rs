@@ -0,0 +1,211 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+async fn spawn_app() -> TestApp {
+    TestApp::spawn_app_with(|configuration| {
+        configuration.application.digest_check_interval_seconds = 1;
+    })
+    .await
+}
+
+async fn publish(app: &TestApp, title: &str, list: Option<&str>) {
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": title,
+            "content": {
+                "text": format!("{title} as plain text"),
+                "html": format!("<p>{title} as HTML</p>"),
+            },
+            "list": list,
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+/// The digests sent so far, as the body of their request to the email API.
+async fn digests(app: &TestApp) -> Vec<serde_json::Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_urlencoded::from_bytes(&request.body).unwrap())
+        .filter(|body: &serde_json::Value| body["subject"] == "Your weekly digest")
+        .collect()
+}
+
+/// Pretend the subscriber's last digest, and everything published since, is a week older.
+async fn a_week_passes(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE subscriptions SET last_digest_at = last_digest_at - interval '7 days 1 minute'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn digest_subscribers_get_one_email_rolling_up_the_week() {
+    // Arrange
+    let app = spawn_app().await;
+    app.put_list("releases", "Release notes").await;
+    app.put_list("events", "Events").await;
+    let preferences_link = app.create_confirmed_subscriber("ursula@example.com").await;
+    app.post_preferences(
+        &preferences_link,
+        &[
+            ("name", "le guin"),
+            ("frequency", "weekly_digest"),
+            ("lists", "releases"),
+        ],
+    )
+    .await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+
+    // Act
+    publish(&app, "First issue", None).await;
+    publish(&app, "Release notes", Some("releases")).await;
+    publish(&app, "Upcoming events", Some("events")).await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let before_a_week = app.email_server.received_requests().await.unwrap().len();
+    a_week_passes(&app).await;
+
+    // Assert
+    assert_eq!(
+        before_a_week, confirmation_emails,
+        "Nothing is sent to digest subscribers before their digest is due"
+    );
+    let mut sent = vec![];
+    for _ in 0..50 {
+        sent = digests(&app).await;
+        if !sent.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(sent.len(), 1, "No digest was sent");
+    let digest = sent[0]["html"].as_str().unwrap();
+    assert!(digest.contains("<p>First issue as HTML</p>"));
+    assert!(digest.contains("<p>Release notes as HTML</p>"));
+    let text = sent[0]["text"].as_str().unwrap();
+    assert!(text.contains("First issue as plain text"));
+    assert!(text.contains("Release notes as plain text"));
+    assert!(
+        !digest.contains("Upcoming events"),
+        "They opted out of events"
+    );
+    assert!(digest.find("First issue").unwrap() < digest.find("Release notes").unwrap());
+
+    // The next digest is a week away.
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(digests(&app).await.len(), 1);
+    let saved = sqlx::query!(
+        r#"SELECT last_digest_at > now() - interval '1 minute' AS "recent!" FROM subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(saved.recent);
+}
+
+#[tokio::test]
+async fn no_digest_is_sent_when_nothing_was_published() {
+    // Arrange
+    let app = spawn_app().await;
+    let preferences_link = app.create_confirmed_subscriber("ursula@example.com").await;
+    app.post_preferences(
+        &preferences_link,
+        &[("name", "le guin"), ("frequency", "weekly_digest")],
+    )
+    .await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    a_week_passes(&app).await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+
+    // Assert
+    let saved = sqlx::query!(
+        r#"SELECT last_digest_at > now() - interval '1 minute' AS "recent!" FROM subscriptions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(saved.recent, "The empty digest still counts as this week's");
+}
+
+#[tokio::test]
+async fn a_digest_that_fails_to_send_is_retried_later_without_holding_up_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    for email in ["ursula@example.com", "octavia@example.com"] {
+        let preferences_link = app.create_confirmed_subscriber(email).await;
+        app.post_preferences(
+            &preferences_link,
+            &[("name", "le guin"), ("frequency", "weekly_digest")],
+        )
+        .await;
+    }
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .and(body_string_contains("ursula%40example.com"))
+        .respond_with(ResponseTemplate::new(500))
+        .with_priority(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    publish(&app, "First issue", None).await;
+
+    // Act
+    a_week_passes(&app).await;
+
+    // Assert
+    let mut sent = vec![];
+    for _ in 0..50 {
+        sent = digests(&app).await;
+        if sent.len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let recipients: Vec<_> = sent.iter().map(|d| d["to"].as_str().unwrap()).collect();
+    assert_eq!(
+        recipients,
+        vec!["ursula@example.com", "octavia@example.com"],
+        "Ursula's failed digest held up Octavia's"
+    );
+
+    let (attempts, retry_later, sent_recently): (i32, bool, bool) = sqlx::query_as(
+        r#"
+    SELECT digest_attempts, next_digest_attempt_at > now(),
+      last_digest_at > now() - interval '1 minute'
+    FROM subscriptions WHERE email = 'ursula@example.com'
+        "#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(attempts, 1);
+    assert!(retry_later);
+    assert!(!sent_recently, "The failed digest isn't recorded as sent");
+}
@@ -0,0 +1,183 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+fn subscription(email: &str) -> serde_json::Value {
+    serde_json::json!({ "name": "le guin", "email": email })
+}
+
+async fn rejection(app: &TestApp, email: &str) -> Option<String> {
+    let response = app.post_subscriptions_json(subscription(email)).await;
+    match response.status().as_u16() {
+        400 => {
+            let body: serde_json::Value = response.json().await.unwrap();
+            Some(body["error"].as_str().unwrap().to_owned())
+        }
+        201 => None,
+        status => panic!("Unexpected status {status} for {email}"),
+    }
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn disposable_and_role_addresses_are_rejected_with_a_specific_error() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let test_cases = vec![
+        ("ursula@mailinator.com", "disposable"),
+        ("ursula@eu.Yopmail.com", "disposable"),
+        ("noreply@gmail.com", "role account"),
+    ];
+
+    for (email, reason) in test_cases {
+        // Act
+        let error = rejection(&app, email).await;
+
+        // Assert
+        let error = error.unwrap_or_else(|| panic!("{email} was accepted"));
+        assert!(error.contains(reason), "{email}: {error}");
+    }
+
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(saved.is_empty());
+}
+
+#[tokio::test]
+async fn admins_can_block_and_allow_domains() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    mock_email_server(&app).await;
+
+    // Act
+    assert_eq!(
+        app.put_email_domain("Example.com", "block").await.status(),
+        204
+    );
+    assert_eq!(
+        app.put_email_domain("team.example.com", "allow")
+            .await
+            .status(),
+        204
+    );
+    assert_eq!(
+        app.put_email_domain("mailinator.com", "allow")
+            .await
+            .status(),
+        204
+    );
+
+    // Assert
+    assert!(rejection(&app, "ursula@example.com")
+        .await
+        .unwrap()
+        .contains("example.com"));
+    assert!(rejection(&app, "ursula@team.example.com").await.is_none());
+    assert!(rejection(&app, "octavia@mailinator.com").await.is_none());
+
+    let rules: serde_json::Value = app.get_admin("/email_domains").await.json().await.unwrap();
+    let domains: Vec<_> = rules
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| (r["domain"].as_str().unwrap(), r["rule"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        domains,
+        vec![
+            ("example.com", "block"),
+            ("mailinator.com", "allow"),
+            ("team.example.com", "allow"),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn invalid_domain_rules_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let invalid_rule = app.put_email_domain("example.com", "maybe").await;
+    let invalid_domain = app.put_email_domain("127.0.0.1", "block").await;
+
+    // Assert
+    assert_eq!(invalid_rule.status(), 400);
+    assert_eq!(invalid_domain.status(), 400);
+}
+
+#[tokio::test]
+async fn deleting_a_rule_restores_the_default_policy() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    mock_email_server(&app).await;
+    app.put_email_domain("example.com", "block").await;
+    let client = reqwest::Client::new();
+    let delete = || {
+        client
+            .delete(format!("{}/admin/email_domains/example.com", app.address))
+            .bearer_auth(&app.admin_token)
+            .send()
+    };
+
+    // Act
+    let deleted = delete().await.unwrap();
+    let deleted_again = delete().await.unwrap();
+
+    // Assert
+    assert_eq!(deleted.status(), 204);
+    assert_eq!(deleted_again.status(), 404);
+    assert!(rejection(&app, "ursula@example.com").await.is_none());
+}
+
+#[tokio::test]
+async fn the_disposable_domain_list_is_reloaded_when_its_file_changes() {
+    // Arrange
+    let file = std::env::temp_dir().join(format!("disposable-{}.txt", Uuid::new_v4()));
+    std::fs::write(&file, "throwaway.example\n").unwrap();
+    let app = TestApp::spawn_app_with(|configuration| {
+        configuration.application.disposable_domains_file = Some(file.clone());
+    })
+    .await;
+    mock_email_server(&app).await;
+    assert!(rejection(&app, "ursula@throwaway.example").await.is_some());
+    // The file replaces the bundled list.
+    assert!(rejection(&app, "ursula@mailinator.com").await.is_none());
+
+    // Act
+    std::fs::write(&file, "# nothing is disposable anymore\n").unwrap();
+
+    // Assert
+    let mut accepted = false;
+    for _ in 0..30 {
+        if rejection(
+            &app,
+            &format!("{}@throwaway.example", Uuid::new_v4().simple()),
+        )
+        .await
+        .is_none()
+        {
+            accepted = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    assert!(accepted, "The updated list was not picked up");
+
+    std::fs::remove_file(file).unwrap();
+}
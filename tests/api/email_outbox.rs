@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+/// The circuit breaker opens on the first failure, and stays open for a second.
+async fn spawn_app() -> TestApp {
+    TestApp::spawn_app_with(|configuration| {
+        configuration.email_client.circuit_breaker_failures = 1;
+        configuration.email_client.circuit_breaker_open_seconds = 1;
+        configuration
+            .application
+            .email_outbox_check_interval_seconds = 1;
+        configuration.application.email_outbox_retry_delay_seconds = 1;
+    })
+    .await
+}
+
+async fn subscribe(app: &TestApp, email: &str) -> reqwest::Response {
+    let body = serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap();
+    app.post_subscriptions(body).await
+}
+
+async fn deferred_emails(app: &TestApp) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn email_circuits(app: &TestApp) -> serde_json::Value {
+    let response = reqwest::get(format!("{}/ready", app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    response.json::<serde_json::Value>().await.unwrap()["email_circuits"].clone()
+}
+
+#[tokio::test]
+async fn subscribing_while_the_email_provider_is_down_defers_the_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = subscribe(&app, "ursula@example.com").await;
+    let second = subscribe(&app, "octavia@example.com").await;
+
+    // Assert - The second confirmation wasn't even tried, as the circuit breaker opened.
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(second.status().as_u16(), 200);
+    assert_eq!(deferred_emails(&app).await, 2);
+    assert_eq!(email_circuits(&app).await["default"], "open");
+    assert_eq!(email_circuits(&app).await["other"], "closed");
+}
+
+#[tokio::test]
+async fn deferred_emails_are_sent_once_the_email_provider_is_back() {
+    // Arrange
+    let app = spawn_app().await;
+    let outage = Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    assert_eq!(
+        subscribe(&app, "ursula@example.com")
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+    assert_eq!(deferred_emails(&app).await, 1);
+
+    // Act
+    drop(outage);
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Assert
+    for _ in 0..20 {
+        if deferred_emails(&app).await == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(deferred_emails(&app).await, 0);
+    assert_eq!(email_circuits(&app).await["default"], "closed");
+    let confirmation = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(&confirmation);
+    assert!(links.html.as_str().contains("subscription_token="));
+}
+
+#[tokio::test]
+async fn emails_the_provider_turns_down_are_not_deferred() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first = subscribe(&app, "ursula@example.com").await;
+    let second = subscribe(&app, "octavia@example.com").await;
+
+    // Assert - A bad request says nothing about the provider being down.
+    assert_eq!(first.status().as_u16(), 500);
+    assert_eq!(second.status().as_u16(), 500);
+    assert_eq!(deferred_emails(&app).await, 0);
+    assert_eq!(email_circuits(&app).await["default"], "closed");
+}
+
+#[tokio::test]
+async fn data_requests_while_the_email_provider_is_down_are_deferred() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_data_requests("email=ursula%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(deferred_emails(&app).await, 1);
+}
+
+#[tokio::test]
+async fn newsletter_deliveries_are_put_off_while_the_circuit_is_open() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|configuration| {
+        configuration.email_client.circuit_breaker_failures = 1;
+        configuration.email_client.circuit_breaker_open_seconds = 60;
+    })
+    .await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    let outage = Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    subscribe(&app, "octavia@example.com").await;
+    drop(outage);
+    assert_eq!(email_circuits(&app).await["default"], "open");
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .await;
+
+    // Assert - Nothing was tried, so the delivery is neither given up on nor an attempt down.
+    assert_eq!(response.status().as_u16(), 200);
+    let (attempts, error, retry_later): (i32, Option<String>, bool) = sqlx::query_as(
+        "SELECT attempts, error, next_attempt_at > now() FROM newsletter_deliveries",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(attempts, 0);
+    assert!(error.is_none());
+    assert!(retry_later);
+}
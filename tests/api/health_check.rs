@@ -1,4 +1,4 @@
-use crate::helpers::TestApp;
+use crate::helpers::{TestApp, OTHER_TENANT};
 
 #[tokio::test]
 async fn health_check_works() {
@@ -17,3 +17,23 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn ready_reports_the_database_and_the_email_circuit_of_every_tenant() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/ready", app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["database"], true);
+    assert_eq!(
+        body["email_circuits"],
+        serde_json::json!({ "default": "closed", OTHER_TENANT: "closed" })
+    );
+}
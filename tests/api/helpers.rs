@@ -1,13 +1,26 @@
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+use zero2prod::circuit_breaker::{DEFAULT_FAILURE_THRESHOLD, DEFAULT_OPEN_DURATION};
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, EmailClientSettings, Settings, TenantSettings,
+};
+use zero2prod::email_client::{EmailProvider, DEFAULT_MAX_ATTACHMENTS_BYTES};
 use zero2prod::startup::Application;
 use zero2prod::telemetry::{get_subscriber, init_subscriber};
 
 pub const EMAIL_URL: &str = "sandboxccf80f2f71824c318e2e1554ec96f417.mailgun.org/messages";
 
+/// A second newsletter served next to the default one, by host and by path prefix.
+pub const OTHER_TENANT: &str = "other";
+pub const OTHER_TENANT_HOST: &str = "other.localhost";
+pub const OTHER_TENANT_SENDER: &str = "other@newsletter.com";
+
 // ==================================== Tracing ============================================================= //
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
@@ -36,10 +49,20 @@ pub struct TestApp {
     pub address: String,
     pub db_pool: PgPool,
     pub email_server: MockServer,
+    pub admin_token: String,
+    /// The plain HTTP port redirecting to HTTPS, when serving HTTPS.
+    pub redirect_port: Option<u16>,
+    /// Every method and path the application routes, as in the `OpenAPI` document.
+    pub routed_operations: Vec<(reqwest::Method, String)>,
 }
 
 impl TestApp {
     pub async fn spawn_app() -> TestApp {
+        Self::spawn_app_with(|_| {}).await
+    }
+
+    /// Spawn the application with settings of its own, e.g. to serve HTTPS.
+    pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
         // The first time `initialize` is invoked the code in `TRACING` is executed.
         // All other invocations will instead skip execution.
         Lazy::force(&TRACING);
@@ -51,14 +74,43 @@ impl TestApp {
             configuration.application.port = 0;
             configuration.database.database_name = Uuid::new_v4().to_string();
             configuration.email_client.base_url = email_server.uri();
+            configuration.tenants = vec![TenantSettings {
+                slug: OTHER_TENANT.into(),
+                hosts: vec![OTHER_TENANT_HOST.into()],
+                base_url: format!("{}/t/{OTHER_TENANT}", configuration.application.base_url),
+                email_client: EmailClientSettings {
+                    base_url: email_server.uri(),
+                    sender_email: OTHER_TENANT_SENDER.into(),
+                    timeout_milliseconds: 10_000,
+                    authorization_token: Secret::new("other-token".into()),
+                    authorization_token_file: None,
+                    provider: EmailProvider::Mailgun,
+                    max_attachments_bytes: DEFAULT_MAX_ATTACHMENTS_BYTES,
+                    attachment_content_types: vec!["image/png".into()],
+                    circuit_breaker_failures: DEFAULT_FAILURE_THRESHOLD,
+                    circuit_breaker_open_seconds: DEFAULT_OPEN_DURATION.as_secs(),
+                },
+            }];
+            configure(&mut configuration);
             configuration
         };
 
         let db_pool = Self::configure_database(&configuration.database).await;
+        let admin_token = configuration
+            .application
+            .admin_token
+            .expose_secret()
+            .clone();
 
-        let application = Application::build(configuration);
-        let address = format!("http://{}", application.address());
+        let scheme = match configuration.application.tls {
+            Some(_) => "https",
+            None => "http",
+        };
+        let application = Application::build(configuration).expect("Failed to build application.");
+        let address = format!("{scheme}://{}", application.address());
         let port = application.port();
+        let redirect_port = application.redirect_port();
+        let routed_operations = application.routed_operations().to_vec();
 
         tokio::spawn(async move { application.run().await.expect("Failed to run the server") });
 
@@ -67,6 +119,9 @@ impl TestApp {
             address,
             db_pool,
             email_server,
+            admin_token,
+            redirect_port,
+            routed_operations,
         }
     }
 
@@ -92,13 +147,37 @@ impl TestApp {
         connection_pool
     }
 
+    /// Publish an issue, then wait for the deliveries due right away to be sent.
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
-        reqwest::Client::new()
+        let response = reqwest::Client::new()
             .post(&format!("{}/newsletters", &self.address))
+            .bearer_auth(&self.admin_token)
             .json(&body)
             .send()
             .await
-            .expect("Failed to execute request.")
+            .expect("Failed to execute request.");
+        self.wait_for_deliveries().await;
+        response
+    }
+
+    /// Wait for the newsletter deliveries that are due to be attempted in the background.
+    pub async fn wait_for_deliveries(&self) {
+        for _ in 0..100 {
+            let due: i64 = sqlx::query_scalar(
+                r#"
+    SELECT COUNT(*) FROM newsletter_deliveries
+    WHERE sent_at IS NULL AND error IS NULL AND next_attempt_at <= now()
+                "#,
+            )
+            .fetch_one(&self.db_pool)
+            .await
+            .unwrap();
+            if due == 0 {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        panic!("The due newsletter deliveries were not attempted");
     }
 
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
@@ -111,8 +190,79 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    pub async fn post_subscriptions_json(&self, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/api/v1/subscriptions", self.address))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_data_requests(&self, body: String) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/data_requests", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn post_subscribers_import(&self, csv: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/subscribers/import", self.address))
+            .bearer_auth(&self.admin_token)
+            .header("Content-Type", "text/csv")
+            .body(csv.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_subscribers_export(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers/export", self.address))
+            .bearer_auth(&self.admin_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn get_admin(&self, path_and_query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin{}", self.address, path_and_query))
+            .bearer_auth(&self.admin_token)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_email_domain(&self, domain: &str, rule: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/email_domains/{domain}", self.address))
+            .bearer_auth(&self.admin_token)
+            .json(&serde_json::json!({ "rule": rule }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
     /// Extract the confirmation links embedded in the request to the email API.
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        self.get_links(email_request, |link| !link.path().ends_with("/preferences"))
+    }
+
+    /// Extract the links to the preference page closing every email.
+    pub fn get_preferences_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        self.get_links(email_request, |link| link.path().ends_with("/preferences"))
+    }
+
+    fn get_links(
+        &self,
+        email_request: &wiremock::Request,
+        wanted: impl Fn(&reqwest::Url) -> bool,
+    ) -> ConfirmationLinks {
         let body: serde_json::Value = serde_urlencoded::from_bytes(&email_request.body).unwrap();
 
         // Extract the link from one of the request fields.
@@ -120,11 +270,12 @@ impl TestApp {
             let links: Vec<_> = linkify::LinkFinder::new()
                 .links(s)
                 .filter(|l| *l.kind() == linkify::LinkKind::Url)
+                .map(|l| reqwest::Url::parse(l.as_str()).unwrap())
+                .filter(|l| wanted(l))
                 .collect();
             assert_eq!(links.len(), 1);
 
-            let raw_link = links[0].as_str().to_owned();
-            let mut confirmation_link = reqwest::Url::parse(&raw_link).unwrap();
+            let mut confirmation_link = links[0].clone();
 
             // Let's make sure we don't call random APIs on the web
             assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
@@ -137,4 +288,122 @@ impl TestApp {
         let plain_text = get_link(body["text"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
+
+    /// Every email sent so far, as the body of its request to the email API. Batches are split
+    /// into an email per recipient, their recipient variables filled in as Mailgun does.
+    pub async fn sent_emails(&self) -> Vec<serde_json::Value> {
+        let mut emails = vec![];
+        for request in self.email_server.received_requests().await.unwrap() {
+            let body: serde_json::Value = serde_urlencoded::from_bytes(&request.body).unwrap();
+            let Some(variables) = body["recipient-variables"].as_str() else {
+                emails.push(body);
+                continue;
+            };
+            let variables: serde_json::Value = serde_json::from_str(variables).unwrap();
+            for recipient in body["to"].as_str().unwrap().split(", ") {
+                let mut email = body.clone();
+                email["to"] = recipient.into();
+                for field in ["subject", "html", "text"] {
+                    let mut value = body[field].as_str().unwrap().to_owned();
+                    for (name, variable) in variables[recipient].as_object().unwrap() {
+                        value = value
+                            .replace(&format!("%recipient.{name}%"), variable.as_str().unwrap());
+                    }
+                    email[field] = value.into();
+                }
+                emails.push(email);
+            }
+        }
+        emails
+    }
+
+    /// Subscribe and confirm, returning the preference page's link from the confirmation email.
+    pub async fn create_confirmed_subscriber(&self, email: &str) -> reqwest::Url {
+        let _mock_guard = Mock::given(path(EMAIL_URL))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+        let body = serde_urlencoded::to_string([("name", "le guin"), ("email", email)]).unwrap();
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        reqwest::get(self.get_confirmation_links(&email_request).html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+        self.get_preferences_links(&email_request).html
+    }
+
+    /// Submit the preference page behind `preferences_link`.
+    pub async fn post_preferences(
+        &self,
+        preferences_link: &reqwest::Url,
+        fields: &[(&str, &str)],
+    ) -> reqwest::Response {
+        let token = preferences_link
+            .query_pairs()
+            .find(|(key, _)| key == "preferences_token")
+            .unwrap()
+            .1
+            .into_owned();
+        let mut fields = fields.to_vec();
+        fields.push(("preferences_token", &token));
+
+        reqwest::Client::new()
+            .post(format!("{}/preferences", self.address))
+            .form(&fields)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_list(&self, slug: &str, name: &str) -> reqwest::Response {
+        self.put_list_with_attributes(slug, name, serde_json::json!({}))
+            .await
+    }
+
+    /// Create or update a list collecting `attributes`, e.g. `{"country": "string"}`.
+    pub async fn put_list_with_attributes(
+        &self,
+        slug: &str,
+        name: &str,
+        attributes: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/lists/{slug}", self.address))
+            .bearer_auth(&self.admin_token)
+            .json(&serde_json::json!({ "name": name, "attributes": attributes }))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    pub async fn put_subscriber_attributes(
+        &self,
+        subscriber_id: Uuid,
+        attributes: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!(
+                "{}/admin/subscribers/{subscriber_id}/attributes",
+                self.address
+            ))
+            .bearer_auth(&self.admin_token)
+            .json(&attributes)
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
 }
@@ -0,0 +1,133 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+async fn subscribe(app: &TestApp, body: &str, accept_language: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept-Language", accept_language)
+        .body(body.to_owned())
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn stored_locale(app: &TestApp) -> String {
+    sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .locale
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_sent_in_the_browser_language() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "fr-CA, fr;q=0.9, en;q=0.5",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(stored_locale(&app).await, "fr");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_urlencoded::from_bytes(&email_request.body).unwrap();
+    assert_eq!(email["subject"], "Bienvenue !");
+    assert!(email["text"]
+        .as_str()
+        .unwrap()
+        .starts_with("Bienvenue dans notre newsletter !"));
+    // The link is still there to confirm.
+    app.get_confirmation_links(email_request);
+}
+
+#[tokio::test]
+async fn the_locale_field_wins_over_the_browser_language() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&locale=de",
+        "fr",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(stored_locale(&app).await, "de");
+}
+
+#[tokio::test]
+async fn unsupported_languages_fall_back_to_english() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    subscribe(
+        &app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "ja",
+    )
+    .await;
+
+    // Assert
+    assert_eq!(stored_locale(&app).await, "en");
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let email: serde_json::Value = serde_urlencoded::from_bytes(&email_request.body).unwrap();
+    assert_eq!(email["subject"], "Welcome!");
+}
+
+#[tokio::test]
+async fn validation_errors_are_translated() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/v1/subscriptions", app.address))
+        .header("Accept-Language", "de")
+        .json(&serde_json::json!({
+            "name": "Ursula",
+            "email": "definitely-not-an-email",
+            "locale": "fr",
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        body["error"],
+        "definitely-not-an-email n'est pas une adresse e-mail valide."
+    );
+}
@@ -1,5 +1,22 @@
+mod admin_subscribers;
+mod audit_events;
+mod digest;
+mod email_domains;
+mod email_outbox;
 mod health_check;
 mod helpers;
+mod locales;
+mod metrics;
 mod newsletter;
+mod openapi;
+mod preferences;
+mod segments;
+mod subject_tests;
+mod subscriber_data;
+mod subscribers_csv;
 mod subscriptions;
+mod subscriptions_api;
 mod subscriptions_confirm;
+mod tenants;
+mod tls;
+mod webhooks;
@@ -0,0 +1,34 @@
+use crate::helpers::TestApp;
+
+#[tokio::test]
+async fn metrics_require_the_admin_token() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/admin/metrics", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn emails_let_through_by_the_throttle_are_counted() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+
+    // Act
+    let response = app.get_admin("/metrics").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let metrics = response.text().await.unwrap();
+    assert!(
+        metrics.contains("email_throttle_messages_total{provider=\"mailgun\"} 1\n"),
+        "{metrics}"
+    );
+    assert!(metrics.contains("email_throttle_waiting{provider=\"mailgun\"} 0\n"));
+}
@@ -72,7 +72,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = TestApp::spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path(EMAIL_URL))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
@@ -88,16 +88,24 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
       }
     });
 
+    // The delivery worker sends the issue before this returns.
     let response = app.post_newsletters(newsletter_request_body).await;
 
     // Assert
     assert_eq!(response.status().as_u16(), 200);
+    let sent: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM newsletter_deliveries WHERE sent_at IS NOT NULL")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(sent, 1);
 }
 
 #[tokio::test]
-async fn newsletters_returns_400_for_invalid_data() {
+async fn newsletters_returns_an_error_for_invalid_data() {
     // Arrange
     let app = TestApp::spawn_app().await;
+    // A payload missing a field is rejected before the issue is looked at.
     let test_cases = vec![
         (
             serde_json::json!({
@@ -106,23 +114,477 @@ async fn newsletters_returns_400_for_invalid_data() {
               "html": "<p>Newsletter body as HTML</p>",
               }
             }),
+            422,
             "missing title",
         ),
         (
             serde_json::json!({"title": "Newsletter!"}),
+            400,
             "missing content",
         ),
     ];
 
-    for (invalid_body, error_message) in test_cases {
+    for (invalid_body, status, error_message) in test_cases {
         let response = app.post_newsletters(invalid_body).await;
 
         // Assert
         assert_eq!(
-            400,
+            status,
             response.status().as_u16(),
-            "The API did not fail with 400 Bad Request when the payload was {}.",
+            "The API did not fail with {} when the payload was {}.",
+            status,
             error_message
         );
     }
 }
+
+#[tokio::test]
+async fn newsletters_can_only_be_published_with_the_admin_token() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let published: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(published, 0);
+}
+
+/// The one email sent after publishing `body` to a single confirmed subscriber, as the body of
+/// its request to the email API.
+async fn publish_to_one_subscriber(app: &TestApp, body: serde_json::Value) -> serde_json::Value {
+    create_confirmed_subscriber(app).await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(body).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    app.sent_emails().await.pop().unwrap()
+}
+
+#[tokio::test]
+async fn newsletters_written_in_markdown_are_rendered_into_html_and_text() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let email = publish_to_one_subscriber(
+        &app,
+        serde_json::json!({
+            "title": "Newsletter title",
+            "markdown": "# Release notes\n\nRead **the post** on [our blog](https://example.com/post).\n\n<script>alert(1)</script>",
+        }),
+    )
+    .await;
+
+    // Assert
+    let html = email["html"].as_str().unwrap();
+    let text = email["text"].as_str().unwrap();
+    assert!(html.contains("<h1>Release notes</h1>"));
+    assert!(html.contains(r#"<a href="https://example.com/post""#));
+    assert!(!html.contains("<script"));
+    assert!(text.contains("Release notes"));
+    assert!(text.contains("https://example.com/post"));
+    assert!(!text.contains("<strong>"));
+}
+
+#[tokio::test]
+async fn the_plain_text_version_is_derived_from_the_html_when_left_out() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let email = publish_to_one_subscriber(
+        &app,
+        serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>Newsletter body as <em>HTML</em></p>" },
+        }),
+    )
+    .await;
+
+    // Assert
+    let text = email["text"].as_str().unwrap();
+    assert!(text.starts_with("Newsletter body as *HTML*\n"));
+    assert!(!text.contains("<p>"));
+}
+
+#[tokio::test]
+async fn newsletters_with_both_html_and_markdown_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "html": "<p>Newsletter body as HTML</p>" },
+            "markdown": "Newsletter body as Markdown",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletters_are_sanitised_and_their_css_inlined_before_sending() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let email = publish_to_one_subscriber(
+        &app,
+        serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<html><head><style>p { color: #333333 }</style></head><body>\
+                    <p>Read <a href=\"posts/1\">the post</a></p>\
+                    <script>alert(1)</script></body></html>",
+            },
+        }),
+    )
+    .await;
+
+    // Assert
+    let html = email["html"].as_str().unwrap();
+    assert!(html.starts_with(r#"<p style="color: #333333">"#));
+    assert!(html.contains(r#"href="http://127.0.0.1/posts/1""#));
+    assert!(!html.contains("<style"));
+    assert!(!html.contains("<script"));
+}
+
+#[tokio::test]
+async fn newsletters_that_gmail_would_clip_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>All work and no play makes Jack a dull boy.</p>".repeat(3000),
+            },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletters_are_sent_in_one_batch_with_a_preference_link_per_recipient() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let mut preferences_links = vec![];
+    for email in ["ursula@example.com", "octavia@example.com"] {
+        preferences_links.push((email, app.create_confirmed_subscriber(email).await));
+    }
+    let confirmation_emails = app.email_server.received_requests().await.unwrap().len();
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let emails = app.sent_emails().await.split_off(confirmation_emails);
+    assert_eq!(emails.len(), 2);
+    for (email, preferences_link) in preferences_links {
+        let token = preferences_link.query().unwrap();
+        let sent = emails.iter().find(|sent| sent["to"] == email).unwrap();
+        assert!(sent["html"].as_str().unwrap().contains(token));
+        assert!(sent["text"].as_str().unwrap().contains(token));
+    }
+    let sent: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM newsletter_deliveries WHERE sent_at IS NOT NULL")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(sent, 2);
+}
+
+#[tokio::test]
+async fn deliveries_whose_batch_failed_are_retried_later() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|configuration| {
+        configuration
+            .application
+            .newsletter_delivery_check_interval_seconds = 1;
+        configuration
+            .application
+            .newsletter_delivery_retry_delay_seconds = 1;
+    })
+    .await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(
+        response.status().as_u16(),
+        200,
+        "Retrying the request would publish the issue twice"
+    );
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report, serde_json::json!({ "recipients": 1 }));
+    let mut delivery = (None, 0, None);
+    for _ in 0..50 {
+        delivery = sqlx::query_as("SELECT sent_at, attempts, error FROM newsletter_deliveries")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        if delivery.0.is_some() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    let (sent_at, attempts, error): (Option<chrono::DateTime<chrono::Utc>>, i32, Option<String>) =
+        delivery;
+    assert!(sent_at.is_some(), "The delivery wasn't retried");
+    assert_eq!(attempts, 1);
+    assert!(error.is_none());
+}
+
+#[tokio::test]
+async fn deliveries_record_why_the_email_api_turned_them_down() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(400))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let (sent_at, next_attempt_at, error): (
+        Option<chrono::DateTime<chrono::Utc>>,
+        Option<chrono::DateTime<chrono::Utc>>,
+        Option<String>,
+    ) = sqlx::query_as("SELECT sent_at, next_attempt_at, error FROM newsletter_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(sent_at.is_none());
+    assert!(
+        next_attempt_at.is_none(),
+        "A turned down email isn't retried"
+    );
+    assert!(error.unwrap().contains("400"));
+}
+
+#[tokio::test]
+async fn publishing_answers_without_waiting_for_the_email_api() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(3)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let started = std::time::Instant::now();
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .bearer_auth(&app.admin_token)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "Newsletter body", "html": "<p>Newsletter body</p>" },
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(
+        started.elapsed() < std::time::Duration::from_secs(2),
+        "Publishing waited for the email to be sent"
+    );
+    app.wait_for_deliveries().await;
+    let sent: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM newsletter_deliveries WHERE sent_at IS NOT NULL")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(sent, 1, "The issue was sent in the background");
+}
+
+#[tokio::test]
+async fn batches_carry_each_recipient_name_for_the_email_api_to_fill_in() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(serde_json::json!({
+        "title": "News for %recipient.name%",
+        "content": {
+            "text": "Dear %recipient.name%",
+            "html": "<p>Dear %recipient.name%</p>",
+        },
+    }))
+    .await;
+
+    // Assert
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_urlencoded::from_bytes(&request.body).unwrap();
+    let variables: serde_json::Value =
+        serde_json::from_str(body["recipient-variables"].as_str().unwrap()).unwrap();
+    assert_eq!(variables["ursula_le_guin@gmail.com"]["name"], "le guin");
+    let email = app.sent_emails().await.pop().unwrap();
+    assert_eq!(email["subject"], "News for le guin");
+    assert!(email["text"].as_str().unwrap().starts_with("Dear le guin"));
+}
+
+#[tokio::test]
+async fn batches_carry_no_more_than_a_second_worth_of_recipients_per_domain() {
+    // Arrange
+    let app = TestApp::spawn_app_with(|configuration| {
+        configuration
+            .email_throttle
+            .domains
+            .insert("gmail.com".into(), 1);
+    })
+    .await;
+    for email in ["ursula@gmail.com", "octavia@gmail.com", "nk@example.com"] {
+        app.create_confirmed_subscriber(email).await;
+    }
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    let batches: Vec<Vec<String>> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter_map(|request| {
+            let body: serde_json::Value = serde_urlencoded::from_bytes(&request.body).unwrap();
+            (body["subject"] == "Newsletter title").then(|| {
+                body["to"]
+                    .as_str()
+                    .unwrap()
+                    .split(", ")
+                    .map(str::to_owned)
+                    .collect()
+            })
+        })
+        .collect();
+    assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), 3);
+    for batch in &batches {
+        assert!(
+            batch.iter().filter(|to| to.ends_with("@gmail.com")).count() <= 1,
+            "A batch went out with more than a second's worth of gmail.com recipients: {batch:?}"
+        );
+    }
+}
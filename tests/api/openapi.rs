@@ -0,0 +1,101 @@
+use crate::helpers::TestApp;
+
+#[tokio::test]
+async fn the_openapi_document_is_served() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let document: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(document["openapi"], "3.1.0");
+    for schema in ["FormData", "SubscriptionCreated", "BodyData", "Content"] {
+        assert!(
+            document["components"]["schemas"][schema].is_object(),
+            "The {schema} schema is missing from the OpenAPI document."
+        );
+    }
+}
+
+#[tokio::test]
+async fn every_documented_operation_is_routed() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let client = reqwest::Client::new();
+    let document: serde_json::Value = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let paths = document["paths"].as_object().unwrap();
+    assert!(!paths.is_empty());
+
+    for (path, operations) in paths {
+        for method in operations.as_object().unwrap().keys() {
+            // Act
+            let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+            let response = client
+                .request(method.clone(), format!("{}{}", app.address, path))
+                .send()
+                .await
+                .unwrap();
+
+            // Assert
+            let status = response.status().as_u16();
+            assert!(
+                status != 404 && status != 405,
+                "{method} {path} is documented but the router answered with {status}."
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn every_routed_operation_is_documented() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let document: serde_json::Value = reqwest::get(format!("{}/openapi.json", app.address))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let undocumented: Vec<_> = app
+        .routed_operations
+        .iter()
+        // The document, and the page rendering it, aren't part of the API.
+        .filter(|(_, path)| !["/openapi.json", "/docs"].contains(&path.as_str()))
+        .filter(|(method, path)| {
+            document["paths"][path.as_str()][method.as_str().to_lowercase()].is_null()
+        })
+        .map(|(method, path)| format!("{method} {path}"))
+        .collect();
+    assert!(
+        undocumented.is_empty(),
+        "{undocumented:?} are routed but missing from the OpenAPI document."
+    );
+}
+
+#[tokio::test]
+async fn the_interactive_docs_point_at_the_openapi_document() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/docs", app.address)).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("/openapi.json"));
+}
@@ -0,0 +1,265 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+async fn publish(app: &TestApp, list: Option<&str>) -> reqwest::Response {
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "list": list,
+    }))
+    .await
+}
+
+#[tokio::test]
+async fn the_preference_page_is_linked_from_the_confirmation_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.put_list("releases", "Release notes").await;
+    let preferences_link = app.create_confirmed_subscriber("ursula@example.com").await;
+
+    // Act
+    let response = reqwest::get(preferences_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"value="le guin""#));
+    assert!(page.contains(r#"name="lists" value="releases" checked"#));
+    assert!(page.contains(r#"value="every_issue" checked"#));
+}
+
+#[tokio::test]
+async fn unknown_preference_tokens_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/preferences?preferences_token=not-a-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn preferences_are_saved_and_audited() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.put_list("releases", "Release notes").await;
+    app.put_list("events", "Events").await;
+    let preferences_link = app.create_confirmed_subscriber("ursula@example.com").await;
+
+    // Act
+    let response = app
+        .post_preferences(
+            &preferences_link,
+            &[
+                ("name", "Ursula K. Le Guin"),
+                ("frequency", "weekly_digest"),
+                ("lists", "events"),
+                ("pause_days", "30"),
+            ],
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(
+        r#"
+        SELECT name, frequency, unsubscribed_lists,
+          paused_until > now() + interval '29 days' AS "paused!"
+        FROM subscriptions
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    assert_eq!(saved.frequency, "weekly_digest");
+    assert_eq!(saved.unsubscribed_lists, vec!["releases"]);
+    assert!(saved.paused);
+
+    let audited = sqlx::query!("SELECT action, details FROM audit_events ORDER BY id DESC LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audited.action, "update_preferences");
+    assert_eq!(audited.details["frequency"], "weekly_digest");
+    assert!(audited.details.get("name").is_none());
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let preferences_link = app.create_confirmed_subscriber("ursula@example.com").await;
+    let test_cases = [
+        (
+            vec![("name", ""), ("frequency", "every_issue")],
+            "empty name",
+        ),
+        (
+            vec![("name", "Ursula"), ("frequency", "daily")],
+            "unknown frequency",
+        ),
+        (
+            vec![
+                ("name", "Ursula"),
+                ("frequency", "every_issue"),
+                ("lists", "nope"),
+            ],
+            "unknown list",
+        ),
+        (
+            vec![
+                ("name", "Ursula"),
+                ("frequency", "every_issue"),
+                ("pause_days", "1000"),
+            ],
+            "pause too long",
+        ),
+    ];
+
+    for (fields, description) in test_cases {
+        // Act
+        let response = app.post_preferences(&preferences_link, &fields).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for an {description}."
+        );
+    }
+}
+
+#[tokio::test]
+async fn publishing_honours_lists_pauses_and_digests() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.put_list("releases", "Release notes").await;
+    let opted_out = app
+        .create_confirmed_subscriber("opted-out@example.com")
+        .await;
+    let paused = app.create_confirmed_subscriber("paused@example.com").await;
+    let digest = app.create_confirmed_subscriber("digest@example.com").await;
+    app.create_confirmed_subscriber("everything@example.com")
+        .await;
+    app.post_preferences(&opted_out, &[("name", "a"), ("frequency", "every_issue")])
+        .await;
+    app.post_preferences(
+        &paused,
+        &[
+            ("name", "b"),
+            ("frequency", "every_issue"),
+            ("lists", "releases"),
+            ("pause_days", "7"),
+        ],
+    )
+    .await;
+    app.post_preferences(
+        &digest,
+        &[
+            ("name", "c"),
+            ("frequency", "weekly_digest"),
+            ("lists", "releases"),
+        ],
+    )
+    .await;
+
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let to_list = publish(&app, Some("releases")).await;
+    let to_everyone = publish(&app, None).await;
+
+    // Assert
+    assert_eq!(to_list.status().as_u16(), 200);
+    assert_eq!(to_everyone.status().as_u16(), 200);
+    let mut recipients: Vec<String> = app
+        .sent_emails()
+        .await
+        .into_iter()
+        .filter(|body| body["subject"] == "Newsletter title")
+        .map(|body| body["to"].as_str().unwrap().to_owned())
+        .collect();
+    recipients.sort();
+    // Everyone active gets the issue for everyone, only those on the list get the other one.
+    assert_eq!(
+        recipients,
+        vec![
+            "everything@example.com",
+            "everything@example.com",
+            "opted-out@example.com",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn publishing_to_an_unknown_list_is_rejected() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = publish(&app, Some("releases")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn lists_are_managed_by_admins() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let client = reqwest::Client::new();
+
+    // Act
+    let created = app.put_list("releases", "Release notes").await;
+    let renamed = app.put_list("releases", "What's new").await;
+    let invalid = app.put_list("Not A Slug", "Nope").await;
+    let listed: serde_json::Value = client
+        .get(format!("{}/admin/lists", app.address))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let deleted = client
+        .delete(format!("{}/admin/lists/releases", app.address))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap();
+    let deleted_again = client
+        .delete(format!("{}/admin/lists/releases", app.address))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(created.status().as_u16(), 204);
+    assert_eq!(renamed.status().as_u16(), 204);
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+    assert_eq!(listed[0]["name"], "What's new");
+    assert_eq!(deleted.status().as_u16(), 204);
+    assert_eq!(deleted_again.status().as_u16(), 404);
+}
@@ -0,0 +1,234 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+async fn spawn_app() -> TestApp {
+    let app = TestApp::spawn_app().await;
+    let response = app
+        .put_list_with_attributes(
+            "customers",
+            "Customers",
+            serde_json::json!({ "country": "string", "seats": "number" }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+    app
+}
+
+async fn subscriber_id(app: &TestApp, email: &str) -> Uuid {
+    sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+#[tokio::test]
+async fn attributes_collected_by_a_list_are_accepted_when_subscribing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions_json(serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "attributes": { "country": "US", "seats": 3 },
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 201);
+    let saved = sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.attributes,
+        serde_json::json!({ "country": "US", "seats": 3 })
+    );
+}
+
+#[tokio::test]
+async fn subscribing_with_unknown_or_mistyped_attributes_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = [
+        (serde_json::json!({ "city": "Portland" }), "city"),
+        (serde_json::json!({ "seats": "three" }), "seats"),
+    ];
+
+    for (attributes, attribute) in test_cases {
+        // Act
+        let response = app
+            .post_subscriptions_json(serde_json::json!({
+                "name": "le guin",
+                "email": "ursula_le_guin@gmail.com",
+                "attributes": attributes,
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 400);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            body["error"].as_str().unwrap().contains(attribute),
+            "{body}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn admins_set_attributes() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    let id = subscriber_id(&app, "ursula@example.com").await;
+
+    // Act
+    let saved = app
+        .put_subscriber_attributes(id, serde_json::json!({ "country": "FR" }))
+        .await;
+    let invalid = app
+        .put_subscriber_attributes(id, serde_json::json!({ "country": 33 }))
+        .await;
+    let unknown = app
+        .put_subscriber_attributes(Uuid::new_v4(), serde_json::json!({}))
+        .await;
+
+    // Assert
+    assert_eq!(saved.status().as_u16(), 204);
+    assert_eq!(invalid.status().as_u16(), 400);
+    assert_eq!(unknown.status().as_u16(), 404);
+    let detail: serde_json::Value = app
+        .get_admin(&format!("/subscribers/{id}"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(
+        detail["subscriber"]["attributes"],
+        serde_json::json!({ "country": "FR" })
+    );
+    let audited = sqlx::query!("SELECT details FROM audit_events ORDER BY id DESC LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        audited.details,
+        serde_json::json!({ "attributes": ["country"] })
+    );
+}
+
+#[tokio::test]
+async fn lists_cannot_disagree_on_the_type_of_an_attribute() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .put_list_with_attributes(
+            "partners",
+            "Partners",
+            serde_json::json!({ "country": "number" }),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_published_to_a_segment_only_go_to_matching_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    for (email, attributes) in [
+        (
+            "big-us@example.com",
+            serde_json::json!({ "country": "US", "seats": 10 }),
+        ),
+        (
+            "small-us@example.com",
+            serde_json::json!({ "country": "US", "seats": 1 }),
+        ),
+        (
+            "big-fr@example.com",
+            serde_json::json!({ "country": "FR", "seats": 10 }),
+        ),
+        ("unknown@example.com", serde_json::json!({})),
+    ] {
+        app.create_confirmed_subscriber(email).await;
+        let id = subscriber_id(&app, email).await;
+        app.put_subscriber_attributes(id, attributes)
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "segment": r#"country IN ("US", "CA") AND NOT seats < 5 OR country = "FR" AND seats = 1"#,
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let recipients: Vec<String> = app
+        .sent_emails()
+        .await
+        .into_iter()
+        .filter(|body| body["subject"] == "Newsletter title")
+        .map(|body| body["to"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(recipients, vec!["big-us@example.com"]);
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = [
+        (r#"country = "US" AND"#, "a syntax error"),
+        (r#"city = "Portland""#, "an attribute no list collects"),
+        (r#"seats = "ten""#, "a value of the wrong type"),
+        ("country = 1; DROP TABLE subscriptions", "SQL"),
+    ];
+
+    for (segment, description) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": { "text": "text", "html": "<p>html</p>" },
+                "segment": segment,
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for {description}."
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+async fn spawn_app() -> TestApp {
+    TestApp::spawn_app_with(|configuration| {
+        configuration
+            .application
+            .subject_test_check_interval_seconds = 1;
+    })
+    .await
+}
+
+async fn publish(app: &TestApp, subject_test: serde_json::Value) -> reqwest::Response {
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Read the post at https://example.com/post",
+            "html": r#"<p>Read <a href="https://example.com/post">the post</a></p>"#,
+        },
+        "subject_test": subject_test,
+    }))
+    .await
+}
+
+/// The emails sent so far under `subject`, as the body of their request to the email API.
+async fn emails_with_subject(app: &TestApp, subject: &str) -> Vec<serde_json::Value> {
+    app.sent_emails()
+        .await
+        .into_iter()
+        .filter(|body| body["subject"] == subject)
+        .collect()
+}
+
+/// The link to `/newsletters/{action}` in an email, pointed at the test server.
+fn tracking_link(app: &TestApp, email: &serde_json::Value, action: &str) -> Option<reqwest::Url> {
+    ["html", "text"]
+        .iter()
+        .filter_map(|field| email[field].as_str())
+        .flat_map(|content| linkify::LinkFinder::new().links(content))
+        .map(|link| reqwest::Url::parse(&link.as_str().replace("&amp;", "&")).unwrap())
+        .find(|link| link.path().ends_with(&format!("/newsletters/{action}")))
+        .map(|mut link| {
+            assert_eq!(link.host_str().unwrap(), "127.0.0.1");
+            link.set_port(Some(app.port)).unwrap();
+            link
+        })
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_subject_with_the_best_click_rate_goes_to_everyone_else() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 0..4 {
+        app.create_confirmed_subscriber(&format!("reader{i}@example.com"))
+            .await;
+    }
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Test both subjects on half of the subscribers
+    let response = publish(
+        &app,
+        serde_json::json!({
+            "subjects": ["Option A", "Option B"],
+            "cohort_percentage": 50,
+            "winner_by": "click_rate",
+        }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let a = emails_with_subject(&app, "Option A").await;
+    let b = emails_with_subject(&app, "Option B").await;
+    assert_eq!((a.len(), b.len()), (1, 1));
+    assert!(emails_with_subject(&app, "Newsletter title")
+        .await
+        .is_empty());
+
+    // Act - Part 2 - Both are opened, only B is clicked
+    let opened = client()
+        .get(tracking_link(&app, &a[0], "open").unwrap())
+        .send()
+        .await
+        .unwrap();
+    let clicked = client()
+        .get(tracking_link(&app, &b[0], "click").unwrap())
+        .send()
+        .await
+        .unwrap();
+
+    // Act - Part 3 - Close the test window
+    sqlx::query!("UPDATE newsletter_issues SET winner_due_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let mut b = vec![];
+    for _ in 0..50 {
+        b = emails_with_subject(&app, "Option B").await;
+        if b.len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    // Assert
+    assert_eq!(opened.status().as_u16(), 200);
+    assert_eq!(opened.headers()["content-type"], "image/gif");
+    assert_eq!(clicked.status().as_u16(), 303);
+    assert_eq!(clicked.headers()["location"], "https://example.com/post");
+    assert_eq!(b.len(), 3, "The winner wasn't sent to everyone else");
+    assert!(
+        b[1..]
+            .iter()
+            .all(|email| tracking_link(&app, email, "open").is_none()),
+        "Only the cohort is tracked"
+    );
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(emails_with_subject(&app, "Option A").await.len(), 1);
+    assert_eq!(emails_with_subject(&app, "Option B").await.len(), 3);
+
+    let audited = sqlx::query!("SELECT details FROM audit_events WHERE action = 'pick_subject'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audited.details["winning_subject"], "Option B");
+}
+
+#[tokio::test]
+async fn a_winner_that_fails_to_send_is_retried_later_without_holding_up_the_others() {
+    // Arrange
+    let app = spawn_app().await;
+    for i in 0..4 {
+        app.create_confirmed_subscriber(&format!("reader{i}@example.com"))
+            .await;
+    }
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    publish(
+        &app,
+        serde_json::json!({ "subjects": ["Option A", "Option B"], "cohort_percentage": 50 }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let outside_cohort: Vec<String> = sqlx::query_scalar(
+        r#"
+    SELECT s.email FROM newsletter_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+    WHERE d.variant IS NULL ORDER BY s.email
+        "#,
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    let failing = outside_cohort[0].replace('@', "%40");
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .and(body_string_contains(format!("to={failing}")))
+        .respond_with(ResponseTemplate::new(500))
+        .with_priority(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    sqlx::query!("UPDATE newsletter_issues SET winner_due_at = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Assert
+    let mut a = vec![];
+    for _ in 0..50 {
+        a = emails_with_subject(&app, "Option A").await;
+        if a.len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(a.len(), 3, "The winner wasn't attempted for everyone else");
+    let deliveries: Vec<(String, bool, i32, bool, Option<String>)> = sqlx::query_as(
+        r#"
+    SELECT s.email, d.sent_at IS NOT NULL, d.attempts,
+      COALESCE(d.next_attempt_at > now(), false), d.error
+    FROM newsletter_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+    WHERE d.variant IS NULL ORDER BY s.email
+        "#,
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(
+        deliveries,
+        vec![
+            (outside_cohort[0].clone(), false, 1, true, None),
+            (outside_cohort[1].clone(), true, 0, false, None),
+        ],
+        "The failed delivery is retried later, and didn't hold up the other one"
+    );
+}
+
+#[tokio::test]
+async fn invalid_subject_tests_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({ "subjects": ["Only one"], "cohort_percentage": 20 }),
+            "a single subject",
+        ),
+        (
+            serde_json::json!({ "subjects": ["A", "B"], "cohort_percentage": 100 }),
+            "no one left to send the winner to",
+        ),
+        (
+            serde_json::json!({
+                "subjects": ["A", "B"],
+                "cohort_percentage": 20,
+                "winner_by": "revenue",
+            }),
+            "an unknown metric",
+        ),
+    ];
+
+    for (subject_test, description) in test_cases {
+        // Act
+        let response = publish(&app, subject_test).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for {description}."
+        );
+    }
+}
+
+#[tokio::test]
+async fn only_links_from_the_issue_are_followed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    publish(
+        &app,
+        serde_json::json!({ "subjects": ["A", "B"], "cohort_percentage": 50 }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let email = &emails_with_subject(&app, "A").await[0];
+    let mut elsewhere = tracking_link(&app, email, "click").unwrap();
+    let tracking_token = elsewhere
+        .query_pairs()
+        .find(|(key, _)| key == "tracking_token")
+        .unwrap()
+        .1
+        .into_owned();
+    elsewhere
+        .query_pairs_mut()
+        .clear()
+        .append_pair("tracking_token", &tracking_token)
+        .append_pair("url", "https://evil.example.com");
+    let mut unknown_token = tracking_link(&app, email, "open").unwrap();
+    unknown_token.set_query(Some("tracking_token=nope"));
+
+    // Act
+    let elsewhere = client().get(elsewhere).send().await.unwrap();
+    let unknown_token = client().get(unknown_token).send().await.unwrap();
+
+    // Assert
+    assert_eq!(elsewhere.status().as_u16(), 404);
+    assert_eq!(unknown_token.status().as_u16(), 404);
+}
@@ -0,0 +1,292 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+/// Subscribe, then ask for a data link and return it.
+async fn request_data_link(app: &TestApp) -> reqwest::Url {
+    let _mock_guard = Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.post_data_requests("email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+fn data_request_token(link: &reqwest::Url) -> String {
+    link.query_pairs()
+        .find(|(key, _)| key == "data_request_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn data_requests_for_unknown_emails_succeed_without_sending_an_email() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_data_requests("email=nobody%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_data_link_leads_to_a_page_to_manage_the_data() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let link = request_data_link(&app).await;
+
+    // Act
+    let response = reqwest::get(link.clone()).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(&data_request_token(&link)));
+}
+
+#[tokio::test]
+async fn the_export_contains_everything_we_hold_about_the_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let link = request_data_link(&app).await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/data/export?data_request_token={}",
+        app.address,
+        data_request_token(&link)
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(export["subscriber"]["email"], "ursula_le_guin@gmail.com");
+    assert_eq!(export["subscriber"]["name"], "le guin");
+    assert_eq!(export["subscriber"]["status"], "pending_confirmation");
+    assert_eq!(export["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(export["data_requests"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn the_export_contains_the_issues_sent_to_the_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.create_confirmed_subscriber("ursula_le_guin@gmail.com")
+        .await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.post_data_requests("email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let link = app.get_confirmation_links(&email_request).html;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/data/export?data_request_token={}",
+        app.address,
+        data_request_token(&link)
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let export: serde_json::Value = response.json().await.unwrap();
+    assert!(export["subscriber"]["last_digest_at"].is_null());
+    let deliveries = export["deliveries"].as_array().unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["title"], "Newsletter title");
+    assert!(deliveries[0]["sent_at"].is_string());
+}
+
+#[tokio::test]
+async fn erasing_deletes_the_subscriber_and_their_tokens() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let link = request_data_link(&app).await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/data/erase", app.address))
+        .form(&[("data_request_token", data_request_token(&link))])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers = sqlx::query!("SELECT COUNT(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let tokens = sqlx::query!("SELECT COUNT(*) AS count FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers.count, Some(0));
+    assert_eq!(tokens.count, Some(0));
+
+    // The link can't be used anymore.
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn erasing_leaves_the_email_in_neither_webhook_payloads_nor_the_outbox() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/webhooks", app.address))
+        .bearer_auth(&app.admin_token)
+        .json(&serde_json::json!({
+            "url": "https://crm.example.com/hooks",
+            "events": ["subscriber.created", "subscriber.unsubscribed"],
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    let link = request_data_link(&app).await;
+    sqlx::query(
+        r#"
+    INSERT INTO email_outbox (id, tenant, recipient, subject, html_content, text_content)
+    VALUES (gen_random_uuid(), 'default', 'ursula_le_guin@gmail.com', 'Hi', 'Hi', 'Hi')
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/data/erase", app.address))
+        .form(&[("data_request_token", data_request_token(&link))])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let payloads: Vec<(String, serde_json::Value)> =
+        sqlx::query_as("SELECT event_type, payload FROM webhook_deliveries ORDER BY created_at")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(payloads.len(), 2);
+    for (event_type, payload) in &payloads {
+        assert!(
+            !payload.to_string().contains("ursula_le_guin"),
+            "The {event_type} payload still has the email: {payload}"
+        );
+        assert_eq!(
+            payload["data"], payloads[0].1["data"],
+            "Only the id is left"
+        );
+    }
+    let deferred: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM email_outbox")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(deferred, 0);
+}
+
+#[tokio::test]
+async fn erasing_is_recorded_in_the_audit_log() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let link = request_data_link(&app).await;
+
+    // Act
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/data/erase", app.address))
+        .form(&[("data_request_token", data_request_token(&link))])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let page: serde_json::Value = app
+        .get_admin("/audit_events?action=erase")
+        .await
+        .json()
+        .await
+        .unwrap();
+    let event = &page["events"][0];
+    assert_eq!(event["actor"], "subscriber");
+    assert_eq!(event["status_before"], "pending_confirmation");
+    assert_eq!(event["status_after"], serde_json::Value::Null);
+}
+
+#[tokio::test]
+async fn unknown_data_request_tokens_are_rejected_with_a_401() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!(
+        "{}/subscriptions/data/export?data_request_token=not-a-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
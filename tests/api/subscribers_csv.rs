@@ -0,0 +1,156 @@
+use crate::helpers::TestApp;
+
+#[tokio::test]
+async fn admin_endpoints_reject_requests_without_the_admin_token() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let client = reqwest::Client::new();
+    let test_cases = vec![
+        (None, "missing token"),
+        (Some("not-the-admin-token"), "wrong token"),
+    ];
+
+    for (token, description) in test_cases {
+        let mut request = client.get(format!("{}/admin/subscribers/export", app.address));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+
+        // Act
+        let response = request.send().await.unwrap();
+
+        // Assert
+        assert_eq!(
+            401,
+            response.status().as_u16(),
+            "The API did not return a 401 Unauthorized with a {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn importing_persists_valid_rows_and_reports_invalid_ones() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let csv = "email,name,status,subscribed_at\n\
+        ursula@domain.com,Ursula Le Guin,confirmed,2023-07-08T20:17:05Z\n\
+        not-an-email,Octavia Butler,confirmed,\n\
+        octavia@domain.com,Octavia Butler,pending_confirmation,\n\
+        ted@domain.com,Ted Chiang,subscribed,\n";
+
+    // Act
+    let response = app.post_subscribers_import(csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    let failed_lines: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["line"].as_u64().unwrap())
+        .collect();
+    assert_eq!(failed_lines, vec![3, 5]);
+
+    let saved = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].email, "octavia@domain.com");
+    assert_eq!(saved[0].status, "pending_confirmation");
+    assert_eq!(saved[1].email, "ursula@domain.com");
+    assert_eq!(saved[1].status, "confirmed");
+}
+
+#[tokio::test]
+async fn importing_an_existing_subscriber_reports_it_as_a_row_error() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let csv = "email,name,status,subscribed_at\n\
+        ursula@domain.com,Ursula Le Guin,confirmed,\n";
+    app.post_subscribers_import(csv).await;
+
+    // Act
+    let response = app.post_subscribers_import(csv).await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["errors"][0]["line"], 2);
+}
+
+#[tokio::test]
+async fn importing_the_same_email_in_another_case_reports_it_as_a_row_error() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let csv = "email,name,status,subscribed_at\n\
+        ursula@domain.com,Ursula Le Guin,confirmed,\n\
+        Ursula@DOMAIN.com,Ursula Le Guin,confirmed,\n";
+
+    // Act
+    let response = app.post_subscribers_import(csv).await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["errors"][0]["line"], 3);
+}
+
+#[tokio::test]
+async fn importing_reports_addresses_the_email_policy_rejects_as_row_errors() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.put_email_domain("blocked.com", "block").await;
+    let csv = "email,name,status,subscribed_at\n\
+        ursula@domain.com,Ursula Le Guin,confirmed,\n\
+        ursula@mailinator.com,Ursula Le Guin,confirmed,\n\
+        octavia@blocked.com,Octavia Butler,confirmed,\n\
+        noreply@domain.com,No Reply,confirmed,\n";
+
+    // Act
+    let response = app.post_subscribers_import(csv).await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    let errors: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["line"].as_u64().unwrap(), e["error"].as_str().unwrap()))
+        .collect();
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0].0, 3);
+    assert!(errors[0].1.contains("disposable"), "{}", errors[0].1);
+    assert_eq!(errors[1].0, 4);
+    assert!(errors[1].1.contains("blocked.com"), "{}", errors[1].1);
+    assert_eq!(errors[2].0, 5);
+    assert!(errors[2].1.contains("role account"), "{}", errors[2].1);
+
+    let saved: Vec<String> = sqlx::query_scalar("SELECT email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved, vec!["ursula@domain.com"]);
+}
+
+#[tokio::test]
+async fn the_export_round_trips_through_the_import() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    let csv = "email,name,status,subscribed_at\n\
+        ursula@domain.com,Ursula Le Guin,confirmed,2023-07-08T20:17:05+00:00\n\
+        octavia@domain.com,Octavia Butler,pending_confirmation,2023-07-09T10:00:00+00:00\n";
+    app.post_subscribers_import(csv).await;
+
+    // Act
+    let response = app.get_subscribers_export().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["content-type"], "text/csv");
+    assert_eq!(response.text().await.unwrap(), csv);
+}
@@ -4,6 +4,8 @@ use wiremock::{
     Mock, ResponseTemplate,
 };
 
+use uuid::Uuid;
+
 use crate::helpers::{TestApp, EMAIL_URL};
 
 #[tokio::test]
@@ -46,6 +48,125 @@ async fn subscribe_persists_the_new_subscriber() {
     assert_eq!(saved.status, "pending_confirmation");
 }
 
+#[tokio::test]
+async fn subscribe_treats_emails_differing_only_by_case_as_the_same_subscriber() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=Ursula%40Example.COM".into())
+        .await;
+    app.post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT email, canonical_email FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "Ursula@Example.COM");
+    assert_eq!(saved[0].canonical_email, "ursula@example.com");
+}
+
+/// A subscriber stored before canonical emails had punycode domains, as the migration adding
+/// them left them.
+async fn insert_legacy_subscriber(app: &TestApp, email: &str, status: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query(
+        "INSERT INTO subscriptions \
+          (id, tenant, email, canonical_email, name, subscribed_at, status, preferences_token) \
+        VALUES ($1, 'default', $2, lower($2), 'le guin', now() - interval '1 day', $3, $4)",
+    )
+    .bind(id)
+    .bind(email)
+    .bind(status)
+    .bind(Uuid::new_v4().simple().to_string())
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+#[tokio::test]
+async fn subscribers_with_a_unicode_domain_from_before_punycode_are_not_added_twice() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let legacy_id = insert_legacy_subscriber(&app, "ursula@Bücher.example", "confirmed").await;
+
+    // Act
+    let fixed = zero2prod::canonical_email::backfill(&app.db_pool)
+        .await
+        .unwrap();
+    let body =
+        serde_urlencoded::to_string([("name", "le guin"), ("email", "ursula@bücher.example")])
+            .unwrap();
+    app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(fixed, 1);
+    let saved: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT id, canonical_email FROM subscriptions")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        saved,
+        vec![(legacy_id, "ursula@xn--bcher-kva.example".to_owned())]
+    );
+}
+
+#[tokio::test]
+async fn the_canonical_email_backfill_erases_duplicates_added_in_the_meantime() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let legacy_id = insert_legacy_subscriber(&app, "ursula@bücher.example", "confirmed").await;
+    let body =
+        serde_urlencoded::to_string([("name", "le guin"), ("email", "ursula@bücher.example")])
+            .unwrap();
+    app.post_subscriptions(body).await;
+
+    // Act
+    zero2prod::canonical_email::backfill(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Assert - The confirmed subscriber is kept over the pending duplicate.
+    let saved: Vec<(Uuid, String)> =
+        sqlx::query_as("SELECT id, canonical_email FROM subscriptions")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        saved,
+        vec![(legacy_id, "ursula@xn--bcher-kva.example".to_owned())]
+    );
+    let erased: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_events \
+        WHERE action = 'erase' AND details->>'duplicate_of' = $1",
+    )
+    .bind(legacy_id.to_string())
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(erased, 1);
+}
+
 #[tokio::test]
 async fn subscribe_returns_a_400_when_data_is_missing() {
     // Arrange
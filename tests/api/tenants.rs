@@ -0,0 +1,199 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL, OTHER_TENANT, OTHER_TENANT_HOST, OTHER_TENANT_SENDER};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+async fn post_subscriptions_with_host(app: &TestApp, host: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Host", host)
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(BODY)
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn tenants_of_subscribers(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT tenant FROM subscriptions ORDER BY tenant")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.tenant)
+        .collect()
+}
+
+#[tokio::test]
+async fn the_same_email_can_subscribe_to_every_tenant() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    mock_email_server(&app).await;
+
+    // Act
+    let default = app.post_subscriptions(BODY.into()).await;
+    let by_prefix = reqwest::Client::new()
+        .post(format!("{}/t/{OTHER_TENANT}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(BODY)
+        .send()
+        .await
+        .unwrap();
+    let again_by_host = post_subscriptions_with_host(&app, OTHER_TENANT_HOST).await;
+
+    // Assert
+    assert_eq!(default.status().as_u16(), 200);
+    assert_eq!(by_prefix.status().as_u16(), 200);
+    // Same tenant, same email: still a duplicate.
+    assert_eq!(again_by_host.status().as_u16(), 500);
+    assert_eq!(
+        tenants_of_subscribers(&app).await,
+        vec!["default", OTHER_TENANT]
+    );
+}
+
+#[tokio::test]
+async fn unknown_hosts_are_served_by_the_default_tenant() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    mock_email_server(&app).await;
+
+    // Act
+    let response = post_subscriptions_with_host(&app, "unknown.localhost").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(tenants_of_subscribers(&app).await, vec!["default"]);
+}
+
+#[tokio::test]
+async fn unknown_tenant_prefixes_are_rejected_with_a_404() {
+    let app = TestApp::spawn_app().await;
+
+    let response = reqwest::get(format!("{}/t/unknown/health_check", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn confirmation_emails_come_from_the_tenant_and_link_back_to_it() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    mock_email_server(&app).await;
+    post_subscriptions_with_host(&app, OTHER_TENANT_HOST).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_urlencoded::from_bytes(&email_request.body).unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(body["from"], OTHER_TENANT_SENDER);
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn tokens_cannot_be_used_on_another_tenant() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    mock_email_server(&app).await;
+    post_subscriptions_with_host(&app, OTHER_TENANT_HOST).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let mut confirmation_link = app.get_confirmation_links(email_request).html;
+    let other_path = confirmation_link.path().to_owned();
+    confirmation_link.set_path(other_path.trim_start_matches(&format!("/t/{OTHER_TENANT}")));
+
+    // Act
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_the_tenant_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.post_subscribers_import(
+        "email,name,status,subscribed_at\nursula@domain.com,Ursula,confirmed,\n",
+    )
+    .await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", app.address))
+        .bearer_auth(&app.admin_token)
+        .header("Host", OTHER_TENANT_HOST)
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+    app.wait_for_deliveries().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let issue = sqlx::query!("SELECT tenant FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.tenant, OTHER_TENANT);
+}
+
+#[tokio::test]
+async fn admin_searches_only_see_the_tenant_subscribers() {
+    // Arrange
+    let app = TestApp::spawn_app().await;
+    app.post_subscribers_import(
+        "email,name,status,subscribed_at\nursula@domain.com,Ursula,confirmed,\n",
+    )
+    .await;
+
+    // Act
+    let page: serde_json::Value = reqwest::Client::new()
+        .get(format!(
+            "{}/t/{OTHER_TENANT}/admin/subscribers",
+            app.address
+        ))
+        .bearer_auth(&app.admin_token)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(page["subscribers"], serde_json::json!([]));
+}
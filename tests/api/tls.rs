@@ -0,0 +1,120 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::tls::TlsInfo;
+use uuid::Uuid;
+use zero2prod::configuration::TlsSettings;
+
+use crate::helpers::TestApp;
+
+// Self-signed certificates for `localhost` and `127.0.0.1`, generated with
+// `openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days 36500`.
+const FIXTURES: &str = "tests/fixtures/tls";
+
+/// A copy of the `first` certificate that tests can replace.
+fn certificate_directory() -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("tls-{}", Uuid::new_v4()));
+    std::fs::create_dir(&directory).unwrap();
+    install_certificate(&directory, "first");
+    directory
+}
+
+fn install_certificate(directory: &Path, name: &str) {
+    for extension in ["crt", "key"] {
+        std::fs::copy(
+            Path::new(FIXTURES).join(format!("{name}.{extension}")),
+            directory.join(format!("server.{extension}")),
+        )
+        .unwrap();
+    }
+}
+
+async fn spawn_https_app(directory: &Path) -> TestApp {
+    TestApp::spawn_app_with(|configuration| {
+        configuration.application.tls = Some(TlsSettings {
+            certificate_file: directory.join("server.crt"),
+            key_file: directory.join("server.key"),
+            redirect_http_port: Some(0),
+        });
+    })
+    .await
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .use_rustls_tls()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+/// The certificate the server presented, in DER.
+async fn peer_certificate(client: &reqwest::Client, app: &TestApp) -> Vec<u8> {
+    let response = client
+        .get(format!("{}/health_check", app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert!(response.status().is_success());
+    response
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(TlsInfo::peer_certificate)
+        .expect("No peer certificate")
+        .to_vec()
+}
+
+#[tokio::test]
+async fn https_is_served_and_plain_http_is_redirected() {
+    // Arrange
+    let directory = certificate_directory();
+    let app = spawn_https_app(&directory).await;
+    let client = client();
+
+    // Act
+    peer_certificate(&client, &app).await;
+    let response = client
+        .post(format!(
+            "http://127.0.0.1:{}/subscriptions?source=test",
+            app.redirect_port.unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 308);
+    assert_eq!(
+        response.headers()["Location"],
+        format!("https://127.0.0.1:{}/subscriptions?source=test", app.port).as_str()
+    );
+}
+
+#[tokio::test]
+async fn the_certificate_is_reloaded_when_its_files_change() {
+    // Arrange
+    let directory = certificate_directory();
+    let app = spawn_https_app(&directory).await;
+    let established = client();
+    let first = peer_certificate(&established, &app).await;
+
+    // Act
+    install_certificate(&directory, "second");
+
+    // Assert
+    let mut reloaded = false;
+    for _ in 0..50 {
+        if peer_certificate(&client(), &app).await != first {
+            reloaded = true;
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert!(reloaded, "The new certificate was not picked up");
+    // Connections made before the reload keep working.
+    assert_eq!(peer_certificate(&established, &app).await, first);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
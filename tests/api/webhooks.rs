@@ -0,0 +1,263 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+
+use crate::helpers::{TestApp, EMAIL_URL};
+
+async fn spawn_app() -> TestApp {
+    TestApp::spawn_app_with(|configuration| {
+        configuration.application.webhook_check_interval_seconds = 1;
+        configuration.application.webhook_retry_delay_seconds = 1;
+    })
+    .await
+}
+
+async fn post_webhook(app: &TestApp, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/webhooks", app.address))
+        .bearer_auth(&app.admin_token)
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+/// Add an endpoint at `{server}/hooks`, returning its id and secret.
+async fn create_webhook(app: &TestApp, server: &MockServer, events: &[&str]) -> (String, String) {
+    let response = post_webhook(
+        app,
+        serde_json::json!({ "url": format!("{}/hooks", server.uri()), "events": events }),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    (
+        created["id"].as_str().unwrap().to_owned(),
+        created["secret"].as_str().unwrap().to_owned(),
+    )
+}
+
+/// The requests received by `server`, waiting up to 5 seconds for there to be `count`.
+async fn received(server: &MockServer, count: usize) -> Vec<wiremock::Request> {
+    let mut requests = vec![];
+    for _ in 0..50 {
+        requests = server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    requests
+}
+
+fn header<'a>(request: &'a wiremock::Request, name: &str) -> &'a str {
+    request
+        .headers
+        .get(&wiremock::http::HeaderName::from(name))
+        .unwrap()
+        .last()
+        .as_str()
+}
+
+#[tokio::test]
+async fn subscriber_events_are_signed_and_delivered_in_order() {
+    // Arrange
+    let app = spawn_app().await;
+    let webhook_server = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&webhook_server)
+        .await;
+    let (_, secret) = create_webhook(
+        &app,
+        &webhook_server,
+        &["subscriber.created", "subscriber.confirmed"],
+    )
+    .await;
+
+    // Act
+    app.create_confirmed_subscriber("ursula@example.com").await;
+
+    // Assert
+    let requests = received(&webhook_server, 2).await;
+    assert_eq!(requests.len(), 2);
+    let mut event_types = vec![];
+    for request in &requests {
+        let body = std::str::from_utf8(&request.body).unwrap();
+        let timestamp: i64 = header(request, "Webhook-Timestamp").parse().unwrap();
+        assert_eq!(
+            header(request, "Webhook-Signature"),
+            format!("v1={}", zero2prod::webhooks::sign(&secret, timestamp, body))
+        );
+        let event: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(header(request, "Webhook-Id"), event["id"]);
+        assert_eq!(event["data"]["email"], "ursula@example.com");
+        event_types.push(event["type"].as_str().unwrap().to_owned());
+    }
+    assert_eq!(event_types, ["subscriber.created", "subscriber.confirmed"]);
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_and_logged() {
+    // Arrange
+    let app = spawn_app().await;
+    let webhook_server = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&webhook_server)
+        .await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&webhook_server)
+        .await;
+    let (webhook_id, _) = create_webhook(&app, &webhook_server, &["subscriber.created"]).await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let requests = received(&webhook_server, 2).await;
+    assert_eq!(requests.len(), 2, "The failed delivery wasn't retried");
+    assert_eq!(
+        header(&requests[0], "Webhook-Id"),
+        header(&requests[1], "Webhook-Id")
+    );
+    assert_eq!(requests[0].body, requests[1].body);
+
+    let mut deliveries = serde_json::Value::Null;
+    for _ in 0..50 {
+        deliveries = app
+            .get_admin(&format!("/webhooks/{webhook_id}/deliveries"))
+            .await
+            .json()
+            .await
+            .unwrap();
+        if deliveries[0]["status"] == "delivered" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(deliveries.as_array().unwrap().len(), 1);
+    assert_eq!(deliveries[0]["status"], "delivered");
+    assert_eq!(deliveries[0]["attempts"], 2);
+    assert_eq!(deliveries[0]["last_response_status"], 200);
+    assert_eq!(deliveries[0]["event_type"], "subscriber.created");
+}
+
+#[tokio::test]
+async fn endpoints_only_get_the_events_they_subscribed_to() {
+    // Arrange
+    let app = spawn_app().await;
+    let webhook_server = MockServer::start().await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&webhook_server)
+        .await;
+    create_webhook(&app, &webhook_server, &["issue.published"]).await;
+    app.create_confirmed_subscriber("ursula@example.com").await;
+    Mock::given(path(EMAIL_URL))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "Newsletter body as plain text", "html": "<p>Newsletter body</p>" },
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    // Assert
+    let requests = received(&webhook_server, 1).await;
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(webhook_server.received_requests().await.unwrap().len(), 1);
+    let event: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(event["type"], "issue.published");
+    assert_eq!(event["data"]["title"], "Newsletter title");
+    assert_eq!(event["data"]["recipients"], 1);
+}
+
+#[tokio::test]
+async fn invalid_endpoints_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = [
+        (
+            serde_json::json!({ "url": "ftp://example.com/hooks", "events": ["issue.published"] }),
+            "not an http URL",
+        ),
+        (
+            serde_json::json!({ "url": "https://example.com/hooks", "events": [] }),
+            "no events",
+        ),
+        (
+            serde_json::json!({ "url": "https://example.com/hooks", "events": ["issue.deleted"] }),
+            "an unknown event",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = post_webhook(&app, body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had {description}."
+        );
+    }
+    let endpoints: serde_json::Value = app.get_admin("/webhooks").await.json().await.unwrap();
+    assert_eq!(endpoints, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn deleted_endpoints_are_gone_along_with_their_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    let webhook_server = MockServer::start().await;
+    let (webhook_id, _) = create_webhook(&app, &webhook_server, &["issue.published"]).await;
+    let endpoints: serde_json::Value = app.get_admin("/webhooks").await.json().await.unwrap();
+    assert_eq!(endpoints[0]["id"], webhook_id.as_str());
+    assert!(
+        endpoints[0].get("secret").is_none(),
+        "Secrets are only shown once"
+    );
+    let delete = || {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/webhooks/{webhook_id}", app.address))
+            .bearer_auth(&app.admin_token)
+            .send()
+    };
+
+    // Act
+    let first = delete().await.unwrap();
+    let second = delete().await.unwrap();
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 204);
+    assert_eq!(second.status().as_u16(), 404);
+    let deliveries = app
+        .get_admin(&format!("/webhooks/{webhook_id}/deliveries"))
+        .await;
+    assert_eq!(deliveries.status().as_u16(), 404);
+}